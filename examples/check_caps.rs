@@ -7,12 +7,15 @@ use kvm_rs::cap::CapInt::*;
 use kvm_rs::kvm::Kvm;
 
 #[rustfmt::skip]
-fn main() -> std::io::Result<()> {
+fn main() -> kvm_rs::Result<()> {
     let kvm = Kvm::new()?;
 
-    println!("KVM_CAP_CHECK_EXTENSION_VM    = {}", kvm.check_extenstion(CheckExtensionVm));
-    println!("KVM_CAP_NR_VCPUS              = {}", kvm.check_extenstion_int(NrVcpus));
-    println!("KVM_CAP_MAX_VCPUS             = {}", kvm.check_extenstion_int(MaxVcpus));
+    println!("KVM_CAP_CHECK_EXTENSION_VM        = {}", kvm.check_extenstion(CheckExtensionVm)?);
+    println!("KVM_CAP_READONLY_MEM              = {}", kvm.check_extenstion(ReadonlyMem)?);
+    println!("KVM_CAP_NR_VCPUS                  = {}", kvm.check_extenstion_int(NrVcpus)?);
+    println!("KVM_CAP_MAX_VCPUS                 = {}", kvm.check_extenstion_int(MaxVcpus)?);
+    println!("KVM_CAP_NR_MEMSLOTS               = {}", kvm.check_extenstion_int(NrMemslots)?);
+    println!("KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2 = {}", kvm.check_extenstion_int(ManualDirtyLogProtect2)?);
 
     Ok(())
 }
@@ -2,71 +2,40 @@
 //
 // Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
 
+use kvm_rs::bus::{Device, Dispatch, Dispatcher};
+use kvm_rs::gdbstub::GdbStub;
 use kvm_rs::kvm::Kvm;
-use kvm_rs::kvm_sys;
+use kvm_rs::runner::{RunOutcome, Runner};
+use kvm_rs::serial::{Serial, COM1_PORT, NR_PORTS};
 use kvm_rs::vcpu::KvmExit;
-use kvm_rs::x86_64::*;
-use kvm_rs::{PhysAddr, UserMem};
+use kvm_rs::vm::MemFlags;
+use kvm_rs::x86_64::mode;
+use kvm_rs::x86_64::paging::{PageFlags, PageSize, PageTables};
+use kvm_rs::{PhysAddr, Result, UserMem};
 
 use std::convert::TryInto;
+use std::time::Duration;
 
-fn setup_long_mode_segments(sregs: &mut kvm_sys::kvm_sregs) {
-    let code_seg = |seg: &mut kvm_sys::kvm_segment| {
-        // Segment base address (unused in 64bit).
-        seg.base = 0x0;
-        // Limit (unused in 64bit).
-        seg.limit = 0x0;
-        // Segment selector -> Index=1, Table=GDT, RPL=0.
-        seg.selector = 0x8;
-        // Code read + execute.
-        seg.type_ = 10;
-        // Segment present.
-        seg.present = 1;
-        // Descriptor privilege level.
-        seg.dpl = 0;
-        // Operand/Pointer size (unused in 64bit, but must be 0 in 64bit mode).
-        seg.db = 0;
-        // Code/data segment.
-        seg.s = 1;
-        // Native 64 bit code segment.
-        seg.l = 1;
-        // Granularity (unused in 64bit).
-        seg.g = 0;
-    };
+/// Guest IO port to print `u32` values, see `pu64` in `guest64-msr.S`.
+const VALUE_PORT: u16 = 0x0;
 
-    let data_seg = |seg: &mut kvm_sys::kvm_segment| {
-        // Segment base address (unused in 64bit).
-        seg.base = 0x0;
-        // Limit (unused in 64bit).
-        seg.limit = 0x0;
-        // Segment selector -> Index=2, Table=GDT, RPL=0.
-        seg.selector = 0x10;
-        // Data read + write.
-        seg.type_ = 2;
-        // Segment present.
-        seg.present = 1;
-        // Descriptor privilege level.
-        seg.dpl = 0;
-        // Operand/Pointer size (unused in 64bit, but must be 0 in 64bit mode).
-        seg.db = 0;
-        // Code/data segment.
-        seg.s = 1;
-        // Native 64 bit code segment.
-        seg.l = 0;
-        // Granularity (unused in 64bit).
-        seg.g = 0;
-    };
+/// Prints values written by the guest to [`VALUE_PORT`] as hex.
+struct ValueOutput;
+
+impl Device for ValueOutput {
+    fn read(&mut self, _offset: u64, data: &mut [u8]) {
+        data.fill(0xff);
+    }
 
-    code_seg(&mut sregs.cs);
-    data_seg(&mut sregs.ds);
-    data_seg(&mut sregs.ss);
-    data_seg(&mut sregs.fs);
-    data_seg(&mut sregs.gs);
-    data_seg(&mut sregs.es);
+    fn write(&mut self, _offset: u64, data: &[u8]) {
+        match data.try_into() {
+            Ok(val) => println!("{:x}", u32::from_le_bytes(val)),
+            Err(_) => println!("{:x?}", data),
+        }
+    }
 }
 
-#[rustfmt::skip]
-fn setup_long_mode_4level_paging(mem: &mut UserMem) -> PhysAddr {
+fn setup_long_mode_4level_paging(mem: &mut UserMem) -> Result<u64> {
     assert_eq!(0x8000, mem.as_ref().len());
 
     // As a small exercise we create the following 4-level virtual address mapping using 4K pages:
@@ -95,65 +64,83 @@ fn setup_long_mode_4level_paging(mem: &mut UserMem) -> PhysAddr {
     //
     // PML4, PDP, PD will contain a single entry at index 0.
     // PT will contain 5 page table entries (PTE) at index {0,1,2,3,4} -> 5 * 4K = 20K.
+    let mut pt = PageTables::new(mem, PhysAddr(0x0000), 0x4000)?;
 
-    let mut w = |addr: PhysAddr, val: u64| mem.load(addr, &val.to_le_bytes());
-
-    // PML4E[0] refers to PDPE[0:4095].
-    w(PhysAddr(0x0000), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x1000);
-    // PDPE[0] refers to PDE[0:4095].
-    w(PhysAddr(0x1000), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x2000);
-    // PDE[0] refers to PTE[0:4095].
-    w(PhysAddr(0x2000), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x3000);
-
-    // PTE[0] maps Virt [0x0000:0x0fff] -> Phys [0x4000:0x4fff].
+    // Map Virt [0x0000:0x0fff] -> Phys [0x4000:0x4fff].
     // Just because we can, map this page readonly, as we loaded our guest sw here.
-    w(PhysAddr(0x3000), PAGE_ENTRY_PRESENT | 0x4000);
-    // PTE[1] maps Virt [0x1000:0x1fff] -> Phys [0x5000:0x5fff].
-    w(PhysAddr(0x3008), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x5000);
-    // PTE[2] maps Virt [0x2000:0x2fff] -> Phys [0x6000:0x6fff].
-    w(PhysAddr(0x3010), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x6000);
-    // PTE[3] maps Virt [0x3000:0x3fff] -> Phys [0x7000:0x7fff].
-    w(PhysAddr(0x3018), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x7000);
-    // PTE[4] maps Virt [0x4000:0x4fff] -> Phys [0x8000:0x8fff]
+    pt.map(
+        mem,
+        0x0000,
+        PhysAddr(0x4000),
+        0x1000,
+        PageSize::Size4K,
+        PageFlags::NONE,
+    )?;
+    // Map Virt [0x1000:0x4fff] -> Phys [0x5000:0x8fff].
     //
-    // The PA range is not backed by a memory mapping in the VM and hence
-    // writing to the VA range from the guest should trigger a VM MMIO exit.
-    w(PhysAddr(0x3020), PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | 0x8000);
+    // The PA range [0x8000:0x8fff] is not backed by a memory mapping in the VM and hence
+    // writing to the VA range [0x4000:0x4fff] from the guest should trigger a VM MMIO exit.
+    pt.map(
+        mem,
+        0x1000,
+        PhysAddr(0x5000),
+        0x4000,
+        PageSize::Size4K,
+        PageFlags::RW,
+    )?;
 
     // Return address of PML4.
-    PhysAddr(0x0000)
+    Ok(pt.cr3())
 }
 
-fn setup_long_mode(sregs: &mut kvm_sys::kvm_sregs, mem: &mut UserMem) {
-    // Setup segment descriptors for long mode.
-    setup_long_mode_segments(sregs);
-
-    // Setup paging structures.
-    let pml4_base = setup_long_mode_4level_paging(mem);
-    // Setup physical address of first paging structure (PML4).
-    sregs.cr3 = pml4_base.0;
-
-    // Enable paging (PG) and protection (PE).
-    sregs.cr0 = CR0_PG | CR0_PE;
-    // Enable physical address extension (PAE).
-    sregs.cr4 = CR4_PAE;
-    // Set long mode active (LMA) and long mode enabled (LME).
-    sregs.efer = EFER_LMA | EFER_LME;
+fn handle_exit(dispatcher: &mut Dispatcher, exit: KvmExit<'_>) -> Result<bool> {
+    let exit = match dispatcher.dispatch(exit) {
+        Dispatch::Handled => return Ok(true),
+        Dispatch::Unhandled(exit) | Dispatch::Exit(exit) => exit,
+    };
+
+    match exit {
+        KvmExit::Halt => return Ok(false),
+        KvmExit::IoIn(port, data) => {
+            println!("IO_IN: port={} len={}", port, data.len());
+            // Provide some input data.
+            data.fill(0xaa);
+        }
+        KvmExit::MmioRead(addr, data) => {
+            println!("MMIO_READ: addr={:#x} len={}", addr, data.len());
+            // Provide some read data.
+            data.fill(0xbb);
+        }
+        KvmExit::MmioWrite(addr, data) => {
+            println!(
+                "MMIO_WRITE: addr={:#x} len={} data={:#x?}",
+                addr,
+                data.len(),
+                data
+            );
+        }
+        KvmExit::Debug(_) => {}
+        exit => {
+            println!("UNHANDLED_EXIT: {:?}", exit);
+            return Ok(false);
+        }
+    };
+    Ok(true)
 }
 
-fn main() -> std::io::Result<()> {
+fn main() -> Result<()> {
     // Create VM & VCPU.
-    let vm = Kvm::new()?.create_vm()?;
+    let mut vm = Kvm::new()?.create_vm()?;
     let mut vcpu = vm.create_vpcu(0)?;
 
     // Map memory for guest VM.
     let mut mem = UserMem::new(0x8000)?;
     unsafe {
-        vm.set_user_memory_region(PhysAddr(0x0), &mem)?;
+        vm.set_user_memory_region(0, PhysAddr(0x0), &mem, MemFlags::NONE)?;
     }
 
     // Load guest image at physical address starting from 0x4000.
-    mem.load(PhysAddr(0x4000), include_bytes!("../guest/guest64"));
+    mem.load(PhysAddr(0x4000), include_bytes!("../guest/guest64"))?;
 
     // Initialize VPCU registers.
     let mut regs = vcpu.get_regs()?;
@@ -164,51 +151,44 @@ fn main() -> std::io::Result<()> {
     regs.rflags = 0x2;
     vcpu.set_regs(regs)?;
 
-    // Initialize VPCU special registers.
-    let mut sregs = vcpu.get_sregs()?;
-    // Setup long mode and paging to map:
+    // Setup paging to map:
     //     VirtAddr [0x0000:0x3fff] -> PhysAddr [0x4000:0x7fff]
-    setup_long_mode(&mut sregs, &mut mem);
-    vcpu.set_sregs(sregs)?;
+    let cr3 = setup_long_mode_4level_paging(&mut mem)?;
+    // Setup long mode with the GDT at VirtAddr 0x3000 (PhysAddr 0x7000), which is unused by the
+    // guest.
+    mode::setup_long_mode(&vcpu, &mut mem, cr3, 0x3000)?;
+
+    // Optionally wait for `gdb` to attach, eg `--gdb 127.0.0.1:1234`.
+    let mut gdb = match std::env::args().skip_while(|arg| arg != "--gdb").nth(1) {
+        Some(addr) => Some(GdbStub::listen_tcp(addr)?),
+        None => None,
+    };
+
+    // Setup devices.
+    let mut dispatcher = Dispatcher::new();
+    dispatcher.io.insert(
+        COM1_PORT,
+        NR_PORTS,
+        Box::new(Serial::new(std::io::stdout())),
+    )?;
+    dispatcher.io.insert(VALUE_PORT, 1, Box::new(ValueOutput))?;
 
     // Run VCPU until `hlt` instruction.
-    while let Ok(exit) = vcpu.run() {
-        match exit {
-            KvmExit::Halt => break,
-            KvmExit::IoIn(port, data) => {
-                println!("IO_IN: port={} len={}", port, data.len());
-                // Provide some input data.
-                data.fill(0xaa);
-            }
-            KvmExit::IoOut(port, data) => {
-                if port == 0x42 {
-                    // Magic port to interpret any input as string.
-                    let s = std::str::from_utf8(data).unwrap();
-                    print!("{}", s);
-                } else {
-                    // By default format print bytes as hex string.
-                    let val = match data.len() {
-                        4 => u32::from_le_bytes(data.try_into().unwrap()) as u64,
-                        _ => todo!("unknown size {}", data.len()),
-                    };
-                    println!("{:x}", val);
-                }
-            }
-            KvmExit::MmioRead(addr, data) => {
-                println!("MMIO_READ: addr={:#x} len={}", addr, data.len());
-                // Provide some read data.
-                data.fill(0xbb);
-            }
-            KvmExit::MmioWrite(addr, data) => {
-                println!(
-                    "MMIO_WRITE: addr={:#x} len={} data={:#x?}",
-                    addr,
-                    data.len(),
-                    data
-                );
+    match gdb.as_mut() {
+        Some(gdb) => loop {
+            let exit = gdb.run(&mut vcpu, &mut mem)?;
+            if !handle_exit(&mut dispatcher, exit)? {
+                break;
             }
-            KvmExit::Debug(_pc) => {}
-        };
+        },
+        None => match Runner::new()
+            .timeout(Duration::from_secs(5))
+            .run(&mut vcpu, |exit| handle_exit(&mut dispatcher, exit))
+        {
+            RunOutcome::Halted => {}
+            RunOutcome::Error(err) => return Err(err),
+            outcome => println!("GUEST_STOPPED: {:?}", outcome),
+        },
     }
 
     // The guest writes at virtual address [0x2000 - 0x2003] which will be visible in physical
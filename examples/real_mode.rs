@@ -2,19 +2,42 @@
 //
 // Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
 
+use std::time::Duration;
+
+use kvm_rs::bus::{Device, Dispatch, Dispatcher};
 use kvm_rs::kvm::Kvm;
+use kvm_rs::runner::{RunOutcome, Runner};
 use kvm_rs::vcpu::KvmExit;
-use kvm_rs::{PhysAddr, UserMem};
+use kvm_rs::vm::MemFlags;
+use kvm_rs::{PhysAddr, Result, UserMem};
+
+/// Guest IO port for string output and input.
+const CONSOLE_PORT: u16 = 0x1000;
+
+/// Console on the guest output port, reads provide some input data.
+struct Console;
 
-fn main() -> std::io::Result<()> {
+impl Device for Console {
+    fn read(&mut self, _offset: u64, data: &mut [u8]) {
+        println!("IO_IN: port={} len={}", CONSOLE_PORT, data.len());
+        data.fill(0xaa);
+    }
+
+    fn write(&mut self, _offset: u64, data: &[u8]) {
+        let s = std::str::from_utf8(data).unwrap();
+        print!("{}", s);
+    }
+}
+
+fn main() -> Result<()> {
     // Create VM & VCPU.
-    let vm = Kvm::new()?.create_vm()?;
+    let mut vm = Kvm::new()?.create_vm()?;
     let mut vcpu = vm.create_vpcu(0)?;
 
     // Map memory for guest VM and initialize with guest image.
     let mem = UserMem::with_init(0x1000, include_bytes!("../guest/guest16"))?;
     unsafe {
-        vm.set_user_memory_region(PhysAddr(0x0), &mem)?;
+        vm.set_user_memory_region(0, PhysAddr(0x0), &mem, MemFlags::NONE)?;
     }
 
     // Initialize VPCU registers.
@@ -29,34 +52,46 @@ fn main() -> std::io::Result<()> {
     sregs.cs.selector = 0;
     vcpu.set_sregs(sregs)?;
 
+    // Setup devices.
+    let mut dispatcher = Dispatcher::new();
+    dispatcher.io.insert(CONSOLE_PORT, 1, Box::new(Console))?;
+
     // Run VCPU until `hlt` instruction.
-    while let Ok(exit) = vcpu.run() {
-        match exit {
-            KvmExit::Halt => break,
-            KvmExit::IoIn(port, data) => {
-                println!("IO_IN: port={} len={}", port, data.len());
-                // Provide some input data.
-                data.fill(0xaa);
-            }
-            KvmExit::IoOut(_port, data) => {
-                let s = std::str::from_utf8(data).unwrap();
-                print!("{}", s);
-            }
-            KvmExit::MmioRead(addr, data) => {
-                println!("MMIO_READ: addr={:#x} len={}", addr, data.len());
-                // Provide some read data.
-                data.fill(0xbb);
-            }
-            KvmExit::MmioWrite(addr, data) => {
-                println!(
-                    "MMIO_WRITE: addr={:#x} len={} data={:#x?}",
-                    addr,
-                    data.len(),
-                    data
-                );
-            }
-            KvmExit::Debug(_pc) => {}
-        };
+    let outcome = Runner::new()
+        .timeout(Duration::from_secs(5))
+        .run(&mut vcpu, |exit| {
+            let exit = match dispatcher.dispatch(exit) {
+                Dispatch::Handled => return Ok(true),
+                Dispatch::Unhandled(exit) | Dispatch::Exit(exit) => exit,
+            };
+
+            match exit {
+                KvmExit::MmioRead(addr, data) => {
+                    println!("MMIO_READ: addr={:#x} len={}", addr, data.len());
+                    // Provide some read data.
+                    data.fill(0xbb);
+                }
+                KvmExit::MmioWrite(addr, data) => {
+                    println!(
+                        "MMIO_WRITE: addr={:#x} len={} data={:#x?}",
+                        addr,
+                        data.len(),
+                        data
+                    );
+                }
+                KvmExit::Debug(_) => {}
+                exit => {
+                    println!("UNHANDLED_EXIT: {:?}", exit);
+                    return Ok(false);
+                }
+            };
+            Ok(true)
+        });
+
+    match outcome {
+        RunOutcome::Halted => {}
+        RunOutcome::Error(err) => return Err(err),
+        outcome => println!("GUEST_STOPPED: {:?}", outcome),
     }
 
     Ok(())
@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! IO port and MMIO buses routing guest accesses to devices.
+//!
+//! Devices implement the [`Device`] trait and are registered for an address range on an
+//! [`IoBus`] or [`MmioBus`]. A [`Dispatcher`] combines both buses and routes the IO and MMIO
+//! exits returned by [`Vcpu::run`](crate::vcpu::Vcpu::run) to the devices.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::vcpu::KvmExit;
+use crate::{Error, Result};
+
+/// Device emulated in user space and accessed by the guest through an [`IoBus`] or [`MmioBus`].
+pub trait Device: Send {
+    /// Guest read of `data.len()` bytes at `offset` relative to the base the device is registered
+    /// at.
+    fn read(&mut self, offset: u64, data: &mut [u8]);
+
+    /// Guest write of `data` at `offset` relative to the base the device is registered at.
+    fn write(&mut self, offset: u64, data: &[u8]);
+}
+
+/// Shared devices, eg to access the device state while it is registered on a bus.
+impl<D: Device> Device for Arc<Mutex<D>> {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        self.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .read(offset, data)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        self.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .write(offset, data)
+    }
+}
+
+/// Device registered for the address range `[base, base + len)`.
+struct BusRange {
+    len: u64,
+    device: Box<dyn Device>,
+}
+
+/// Address space with non overlapping device ranges, indexed by the base address.
+#[derive(Default)]
+struct Bus {
+    ranges: BTreeMap<u64, BusRange>,
+}
+
+impl Bus {
+    fn insert(&mut self, base: u64, len: u64, device: Box<dyn Device>) -> Result<()> {
+        if len == 0 {
+            return Err(Error::InvalidArgument("empty bus range"));
+        }
+        let end = base
+            .checked_add(len)
+            .ok_or(Error::InvalidArgument("bus range exceeds address space"))?;
+
+        // Only the last range starting before `end` can overlap, as ranges do not overlap.
+        if let Some((&other, range)) = self.ranges.range(..end).next_back() {
+            if other + range.len > base {
+                return Err(Error::BusOverlap(base, other));
+            }
+        }
+
+        self.ranges.insert(base, BusRange { len, device });
+        Ok(())
+    }
+
+    fn remove(&mut self, base: u64) -> Option<Box<dyn Device>> {
+        self.ranges.remove(&base).map(|range| range.device)
+    }
+
+    /// Find the device and offset for an access of `len` bytes at `addr`, which must be fully
+    /// contained in the device range.
+    fn find(&mut self, addr: u64, len: u64) -> Option<(&mut dyn Device, u64)> {
+        let (&base, range) = self.ranges.range_mut(..=addr).next_back()?;
+        let offset = addr - base;
+
+        if offset.checked_add(len)? <= range.len {
+            Some((range.device.as_mut(), offset))
+        } else {
+            None
+        }
+    }
+
+    fn read(&mut self, addr: u64, len: u64, data: &mut [u8]) -> bool {
+        match self.find(addr, len) {
+            Some((device, offset)) => {
+                device.read(offset, data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn write(&mut self, addr: u64, len: u64, data: &[u8]) -> bool {
+        match self.find(addr, len) {
+            Some((device, offset)) => {
+                device.write(offset, data);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// IO port bus (`in` / `out` instructions).
+///
+/// String IO (`rep ins` / `rep outs`) repeatedly accesses the same port, therefore an access is
+/// routed to the device registered for its port and the data of all repetitions is passed in a
+/// single call.
+#[derive(Default)]
+pub struct IoBus {
+    bus: Bus,
+}
+
+impl IoBus {
+    /// Create an empty IO bus.
+    pub fn new() -> IoBus {
+        IoBus::default()
+    }
+
+    /// Register `device` for the `len` ports starting at `port`.
+    ///
+    /// Returns [`Error::BusOverlap`](crate::Error::BusOverlap) if the ports overlap with an
+    /// already registered device.
+    pub fn insert(&mut self, port: u16, len: u16, device: Box<dyn Device>) -> Result<()> {
+        if u32::from(port) + u32::from(len) > 0x1_0000 {
+            return Err(Error::InvalidArgument("bus range exceeds address space"));
+        }
+        self.bus.insert(port.into(), len.into(), device)
+    }
+
+    /// Unregister the device registered at `port`.
+    pub fn remove(&mut self, port: u16) -> Option<Box<dyn Device>> {
+        self.bus.remove(port.into())
+    }
+
+    /// Route a read from `port` to the registered device, returns `false` if there is none.
+    pub fn read(&mut self, port: u16, data: &mut [u8]) -> bool {
+        self.bus.read(port.into(), 1, data)
+    }
+
+    /// Route a write to `port` to the registered device, returns `false` if there is none.
+    pub fn write(&mut self, port: u16, data: &[u8]) -> bool {
+        self.bus.write(port.into(), 1, data)
+    }
+}
+
+/// Memory mapped IO bus for guest physical addresses not backed by a memory slot.
+#[derive(Default)]
+pub struct MmioBus {
+    bus: Bus,
+}
+
+impl MmioBus {
+    /// Create an empty MMIO bus.
+    pub fn new() -> MmioBus {
+        MmioBus::default()
+    }
+
+    /// Register `device` for the `len` bytes starting at guest physical address `addr`.
+    ///
+    /// Returns [`Error::BusOverlap`](crate::Error::BusOverlap) if the range overlaps with an
+    /// already registered device.
+    pub fn insert(&mut self, addr: u64, len: u64, device: Box<dyn Device>) -> Result<()> {
+        self.bus.insert(addr, len, device)
+    }
+
+    /// Unregister the device registered at `addr`.
+    pub fn remove(&mut self, addr: u64) -> Option<Box<dyn Device>> {
+        self.bus.remove(addr)
+    }
+
+    /// Route a read at `addr` to the registered device, returns `false` if there is none or the
+    /// access crosses the end of the device range.
+    pub fn read(&mut self, addr: u64, data: &mut [u8]) -> bool {
+        self.bus.read(addr, data.len() as u64, data)
+    }
+
+    /// Route a write at `addr` to the registered device, returns `false` if there is none or the
+    /// access crosses the end of the device range.
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> bool {
+        self.bus.write(addr, data.len() as u64, data)
+    }
+}
+
+/// Result of [`Dispatcher::dispatch`].
+#[derive(Debug)]
+pub enum Dispatch<'cpu> {
+    /// The IO or MMIO access was handled by a device.
+    Handled,
+    /// No device is registered for the IO or MMIO access. The data of reads is set to all ones,
+    /// as for an unconnected bus.
+    Unhandled(KvmExit<'cpu>),
+    /// The exit is not an IO or MMIO access.
+    Exit(KvmExit<'cpu>),
+}
+
+/// Dispatcher routing IO and MMIO exits to the devices on its buses.
+///
+/// ```no_run
+/// # use kvm_rs::bus::{Device, Dispatch, Dispatcher};
+/// # use kvm_rs::runner::Runner;
+/// struct Console;
+///
+/// impl Device for Console {
+///     fn read(&mut self, _offset: u64, data: &mut [u8]) {
+///         data.fill(0);
+///     }
+///
+///     fn write(&mut self, _offset: u64, data: &[u8]) {
+///         print!("{}", String::from_utf8_lossy(data));
+///     }
+/// }
+///
+/// # fn main() -> kvm_rs::Result<()> {
+/// # let vm = kvm_rs::kvm::Kvm::new()?.create_vm()?;
+/// let mut vcpu = vm.create_vpcu(0)?;
+/// let mut dispatcher = Dispatcher::new();
+/// dispatcher.io.insert(0x42, 1, Box::new(Console))?;
+/// // .. setup guest memory and registers.
+/// Runner::new().run(&mut vcpu, |exit| match dispatcher.dispatch(exit) {
+///     Dispatch::Handled => Ok(true),
+///     Dispatch::Unhandled(exit) | Dispatch::Exit(exit) => {
+///         println!("unhandled exit {:?}", exit);
+///         Ok(false)
+///     }
+/// });
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct Dispatcher {
+    pub io: IoBus,
+    pub mmio: MmioBus,
+}
+
+impl Dispatcher {
+    /// Create a dispatcher with empty buses.
+    pub fn new() -> Dispatcher {
+        Dispatcher::default()
+    }
+
+    /// Route the IO or MMIO access of `exit` to the registered device.
+    pub fn dispatch<'cpu>(&mut self, mut exit: KvmExit<'cpu>) -> Dispatch<'cpu> {
+        let handled = match exit {
+            KvmExit::IoIn(port, ref mut data) => self.io.read(port, data) || fill_ones(data),
+            KvmExit::IoOut(port, data) => self.io.write(port, data),
+            KvmExit::MmioRead(addr, ref mut data) => self.mmio.read(addr, data) || fill_ones(data),
+            KvmExit::MmioWrite(addr, data) => self.mmio.write(addr, data),
+            exit => return Dispatch::Exit(exit),
+        };
+
+        if handled {
+            Dispatch::Handled
+        } else {
+            Dispatch::Unhandled(exit)
+        }
+    }
+}
+
+/// Set the data of an unhandled read to all ones, always returns `false`.
+fn fill_ones(data: &mut [u8]) -> bool {
+    data.fill(0xff);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device recording the last access.
+    #[derive(Default)]
+    struct Recorder {
+        last: Option<(u64, Vec<u8>)>,
+    }
+
+    impl Device for Recorder {
+        fn read(&mut self, offset: u64, data: &mut [u8]) {
+            data.fill(offset as u8);
+            self.last = Some((offset, data.to_vec()));
+        }
+
+        fn write(&mut self, offset: u64, data: &[u8]) {
+            self.last = Some((offset, data.to_vec()));
+        }
+    }
+
+    #[test]
+    fn bus_overlap() {
+        let mut bus = MmioBus::new();
+        bus.insert(0x1000, 0x100, Box::new(Recorder::default()))
+            .unwrap();
+        bus.insert(0x1100, 0x100, Box::new(Recorder::default()))
+            .unwrap();
+        bus.insert(0xf00, 0x100, Box::new(Recorder::default()))
+            .unwrap();
+
+        assert!(matches!(
+            bus.insert(0x10ff, 1, Box::new(Recorder::default())),
+            Err(Error::BusOverlap(0x10ff, 0x1000))
+        ));
+        assert!(matches!(
+            bus.insert(0x800, 0x1000, Box::new(Recorder::default())),
+            Err(Error::BusOverlap(0x800, 0x1100))
+        ));
+        assert!(matches!(
+            bus.insert(0x2000, 0, Box::new(Recorder::default())),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            bus.insert(u64::MAX, 2, Box::new(Recorder::default())),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        assert!(bus.remove(0x1000).is_some());
+        assert!(bus.remove(0x1000).is_none());
+        bus.insert(0x10ff, 1, Box::new(Recorder::default()))
+            .unwrap();
+
+        let mut io = IoBus::new();
+        assert!(matches!(
+            io.insert(0xffff, 2, Box::new(Recorder::default())),
+            Err(Error::InvalidArgument(_))
+        ));
+        io.insert(0xffff, 1, Box::new(Recorder::default())).unwrap();
+    }
+
+    #[test]
+    fn bus_routing() {
+        let dev = Arc::new(Mutex::new(Recorder::default()));
+        let mut dispatcher = Dispatcher::new();
+        dispatcher
+            .mmio
+            .insert(0x1000, 0x10, Box::new(dev.clone()))
+            .unwrap();
+        dispatcher
+            .io
+            .insert(0x3f8, 8, Box::new(dev.clone()))
+            .unwrap();
+
+        let mut data = [0u8; 4];
+        assert!(matches!(
+            dispatcher.dispatch(KvmExit::MmioRead(0x1004, &mut data)),
+            Dispatch::Handled
+        ));
+        assert_eq!(data, [4; 4]);
+
+        // Access crossing the end of the device range.
+        assert!(matches!(
+            dispatcher.dispatch(KvmExit::MmioRead(0x100e, &mut data)),
+            Dispatch::Unhandled(KvmExit::MmioRead(0x100e, _))
+        ));
+        assert_eq!(data, [0xff; 4]);
+
+        // String IO passes the data of all repetitions.
+        assert!(matches!(
+            dispatcher.dispatch(KvmExit::IoOut(0x3ff, b"hello")),
+            Dispatch::Handled
+        ));
+        assert_eq!(dev.lock().unwrap().last, Some((7, b"hello".to_vec())));
+
+        assert!(matches!(
+            dispatcher.dispatch(KvmExit::IoOut(0x400, b"x")),
+            Dispatch::Unhandled(KvmExit::IoOut(0x400, _))
+        ));
+        assert!(matches!(
+            dispatcher.dispatch(KvmExit::Halt),
+            Dispatch::Exit(KvmExit::Halt)
+        ));
+    }
+}
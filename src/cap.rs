@@ -5,7 +5,6 @@
 //! Definitions of KVM capabilities.
 
 use crate::kvm_sys;
-use std::convert::Into;
 
 /// Definition of capabilities that return a bool value indicating whether the capability is
 /// supported or not.
@@ -13,11 +12,34 @@ use std::convert::Into;
 pub enum CapBool {
     /// Check if capabilities can be queried on VM fds (`KVM_CAP_CHECK_EXTENSION_VM`).
     CheckExtensionVm = kvm_sys::KVM_CAP_CHECK_EXTENSION_VM,
+    /// Check if memory regions can be mapped readonly (`KVM_CAP_READONLY_MEM`).
+    ReadonlyMem = kvm_sys::KVM_CAP_READONLY_MEM,
+    /// Check if the XSAVE state can be accessed (`KVM_CAP_XSAVE`).
+    Xsave = kvm_sys::KVM_CAP_XSAVE,
+    /// Check if the extended control registers can be accessed (`KVM_CAP_XCRS`).
+    Xcrs = kvm_sys::KVM_CAP_XCRS,
+    /// Check if feature MSRs can be queried on `/dev/kvm` (`KVM_CAP_GET_MSR_FEATURES`).
+    GetMsrFeatures = kvm_sys::KVM_CAP_GET_MSR_FEATURES,
+    /// Check if MSR filters can be installed (`KVM_CAP_X86_MSR_FILTER`).
+    X86MsrFilter = kvm_sys::KVM_CAP_X86_MSR_FILTER,
+    /// Check if guest MSR accesses can be forwarded to user space
+    /// (`KVM_CAP_X86_USER_SPACE_MSR`).
+    X86UserSpaceMsr = kvm_sys::KVM_CAP_X86_USER_SPACE_MSR,
+    /// Check if the CPUID can be configured (`KVM_CAP_EXT_CPUID`).
+    ExtCpuid = kvm_sys::KVM_CAP_EXT_CPUID,
+    /// Check if an in-kernel interrupt controller can be created (`KVM_CAP_IRQCHIP`).
+    Irqchip = kvm_sys::KVM_CAP_IRQCHIP,
+    /// Check if MSIs can be injected (`KVM_CAP_SIGNAL_MSI`).
+    SignalMsi = kvm_sys::KVM_CAP_SIGNAL_MSI,
+    /// Check if pending VCPU events can be accessed (`KVM_CAP_VCPU_EVENTS`).
+    VcpuEvents = kvm_sys::KVM_CAP_VCPU_EVENTS,
+    /// Check if the multiprocessing state can be accessed (`KVM_CAP_MP_STATE`).
+    MpState = kvm_sys::KVM_CAP_MP_STATE,
 }
 
-impl Into<u64> for CapBool {
-    fn into(self) -> u64 {
-        self as u64
+impl From<CapBool> for u64 {
+    fn from(cap: CapBool) -> u64 {
+        cap as u64
     }
 }
 
@@ -29,10 +51,17 @@ pub enum CapInt {
     NrVcpus = kvm_sys::KVM_CAP_NR_VCPUS,
     /// Get the possible max VPCUs (`KVM_CAP_MAX_VCPUS`).
     MaxVcpus = kvm_sys::KVM_CAP_MAX_VCPUS,
+    /// Get the max number of memory slots (`KVM_CAP_NR_MEMSLOTS`).
+    NrMemslots = kvm_sys::KVM_CAP_NR_MEMSLOTS,
+    /// Get the supported flags for manual dirty log protection
+    /// (`KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2`).
+    ManualDirtyLogProtect2 = kvm_sys::KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2,
+    /// Get the size of the XSAVE state in bytes, only available on VM fds (`KVM_CAP_XSAVE2`).
+    Xsave2 = kvm_sys::KVM_CAP_XSAVE2,
 }
 
-impl Into<u64> for CapInt {
-    fn into(self) -> u64 {
-        self as u64
+impl From<CapInt> for u64 {
+    fn from(cap: CapInt) -> u64 {
+        cap as u64
     }
 }
@@ -0,0 +1,637 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! CPUID configuration.
+//!
+//! A new VCPU starts with an empty CPUID table. The CPUID leaves supported by KVM and the host can
+//! be queried with [`Kvm::get_supported_cpuid`](crate::kvm::Kvm::get_supported_cpuid), adjusted
+//! with a [`CpuidBuilder`] and installed with
+//! [`Vcpu::set_cpuid2`](crate::vcpu::Vcpu::set_cpuid2).
+
+use crate::kvm_sys::{self, kvm_cpuid2};
+use crate::{Error, Result};
+
+/// Single CPUID leaf (`struct kvm_cpuid_entry2`).
+pub use crate::kvm_sys::kvm_cpuid_entry2 as CpuidEntry;
+
+/// Number of `u32` words of the `struct kvm_cpuid2` header.
+const HEADER_WORDS: usize = std::mem::size_of::<kvm_cpuid2>() / 4;
+
+/// Number of `u32` words of a [`CpuidEntry`].
+const ENTRY_WORDS: usize = std::mem::size_of::<CpuidEntry>() / 4;
+
+/// Upper bound for the number of entries queried from KVM.
+const MAX_ENTRIES: usize = 4096;
+
+/// Leaves whose output depends on the sub-leaf in `ecx`, same as `cpuid_function_is_indexed` of
+/// KVM.
+const INDEXED_LEAVES: &[u32] = &[
+    0x4,
+    0x7,
+    0xb,
+    0xd,
+    0xf,
+    0x10,
+    0x12,
+    0x14,
+    0x17,
+    0x18,
+    0x1d,
+    0x1e,
+    0x1f,
+    0x24,
+    0x8000_001d,
+];
+
+/// Leaf `0x4000_0000` holding the hypervisor vendor signature.
+pub const HYPERVISOR_LEAF: u32 = 0x4000_0000;
+
+/// CPUID output register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuidReg {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+impl CpuidEntry {
+    /// Entry for the leaf `function` and sub-leaf `index` with all registers set to `0`.
+    ///
+    /// Entries of leaves with sub-leaves, such as leaf `7` or `0xd`, are marked as indexed
+    /// (`KVM_CPUID_FLAG_SIGNIFCANT_INDEX`), including sub-leaf `0`.
+    pub fn new(function: u32, index: u32) -> CpuidEntry {
+        let mut entry = CpuidEntry::default();
+        entry.function = function;
+        entry.index = index;
+        if INDEXED_LEAVES.contains(&function) {
+            entry.flags = kvm_sys::KVM_CPUID_FLAG_SIGNIFCANT_INDEX;
+        }
+        entry
+    }
+
+    /// Check if the entry matches the leaf `function` and sub-leaf `index`, the sub-leaf is only
+    /// compared for indexed entries.
+    pub fn matches(&self, function: u32, index: u32) -> bool {
+        self.function == function
+            && (self.flags & kvm_sys::KVM_CPUID_FLAG_SIGNIFCANT_INDEX == 0 || self.index == index)
+    }
+
+    /// Get the value of the output register `reg`.
+    pub fn reg(&self, reg: CpuidReg) -> u32 {
+        match reg {
+            CpuidReg::Eax => self.eax,
+            CpuidReg::Ebx => self.ebx,
+            CpuidReg::Ecx => self.ecx,
+            CpuidReg::Edx => self.edx,
+        }
+    }
+
+    /// Get the output register `reg` as mutable reference.
+    pub fn reg_mut(&mut self, reg: CpuidReg) -> &mut u32 {
+        match reg {
+            CpuidReg::Eax => &mut self.eax,
+            CpuidReg::Ebx => &mut self.ebx,
+            CpuidReg::Ecx => &mut self.ecx,
+            CpuidReg::Edx => &mut self.edx,
+        }
+    }
+}
+
+/// Feature bit reported in a CPUID leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuidFeature {
+    pub function: u32,
+    pub index: u32,
+    pub reg: CpuidReg,
+    pub bit: u8,
+}
+
+impl CpuidFeature {
+    /// Feature `bit` in output register `reg` of the leaf `function` and sub-leaf `index`.
+    pub const fn new(function: u32, index: u32, reg: CpuidReg, bit: u8) -> CpuidFeature {
+        CpuidFeature {
+            function,
+            index,
+            reg,
+            bit,
+        }
+    }
+
+    /// x87 floating point unit.
+    pub const FPU: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Edx, 0);
+    /// Time stamp counter.
+    pub const TSC: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Edx, 4);
+    /// Model specific registers.
+    pub const MSR: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Edx, 5);
+    /// On-chip local APIC.
+    pub const APIC: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Edx, 9);
+    /// SSE extensions.
+    pub const SSE: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Edx, 25);
+    /// SSE2 extensions.
+    pub const SSE2: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Edx, 26);
+    /// SSE3 extensions.
+    pub const SSE3: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 0);
+    /// Fused multiply add extensions.
+    pub const FMA: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 12);
+    /// x2APIC mode of the local APIC.
+    pub const X2APIC: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 21);
+    /// TSC deadline mode of the local APIC timer.
+    pub const TSC_DEADLINE: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 24);
+    /// `xsave`/`xrstor` and `XCR0`.
+    pub const XSAVE: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 26);
+    /// `CR4.OSXSAVE` set by the OS.
+    pub const OSXSAVE: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 27);
+    /// AVX extensions.
+    pub const AVX: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 28);
+    /// Running under a hypervisor.
+    pub const HYPERVISOR: CpuidFeature = CpuidFeature::new(1, 0, CpuidReg::Ecx, 31);
+    /// AVX2 extensions.
+    pub const AVX2: CpuidFeature = CpuidFeature::new(7, 0, CpuidReg::Ebx, 5);
+    /// AVX-512 foundation.
+    pub const AVX512F: CpuidFeature = CpuidFeature::new(7, 0, CpuidReg::Ebx, 16);
+    /// Execute disable bit.
+    pub const NX: CpuidFeature = CpuidFeature::new(0x8000_0001, 0, CpuidReg::Edx, 20);
+    /// 1G pages.
+    pub const PDPE1GB: CpuidFeature = CpuidFeature::new(0x8000_0001, 0, CpuidReg::Edx, 26);
+    /// Long mode.
+    pub const LM: CpuidFeature = CpuidFeature::new(0x8000_0001, 0, CpuidReg::Edx, 29);
+}
+
+/// List of CPUID entries, safe wrapper of the variable length `struct kvm_cpuid2` used with
+/// [`Kvm::get_supported_cpuid`](crate::kvm::Kvm::get_supported_cpuid),
+/// [`Vcpu::set_cpuid2`](crate::vcpu::Vcpu::set_cpuid2) and
+/// [`Vcpu::get_cpuid2`](crate::vcpu::Vcpu::get_cpuid2).
+#[derive(Clone, Debug)]
+pub struct Cpuid {
+    /// Backing storage of the `struct kvm_cpuid2` header followed by the entries.
+    buf: Vec<u32>,
+}
+
+impl Default for Cpuid {
+    fn default() -> Cpuid {
+        Cpuid::new()
+    }
+}
+
+impl Cpuid {
+    pub fn new() -> Cpuid {
+        Cpuid {
+            buf: vec![0; HEADER_WORDS],
+        }
+    }
+
+    /// Create a list from `entries`.
+    pub fn from_entries(entries: &[CpuidEntry]) -> Cpuid {
+        let mut cpuid = Cpuid::new();
+        for entry in entries {
+            cpuid.push(*entry);
+        }
+        cpuid
+    }
+
+    /// Append `entry`, without checking for an existing entry of the same leaf.
+    pub fn push(&mut self, entry: CpuidEntry) {
+        self.buf.extend_from_slice(&[
+            entry.function,
+            entry.index,
+            entry.flags,
+            entry.eax,
+            entry.ebx,
+            entry.ecx,
+            entry.edx,
+            0,
+            0,
+            0,
+        ]);
+        // Update `nent` in the header.
+        self.buf[0] = self.len() as u32;
+    }
+
+    /// Replace the entry of the same leaf and sub-leaf as `entry` or append `entry`.
+    pub fn set(&mut self, entry: CpuidEntry) {
+        match self.get_mut(entry.function, entry.index) {
+            Some(e) => *e = entry,
+            None => self.push(entry),
+        }
+    }
+
+    /// Remove all entries for which `f` returns `false`.
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: Fn(&CpuidEntry) -> bool,
+    {
+        let entries: Vec<CpuidEntry> = self.entries().iter().copied().filter(f).collect();
+        *self = Cpuid::from_entries(&entries);
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        (self.buf.len() - HEADER_WORDS) / ENTRY_WORDS
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the entries.
+    pub fn entries(&self) -> &[CpuidEntry] {
+        if self.is_empty() {
+            return &[];
+        }
+
+        // Safe as the buffer holds `len` entries after the header and is properly aligned.
+        unsafe { std::slice::from_raw_parts(self.buf[HEADER_WORDS..].as_ptr().cast(), self.len()) }
+    }
+
+    /// Get the entries as mutable slice.
+    pub fn entries_mut(&mut self) -> &mut [CpuidEntry] {
+        if self.is_empty() {
+            return &mut [];
+        }
+
+        let len = self.len();
+        // Safe as the buffer holds `len` entries after the header and is properly aligned.
+        unsafe { std::slice::from_raw_parts_mut(self.buf[HEADER_WORDS..].as_mut_ptr().cast(), len) }
+    }
+
+    /// Get the first entry matching the leaf `function` and sub-leaf `index`, see
+    /// [`CpuidEntry::matches`].
+    pub fn get(&self, function: u32, index: u32) -> Option<&CpuidEntry> {
+        self.entries().iter().find(|e| e.matches(function, index))
+    }
+
+    /// Get the first entry matching the leaf `function` and sub-leaf `index` as mutable
+    /// reference, see [`CpuidEntry::matches`].
+    pub fn get_mut(&mut self, function: u32, index: u32) -> Option<&mut CpuidEntry> {
+        self.entries_mut()
+            .iter_mut()
+            .find(|e| e.matches(function, index))
+    }
+
+    /// Check if `feature` is reported.
+    pub fn has(&self, feature: CpuidFeature) -> bool {
+        self.get(feature.function, feature.index)
+            .map(|e| e.reg(feature.reg) & (1 << feature.bit) != 0)
+            .unwrap_or(false)
+    }
+
+    /// Clear the bit of `feature` to hide it from the guest.
+    pub fn mask(&mut self, feature: CpuidFeature) {
+        if let Some(e) = self.get_mut(feature.function, feature.index) {
+            *e.reg_mut(feature.reg) &= !(1 << feature.bit);
+        }
+    }
+
+    /// Set the bit of `feature` to report it to the guest.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if the leaf of `feature`
+    /// is not present.
+    pub fn unmask(&mut self, feature: CpuidFeature) -> Result<()> {
+        match self.get_mut(feature.function, feature.index) {
+            Some(e) => {
+                *e.reg_mut(feature.reg) |= 1 << feature.bit;
+                Ok(())
+            }
+            None => Err(Error::InvalidArgument("CPUID leaf not present")),
+        }
+    }
+
+    /// Report a hypervisor with the 12 byte `vendor` signature in leaf [`HYPERVISOR_LEAF`] and
+    /// set the [`HYPERVISOR`](CpuidFeature::HYPERVISOR) feature bit.
+    pub fn set_hypervisor_vendor(&mut self, vendor: &[u8; 12]) {
+        let word =
+            |i: usize| u32::from_le_bytes([vendor[i], vendor[i + 1], vendor[i + 2], vendor[i + 3]]);
+
+        let mut entry = self
+            .get(HYPERVISOR_LEAF, 0)
+            .copied()
+            .unwrap_or_else(|| CpuidEntry::new(HYPERVISOR_LEAF, 0));
+        // `eax` holds the highest hypervisor leaf.
+        entry.eax = entry.eax.max(HYPERVISOR_LEAF);
+        entry.ebx = word(0);
+        entry.ecx = word(4);
+        entry.edx = word(8);
+        self.set(entry);
+
+        // Leaf `1` is always present for a CPUID obtained from KVM.
+        let _ = self.unmask(CpuidFeature::HYPERVISOR);
+    }
+
+    /// Pointer to the `struct kvm_cpuid2` passed to the ioctls.
+    pub(crate) fn as_ptr(&self) -> *const kvm_cpuid2 {
+        self.buf.as_ptr().cast()
+    }
+}
+
+/// Get a CPUID list with `ioctl`, which is invoked with a pointer to a `struct kvm_cpuid2`.
+pub(crate) fn get_cpuid<F>(ioctl: F) -> Result<Cpuid>
+where
+    F: Fn(u64) -> Result<libc::c_int>,
+{
+    // The ioctl fails with `E2BIG` if `nent` is too small, retry with a larger buffer.
+    let mut nent = 64;
+    loop {
+        let mut buf = vec![0u32; HEADER_WORDS + nent * ENTRY_WORDS];
+        buf[0] = nent as u32;
+
+        match ioctl(buf.as_mut_ptr() as u64) {
+            Ok(_) => {
+                let nent = (buf[0] as usize).min(nent);
+                buf.truncate(HEADER_WORDS + nent * ENTRY_WORDS);
+                return Ok(Cpuid { buf });
+            }
+            Err(Error::Ioctl(_, err))
+                if err.raw_os_error() == Some(libc::E2BIG) && nent < MAX_ENTRIES =>
+            {
+                nent *= 2
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Features which depend on a feature and are hidden with it by [`CpuidBuilder::hide`].
+const DEPENDENCIES: &[(CpuidFeature, &[CpuidFeature])] = &[
+    (
+        CpuidFeature::XSAVE,
+        &[CpuidFeature::OSXSAVE, CpuidFeature::AVX],
+    ),
+    (
+        CpuidFeature::AVX,
+        &[CpuidFeature::FMA, CpuidFeature::AVX2, CpuidFeature::AVX512F],
+    ),
+    (CpuidFeature::SSE, &[CpuidFeature::SSE2]),
+    (CpuidFeature::SSE2, &[CpuidFeature::SSE3]),
+    (
+        CpuidFeature::APIC,
+        &[CpuidFeature::X2APIC, CpuidFeature::TSC_DEADLINE],
+    ),
+    (CpuidFeature::LM, &[CpuidFeature::PDPE1GB]),
+];
+
+/// `XCR0` state component enabled by AVX (YMM), reported in leaf `0xd`.
+const XCR0_AVX_MASK: u32 = 0b0000_0100;
+
+/// `XCR0` state components enabled by AVX-512 (opmask, ZMM_Hi256, Hi16_ZMM), reported in leaf
+/// `0xd`.
+const XCR0_AVX512_MASK: u32 = 0b1110_0000;
+
+/// Builder for a consistent CPU model, based on the CPUID supported by KVM.
+///
+/// ```no_run
+/// # use kvm_rs::cpuid::{CpuidBuilder, CpuidFeature};
+/// # fn main() -> kvm_rs::Result<()> {
+/// let kvm = kvm_rs::kvm::Kvm::new()?;
+/// let cpuid = CpuidBuilder::new(kvm.get_supported_cpuid()?)
+///     .hide(CpuidFeature::AVX)
+///     .hypervisor_vendor(b"mini-kvm-rs ")
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+pub struct CpuidBuilder {
+    cpuid: Cpuid,
+    hidden: Vec<CpuidFeature>,
+    vendor: Option<[u8; 12]>,
+    apic_id: Option<u32>,
+    max_leaf: Option<u32>,
+}
+
+impl CpuidBuilder {
+    /// Builder starting from the `supported` CPUID, eg from
+    /// [`Kvm::get_supported_cpuid`](crate::kvm::Kvm::get_supported_cpuid).
+    pub fn new(supported: Cpuid) -> CpuidBuilder {
+        CpuidBuilder {
+            cpuid: supported,
+            hidden: Vec::new(),
+            vendor: None,
+            apic_id: None,
+            max_leaf: None,
+        }
+    }
+
+    /// Hide `feature` and all features depending on it from the guest.
+    pub fn hide(&mut self, feature: CpuidFeature) -> &mut CpuidBuilder {
+        if self.hidden.contains(&feature) {
+            return self;
+        }
+
+        self.hidden.push(feature);
+        for (_, deps) in DEPENDENCIES.iter().filter(|(f, _)| *f == feature) {
+            for dep in deps.iter() {
+                self.hide(*dep);
+            }
+        }
+        self
+    }
+
+    /// Report a hypervisor with the 12 byte `vendor` signature, see
+    /// [`Cpuid::set_hypervisor_vendor`].
+    pub fn hypervisor_vendor(&mut self, vendor: &[u8; 12]) -> &mut CpuidBuilder {
+        self.vendor = Some(*vendor);
+        self
+    }
+
+    /// Report the (x2)APIC id `apic_id`, usually the VCPU id.
+    pub fn apic_id(&mut self, apic_id: u32) -> &mut CpuidBuilder {
+        self.apic_id = Some(apic_id);
+        self
+    }
+
+    /// Limit the highest basic leaf to `max_leaf` and drop all basic leaves above it.
+    pub fn max_basic_leaf(&mut self, max_leaf: u32) -> &mut CpuidBuilder {
+        self.max_leaf = Some(max_leaf);
+        self
+    }
+
+    /// Build the CPUID for [`Vcpu::set_cpuid2`](crate::vcpu::Vcpu::set_cpuid2).
+    pub fn build(&self) -> Cpuid {
+        let mut cpuid = self.cpuid.clone();
+
+        if let Some(max_leaf) = self.max_leaf {
+            cpuid.retain(|e| e.function <= max_leaf || e.function >= HYPERVISOR_LEAF);
+            if let Some(e) = cpuid.get_mut(0, 0) {
+                e.eax = e.eax.min(max_leaf);
+            }
+        }
+
+        for feature in &self.hidden {
+            cpuid.mask(*feature);
+        }
+
+        // Keep the XSAVE state components in leaf `0xd` consistent with the hidden features.
+        if self.hidden.contains(&CpuidFeature::XSAVE) {
+            cpuid.retain(|e| e.function != 0xd);
+        } else {
+            if self.hidden.contains(&CpuidFeature::AVX) {
+                if let Some(e) = cpuid.get_mut(0xd, 0) {
+                    e.eax &= !XCR0_AVX_MASK;
+                }
+                cpuid.retain(|e| e.function != 0xd || e.index != 2);
+            }
+            if self.hidden.contains(&CpuidFeature::AVX512F) {
+                if let Some(e) = cpuid.get_mut(0xd, 0) {
+                    e.eax &= !XCR0_AVX512_MASK;
+                }
+                cpuid.retain(|e| e.function != 0xd || !matches!(e.index, 5..=7));
+            }
+        }
+
+        if let Some(apic_id) = self.apic_id {
+            if let Some(e) = cpuid.get_mut(1, 0) {
+                e.ebx = (e.ebx & 0x00ff_ffff) | (apic_id & 0xff) << 24;
+            }
+            // Extended topology leaves report the x2APIC id in `edx` of each sub-leaf.
+            for e in cpuid.entries_mut() {
+                if e.function == 0xb || e.function == 0x1f {
+                    e.edx = apic_id;
+                }
+            }
+        }
+
+        if let Some(vendor) = &self.vendor {
+            cpuid.set_hypervisor_vendor(vendor);
+        }
+
+        cpuid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supported() -> Cpuid {
+        let mut leaf0 = CpuidEntry::new(0, 0);
+        leaf0.eax = 0xd;
+        let mut leaf1 = CpuidEntry::new(1, 0);
+        leaf1.ebx = 0x0100_0800;
+        leaf1.ecx = 1 << 26 | 1 << 28 | 1 << 12;
+        leaf1.edx = 1 << 25 | 1 << 26;
+        let mut leaf7 = CpuidEntry::new(7, 0);
+        leaf7.ebx = 1 << 5 | 1 << 16;
+        let mut leafd = CpuidEntry::new(0xd, 0);
+        leafd.eax = 0b1110_0111;
+
+        Cpuid::from_entries(&[
+            leaf0,
+            leaf1,
+            leaf7,
+            leafd,
+            CpuidEntry::new(0xd, 2),
+            CpuidEntry::new(0xd, 5),
+            CpuidEntry::new(0x8000_0000, 0),
+        ])
+    }
+
+    #[test]
+    fn cpuid_layout() {
+        let mut cpuid = supported();
+        assert_eq!(cpuid.len(), 7);
+        assert_eq!(unsafe { (*cpuid.as_ptr()).nent }, 7);
+        assert_eq!(cpuid.buf.len(), HEADER_WORDS + 7 * ENTRY_WORDS);
+
+        // Indexed leaves match on the sub-leaf, others ignore it.
+        assert_eq!(cpuid.get(0xd, 2).map(|e| e.index), Some(2));
+        assert!(cpuid.get(0xd, 1).is_none());
+        assert_eq!(cpuid.get(1, 5).map(|e| e.function), Some(1));
+
+        assert!(cpuid.has(CpuidFeature::AVX));
+        cpuid.mask(CpuidFeature::AVX);
+        assert!(!cpuid.has(CpuidFeature::AVX));
+        cpuid.unmask(CpuidFeature::X2APIC).unwrap();
+        assert!(cpuid.has(CpuidFeature::X2APIC));
+        assert!(matches!(
+            cpuid.unmask(CpuidFeature::LM),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        cpuid.set_hypervisor_vendor(b"KVMKVMKVM\0\0\0");
+        let leaf = cpuid.get(HYPERVISOR_LEAF, 0).unwrap();
+        assert_eq!(
+            (leaf.eax, leaf.ebx, leaf.ecx, leaf.edx),
+            (HYPERVISOR_LEAF, 0x4b4d_564b, 0x564b_4d56, 0x4d)
+        );
+        assert!(cpuid.has(CpuidFeature::HYPERVISOR));
+        assert_eq!(cpuid.len(), 8);
+
+        let empty = Cpuid::default();
+        assert!(empty.entries().is_empty());
+        assert_eq!(unsafe { (*empty.as_ptr()).nent }, 0);
+    }
+
+    #[test]
+    fn cpuid_sub_leaves() {
+        let mut cpuid = Cpuid::new();
+
+        let mut leaf7 = CpuidEntry::new(7, 0);
+        leaf7.ebx = 1 << 5;
+        cpuid.set(leaf7);
+        let mut leaf7_1 = CpuidEntry::new(7, 1);
+        leaf7_1.eax = 1 << 4;
+        cpuid.set(leaf7_1);
+
+        assert_eq!(cpuid.len(), 2);
+        assert_eq!(cpuid.get(7, 0).unwrap().ebx, 1 << 5);
+        assert_eq!(cpuid.get(7, 1).unwrap().eax, 1 << 4);
+        assert!(cpuid.get(7, 2).is_none());
+
+        // Replace sub-leaf `1` only.
+        leaf7_1.eax = 0;
+        cpuid.set(leaf7_1);
+        assert_eq!(cpuid.len(), 2);
+        assert_eq!(cpuid.get(7, 0).unwrap().ebx, 1 << 5);
+        assert_eq!(cpuid.get(7, 1).unwrap().eax, 0);
+
+        // Leaves without sub-leaves ignore the index.
+        assert_eq!(CpuidEntry::new(1, 0).flags, 0);
+        cpuid.set(CpuidEntry::new(1, 0));
+        assert!(cpuid.get(1, 3).is_some());
+    }
+
+    #[test]
+    fn cpuid_builder() {
+        let cpuid = CpuidBuilder::new(supported())
+            .hide(CpuidFeature::AVX)
+            .apic_id(3)
+            .max_basic_leaf(7)
+            .build();
+
+        assert!(!cpuid.has(CpuidFeature::AVX));
+        assert!(!cpuid.has(CpuidFeature::AVX2));
+        assert!(!cpuid.has(CpuidFeature::FMA));
+        assert!(cpuid.has(CpuidFeature::XSAVE));
+        assert!(cpuid.has(CpuidFeature::SSE2));
+        assert_eq!(cpuid.get(1, 0).unwrap().ebx, 0x0300_0800);
+
+        // Leaf `0xd` is above the highest basic leaf.
+        assert_eq!(cpuid.get(0, 0).unwrap().eax, 7);
+        assert!(cpuid.get(0xd, 0).is_none());
+        assert!(cpuid.get(0x8000_0000, 0).is_some());
+
+        let cpuid = CpuidBuilder::new(supported())
+            .hide(CpuidFeature::AVX)
+            .build();
+        assert_eq!(cpuid.get(0xd, 0).unwrap().eax, 0b011);
+        assert!(cpuid.get(0xd, 2).is_none());
+        assert!(cpuid.get(0xd, 5).is_none());
+
+        // Hiding AVX-512 keeps the AVX state component.
+        let cpuid = CpuidBuilder::new(supported())
+            .hide(CpuidFeature::AVX512F)
+            .build();
+        assert!(cpuid.has(CpuidFeature::AVX2));
+        assert!(!cpuid.has(CpuidFeature::AVX512F));
+        assert_eq!(cpuid.get(0xd, 0).unwrap().eax, 0b111);
+        assert!(cpuid.get(0xd, 2).is_some());
+        assert!(cpuid.get(0xd, 5).is_none());
+
+        let cpuid = CpuidBuilder::new(supported())
+            .hide(CpuidFeature::XSAVE)
+            .build();
+        assert!(!cpuid.has(CpuidFeature::AVX));
+        assert!(cpuid.get(0xd, 0).is_none());
+    }
+}
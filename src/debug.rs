@@ -0,0 +1,375 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Guest debugging support.
+
+use std::collections::BTreeMap;
+
+use crate::vcpu::{KvmExit, Vcpu};
+use crate::x86_64::*;
+use crate::{PhysAddr, Result, UserMem};
+
+/// Opcode of the `int3` instruction.
+const INT3: u8 = 0xcc;
+
+/// Number of hardware breakpoints (debug registers `DR0-DR3`).
+pub const NR_HW_BREAKPOINTS: usize = 4;
+
+/// Length of a data watchpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchLen {
+    One,
+    Two,
+    Four,
+    Eight,
+}
+
+impl WatchLen {
+    /// Length in bytes.
+    pub fn bytes(self) -> u64 {
+        match self {
+            WatchLen::One => 1,
+            WatchLen::Two => 2,
+            WatchLen::Four => 4,
+            WatchLen::Eight => 8,
+        }
+    }
+
+    fn dr7_len(self) -> u64 {
+        match self {
+            WatchLen::One => DR7_LEN_1,
+            WatchLen::Two => DR7_LEN_2,
+            WatchLen::Four => DR7_LEN_4,
+            WatchLen::Eight => DR7_LEN_8,
+        }
+    }
+}
+
+/// Hardware breakpoint installed in one of the debug registers `DR0-DR3` with
+/// [`Vcpu::set_hw_breakpoint`](crate::vcpu::Vcpu::set_hw_breakpoint).
+///
+/// Addresses are guest linear addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwBreakpoint {
+    /// Break before executing the instruction at the address.
+    Exec(u64),
+    /// Break after data writes to the address range.
+    Write(u64, WatchLen),
+    /// Break after data reads or writes to the address range.
+    ReadWrite(u64, WatchLen),
+}
+
+impl HwBreakpoint {
+    /// Guest linear address of the breakpoint.
+    pub fn addr(&self) -> u64 {
+        match *self {
+            HwBreakpoint::Exec(addr)
+            | HwBreakpoint::Write(addr, _)
+            | HwBreakpoint::ReadWrite(addr, _) => addr,
+        }
+    }
+
+    /// Check if the address is aligned to the watchpoint length, as required by the hardware.
+    pub(crate) fn is_aligned(&self) -> bool {
+        match *self {
+            HwBreakpoint::Exec(_) => true,
+            HwBreakpoint::Write(addr, len) | HwBreakpoint::ReadWrite(addr, len) => {
+                addr % len.bytes() == 0
+            }
+        }
+    }
+
+    /// Get the `DR7` bits to enable this breakpoint in debug register `idx`.
+    pub(crate) fn dr7(&self, idx: usize) -> u64 {
+        let (rw, len) = match *self {
+            HwBreakpoint::Exec(_) => (DR7_RW_EXEC, DR7_LEN_1),
+            HwBreakpoint::Write(_, len) => (DR7_RW_WRITE, len.dr7_len()),
+            HwBreakpoint::ReadWrite(_, len) => (DR7_RW_READ_WRITE, len.dr7_len()),
+        };
+
+        dr7_global_enable(idx) | dr7_rw_len(idx, rw, len)
+    }
+}
+
+/// Information about a [`KvmExit::Debug`](crate::vcpu::KvmExit::Debug) exit.
+#[derive(Clone, Copy, Debug)]
+pub struct DebugExit {
+    /// Exception vector which triggered the exit (`#DB` or `#BP`).
+    pub exception: u32,
+    /// Guest linear address of the instruction pointer.
+    pub pc: u64,
+    /// Debug status register `DR6`.
+    pub dr6: u64,
+    /// Debug control register `DR7`.
+    pub dr7: u64,
+}
+
+impl DebugExit {
+    /// Index of the hardware breakpoint that triggered the exit, if any.
+    pub fn hw_breakpoint(&self) -> Option<usize> {
+        if self.exception != u32::from(EXCEPTION_DB) {
+            return None;
+        }
+
+        DR6_B.iter().position(|&b| self.dr6 & b != 0)
+    }
+
+    /// Check if the exit was triggered by single stepping.
+    pub fn is_single_step(&self) -> bool {
+        self.exception == u32::from(EXCEPTION_DB) && self.dr6 & DR6_BS != 0
+    }
+
+    /// Check if the exit was triggered by a software breakpoint (`int3`).
+    pub fn is_sw_breakpoint(&self) -> bool {
+        self.exception == u32::from(EXCEPTION_BP)
+    }
+}
+
+/// Software breakpoint inserted with [`SwBreakpoints::insert`].
+struct SwBreakpoint {
+    phys: PhysAddr,
+    orig: u8,
+}
+
+/// Manager for an unlimited number of software breakpoints (`int3`).
+///
+/// Breakpoints are inserted by patching `int3` into guest memory. Hits are reported as
+/// [`KvmExit::Debug`](crate::vcpu::KvmExit::Debug) exits by
+/// [`SwBreakpoints::run`](crate::debug::SwBreakpoints::run), which transparently steps over the
+/// breakpoint when the VCPU is resumed.
+#[derive(Default)]
+pub struct SwBreakpoints {
+    bps: BTreeMap<u64, SwBreakpoint>,
+    /// Breakpoint currently being stepped over and the single step state of the VCPU before.
+    step_over: Option<(u64, bool)>,
+}
+
+impl SwBreakpoints {
+    pub fn new() -> SwBreakpoints {
+        SwBreakpoints::default()
+    }
+
+    /// Insert a software breakpoint at guest linear address `addr`, where the instruction is
+    /// located at physical address `phys` in `mem`.
+    pub fn insert(&mut self, mem: &mut UserMem, addr: u64, phys: PhysAddr) -> Result<()> {
+        if self.bps.contains_key(&addr) {
+            return Ok(());
+        }
+
+        let mut orig = [0u8; 1];
+        mem.read(phys, &mut orig)?;
+        mem.load(phys, &[INT3])?;
+
+        self.bps.insert(
+            addr,
+            SwBreakpoint {
+                phys,
+                orig: orig[0],
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove the software breakpoint at guest linear address `addr` and restore the original
+    /// instruction byte.
+    pub fn remove(&mut self, mem: &mut UserMem, addr: u64) -> Result<()> {
+        if let Some(bp) = self.bps.remove(&addr) {
+            // While stepping over the breakpoint, the original byte is already restored.
+            if !matches!(self.step_over, Some((step, _)) if step == addr) {
+                mem.load(bp.phys, &[bp.orig])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if a software breakpoint is inserted at guest linear address `addr`.
+    pub fn contains(&self, addr: u64) -> bool {
+        self.bps.contains_key(&addr)
+    }
+
+    /// Get the original byte at physical address `phys` in case it is patched by a software
+    /// breakpoint.
+    pub fn orig_byte(&self, phys: PhysAddr) -> Option<u8> {
+        self.bps
+            .values()
+            .find(|bp| bp.phys == phys)
+            .map(|bp| bp.orig)
+    }
+
+    /// Remove all software breakpoints and restore the original instruction bytes.
+    pub fn clear(&mut self, mem: &mut UserMem) -> Result<()> {
+        let addrs: Vec<u64> = self.bps.keys().copied().collect();
+        for addr in addrs {
+            self.remove(mem, addr)?;
+        }
+        Ok(())
+    }
+
+    /// Run the guest VCPU with [`Vcpu::run`](crate::vcpu::Vcpu::run) and intercept software
+    /// breakpoints.
+    ///
+    /// If the VCPU is resumed at a breakpoint that was previously hit, the original instruction is
+    /// restored and single stepped before the breakpoint is inserted again. The single step is
+    /// only reported if single stepping was enabled on the VCPU.
+    ///
+    /// `int3` instructions of the guest itself, which are not inserted with
+    /// [`SwBreakpoints::insert`], are not reported but re-injected as `#BP` into the guest.
+    pub fn run<'cpu>(&mut self, vcpu: &'cpu mut Vcpu, mem: &mut UserMem) -> Result<KvmExit<'cpu>> {
+        self.enter(vcpu, mem)?;
+        vcpu.exit()
+    }
+
+    /// Same as [`SwBreakpoints::run`] without decoding the exit reason.
+    pub(crate) fn enter(&mut self, vcpu: &mut Vcpu, mem: &mut UserMem) -> Result<()> {
+        if !vcpu.sw_breakpoints() {
+            vcpu.set_sw_breakpoints(true)?;
+        }
+
+        if self.step_over.is_none() {
+            let pc = vcpu.get_regs()?.rip.wrapping_add(vcpu.get_sregs()?.cs.base);
+            if let Some(bp) = self.bps.get(&pc) {
+                // Restore the original instruction and step over it.
+                mem.load(bp.phys, &[bp.orig])?;
+                self.step_over = Some((pc, vcpu.single_step()));
+                vcpu.set_single_step(true)?;
+            }
+        }
+
+        loop {
+            vcpu.enter()?;
+
+            if let Some(debug) = vcpu.debug_exit() {
+                if debug.is_sw_breakpoint() && !self.contains(debug.pc) {
+                    // The `int3` belongs to the guest, deliver the `#BP` to the guest.
+                    vcpu.inject_exception(EXCEPTION_BP, None)?;
+                    continue;
+                }
+            }
+
+            if let Some((addr, single_step)) = self.step_over {
+                if !matches!(vcpu.debug_exit(), Some(debug) if debug.is_single_step()) {
+                    // The instruction exited to userspace (eg IO) and has not finished yet.
+                    return Ok(());
+                }
+
+                // Instruction stepped, insert the breakpoint again.
+                if let Some(bp) = self.bps.get(&addr) {
+                    mem.load(bp.phys, &[INT3])?;
+                }
+                self.step_over = None;
+                vcpu.set_single_step(single_step)?;
+
+                if !single_step {
+                    continue;
+                }
+            }
+
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hw_breakpoint_dr7() {
+        assert_eq!(HwBreakpoint::Exec(0x1000).dr7(0), 0b10);
+        assert_eq!(
+            HwBreakpoint::Write(0x1000, WatchLen::Four).dr7(1),
+            0b1000 | 0b1101 << 20
+        );
+        assert_eq!(
+            HwBreakpoint::ReadWrite(0x1000, WatchLen::Eight).dr7(3),
+            0b1000_0000 | 0b1011 << 28
+        );
+    }
+
+    #[test]
+    fn hw_breakpoint_alignment() {
+        assert!(HwBreakpoint::Exec(0x1001).is_aligned());
+        assert!(HwBreakpoint::Write(0x1002, WatchLen::Two).is_aligned());
+        assert!(!HwBreakpoint::Write(0x1002, WatchLen::Four).is_aligned());
+        assert!(!HwBreakpoint::ReadWrite(0x1004, WatchLen::Eight).is_aligned());
+    }
+
+    #[test]
+    fn debug_exit_reason() {
+        let exit = DebugExit {
+            exception: 1,
+            pc: 0,
+            dr6: 0xffff0ff0 | DR6_B[2],
+            dr7: 0,
+        };
+        assert_eq!(exit.hw_breakpoint(), Some(2));
+        assert!(!exit.is_single_step());
+
+        let exit = DebugExit {
+            exception: 1,
+            pc: 0,
+            dr6: 0xffff0ff0 | DR6_BS,
+            dr7: 0,
+        };
+        assert_eq!(exit.hw_breakpoint(), None);
+        assert!(exit.is_single_step());
+    }
+
+    #[test]
+    #[ignore = "requires /dev/kvm"]
+    fn sw_breakpoint_guest_int3() {
+        // Real mode guest with a `#BP` handler at `0x200`:
+        //   0x100: int3; mov ax, 1; hlt
+        //   0x200: mov bx, 0x1234; iret
+        let mut mem = UserMem::new(0x1000).unwrap();
+        mem.load(PhysAddr(0xc), &[0x00, 0x02, 0x00, 0x00]).unwrap();
+        mem.load(PhysAddr(0x100), &[0xcc, 0xb8, 0x01, 0x00, 0xf4])
+            .unwrap();
+        mem.load(PhysAddr(0x200), &[0xbb, 0x34, 0x12, 0xcf])
+            .unwrap();
+
+        let mut vm = crate::kvm::Kvm::new().unwrap().create_vm().unwrap();
+        unsafe {
+            vm.set_user_memory_region(0, PhysAddr(0), &mem, crate::vm::MemFlags::NONE)
+                .unwrap()
+        };
+        let mut vcpu = vm.create_vpcu(0).unwrap();
+
+        let mut regs = vcpu.get_regs().unwrap();
+        regs.rip = 0x100;
+        regs.rsp = 0x1000;
+        regs.rflags = 0x2;
+        vcpu.set_regs(regs).unwrap();
+
+        let mut sregs = vcpu.get_sregs().unwrap();
+        sregs.cs.base = 0;
+        sregs.cs.selector = 0;
+        vcpu.set_sregs(sregs).unwrap();
+
+        // Breakpoint at a different address, the guest `int3` must not be reported.
+        let mut bps = SwBreakpoints::new();
+        bps.insert(&mut mem, 0x300, PhysAddr(0x300)).unwrap();
+
+        assert!(matches!(bps.run(&mut vcpu, &mut mem), Ok(KvmExit::Halt)));
+
+        let regs = vcpu.get_regs().unwrap();
+        assert_eq!(regs.rax & 0xffff, 1);
+        assert_eq!(regs.rbx & 0xffff, 0x1234);
+    }
+
+    #[test]
+    fn sw_breakpoint_patch() {
+        let mut mem = UserMem::with_init(0x1000, &[0x90, 0x90, 0xf4]).unwrap();
+        let mut bps = SwBreakpoints::new();
+
+        bps.insert(&mut mem, 0x7001, PhysAddr(0x1)).unwrap();
+        assert!(bps.contains(0x7001));
+        assert_eq!(bps.orig_byte(PhysAddr(0x1)), Some(0x90));
+        assert_eq!(&mem.as_ref()[..3], &[0x90, INT3, 0xf4]);
+
+        bps.remove(&mut mem, 0x7001).unwrap();
+        assert!(!bps.contains(0x7001));
+        assert_eq!(&mem.as_ref()[..3], &[0x90, 0x90, 0xf4]);
+    }
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Crate error type.
+
+use std::fmt;
+use std::io;
+
+/// Result type returned by the public APIs of this crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors returned by the public APIs of this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// The KVM ioctl with the given name failed.
+    Ioctl(&'static str, io::Error),
+    /// A system call other than a KVM ioctl failed (eg `open` or `mmap`).
+    Io(io::Error),
+    /// The KVM API version reported by the kernel does not match the `KVM_API_VERSION` this crate
+    /// was built against.
+    ApiVersion(i32),
+    /// The KVM capability with the given name is not supported.
+    UnsupportedCap(&'static str),
+    /// The exit reason reported by `KVM_RUN` could not be decoded.
+    UnknownExit(u32),
+    /// Guest memory access of `len` bytes at `addr` is outside of the memory region of `size`
+    /// bytes.
+    GuestMemOutOfBounds { addr: u64, len: usize, size: usize },
+    /// The memory slot exceeds the number of slots supported by KVM (`KVM_CAP_NR_MEMSLOTS`).
+    MemSlotLimit(u32),
+    /// The guest physical range of the first memory slot overlaps with the second memory slot.
+    MemSlotOverlap(u32, u32),
+    /// The memory slot is not in use.
+    MemSlotUnused(u32),
+    /// The bus range starting at the first address overlaps with the device registered at the
+    /// second address.
+    BusOverlap(u64, u64),
+    /// An argument passed to an API is not valid in the current state.
+    InvalidArgument(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Ioctl(name, err) => write!(f, "ioctl {} failed: {}", name, err),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::ApiVersion(version) => write!(f, "unsupported KVM API version {}", version),
+            Error::UnsupportedCap(cap) => write!(f, "capability {} not supported", cap),
+            Error::UnknownExit(reason) => write!(f, "unknown exit reason {}", reason),
+            Error::GuestMemOutOfBounds { addr, len, size } => write!(
+                f,
+                "guest memory access [{:#x}:{:#x}) out of bounds (size {:#x})",
+                addr,
+                addr.wrapping_add(*len as u64),
+                size
+            ),
+            Error::MemSlotLimit(slot) => {
+                write!(f, "memory slot {} exceeds KVM_CAP_NR_MEMSLOTS", slot)
+            }
+            Error::MemSlotOverlap(slot, other) => write!(
+                f,
+                "memory slot {} overlaps with memory slot {}",
+                slot, other
+            ),
+            Error::MemSlotUnused(slot) => write!(f, "memory slot {} not in use", slot),
+            Error::BusOverlap(base, other) => write!(
+                f,
+                "bus range at {:#x} overlaps with device at {:#x}",
+                base, other
+            ),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Ioctl(_, err) | Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
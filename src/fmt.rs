@@ -2,9 +2,10 @@
 //
 // Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
 
+use std::convert::TryInto;
 use std::fmt;
 
-use crate::kvm_sys::{kvm_dtable, kvm_regs, kvm_segment, kvm_sregs};
+use crate::kvm_sys::{kvm_dtable, kvm_fpu, kvm_regs, kvm_segment, kvm_sregs, kvm_xcrs, kvm_xsave};
 use crate::x86_64::*;
 
 impl fmt::Display for kvm_regs {
@@ -132,3 +133,76 @@ impl fmt::Display for kvm_sregs {
         )
     }
 }
+
+impl fmt::Display for kvm_fpu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fcw: {:#06x} fsw: {:#06x} ftw: {:#04x} fop: {:#06x} fip: {:#018x} fdp: {:#018x}\n\
+             mxcsr: {:#010x}",
+            self.fcw, self.fsw, self.ftwx, self.last_opcode, self.last_ip, self.last_dp, self.mxcsr,
+        )?;
+
+        for (i, st) in self.fpr.iter().enumerate() {
+            // Only the lower 80 bit hold the x87 register.
+            let st = u128::from_le_bytes(*st) & ((1 << 80) - 1);
+            write!(f, "\nst{}   : {:#022x}", i, st)?;
+        }
+
+        for (i, xmm) in self.xmm.iter().enumerate() {
+            write!(f, "\nxmm{:<2}: {:#034x}", i, u128::from_le_bytes(*xmm))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for kvm_xsave {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<u8> = self.region.iter().flat_map(|r| r.to_le_bytes()).collect();
+        let u16_at = |off: usize| u16::from_le_bytes(bytes[off..off + 2].try_into().unwrap());
+        let u32_at = |off: usize| u32::from_le_bytes(bytes[off..off + 4].try_into().unwrap());
+        let u64_at = |off: usize| u64::from_le_bytes(bytes[off..off + 8].try_into().unwrap());
+        let u128_at = |off: usize| u128::from_le_bytes(bytes[off..off + 16].try_into().unwrap());
+
+        let xstate_bv = u64_at(XSAVE_HEADER_OFFSET);
+        write!(
+            f,
+            "xstate_bv: {:#018x} xcomp_bv: {:#018x}\n\
+             fcw: {:#06x} fsw: {:#06x} ftw: {:#04x} mxcsr: {:#010x}",
+            xstate_bv,
+            u64_at(XSAVE_HEADER_OFFSET + 8),
+            u16_at(0),
+            u16_at(2),
+            bytes[4],
+            u32_at(24),
+        )?;
+
+        // Upper halves of the `ymm` registers, in init state if the `AVX` component is cleared.
+        let avx = xstate_bv & XCR0_AVX != 0;
+        for i in 0..16 {
+            let lo = u128_at(XSAVE_XMM_OFFSET + i * 16);
+            let hi = if avx {
+                u128_at(XSAVE_YMM_HI_OFFSET + i * 16)
+            } else {
+                0
+            };
+            write!(f, "\nymm{:<2}: {:#034x}_{:032x}", i, hi, lo)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for kvm_xcrs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nr = (self.nr_xcrs as usize).min(self.xcrs.len());
+        for (i, xcr) in self.xcrs[..nr].iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "xcr{}: {:#018x}", xcr.xcr, xcr.value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,697 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Stub for the [GDB remote serial protocol][gdb-rsp] to debug guests with `gdb`.
+//!
+//! The stub waits for `gdb` to connect and then runs the guest VCPU with [`GdbStub::run`] as
+//! replacement for [`Vcpu::run`](crate::vcpu::Vcpu::run). Exits which are not caused by the
+//! debugger are returned to the caller as usual.
+//!
+//! Supported are reading and writing registers and memory, continue, single step, software
+//! breakpoints, hardware breakpoints and write/access watchpoints. Guest memory is accessed by
+//! guest linear (virtual) addresses, which are translated through the guest page tables. The
+//! [`UserMem`](crate::UserMem) passed to [`GdbStub::run`] is expected to be mapped at guest
+//! physical address `0`.
+//!
+//! Interrupting a running guest (`Ctrl-C` in `gdb`) is not supported.
+//!
+//! [gdb-rsp]: https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use crate::debug::{HwBreakpoint, SwBreakpoints, WatchLen, NR_HW_BREAKPOINTS};
+use crate::vcpu::{KvmExit, Vcpu};
+use crate::x86_64::paging::PAGE_SIZE_4K;
+use crate::{Error, PhysAddr, Result, UserMem};
+
+/// Number of bytes of the general purpose registers in the `g` packet (rax-r15, rip).
+const GPR_BYTES: usize = 17 * 8;
+/// Number of bytes of all registers in the `g` packet (GPRs, eflags, cs, ss, ds, es, fs, gs).
+const REG_BYTES: usize = GPR_BYTES + 7 * 4;
+/// Maximum packet size advertised in `qSupported`, in characters of the packet data.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Action requested by the debugger when leaving the command loop.
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+    Kill,
+}
+
+/// GDB remote serial protocol stub for a single VCPU connected through `conn`.
+pub struct GdbStub<S: Read + Write> {
+    /// Connection to `gdb`, `None` once the debugger detached.
+    conn: Option<S>,
+    sw_breakpoints: SwBreakpoints,
+    /// VCPU is stopped and waits for commands from the debugger.
+    stopped: bool,
+    /// Debugger requested a single step.
+    step: bool,
+}
+
+impl GdbStub<TcpStream> {
+    /// Listen on the TCP address `addr` and wait for `gdb` to connect with `target remote
+    /// <addr>`.
+    pub fn listen_tcp<A: ToSocketAddrs>(addr: A) -> Result<GdbStub<TcpStream>> {
+        let (conn, _) = TcpListener::bind(addr)?.accept()?;
+        conn.set_nodelay(true)?;
+        Ok(GdbStub::new(conn))
+    }
+}
+
+impl GdbStub<UnixStream> {
+    /// Listen on the Unix socket `path` and wait for `gdb` to connect with `target remote
+    /// <path>`.
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> Result<GdbStub<UnixStream>> {
+        let (conn, _) = UnixListener::bind(path)?.accept()?;
+        Ok(GdbStub::new(conn))
+    }
+}
+
+impl<S: Read + Write> GdbStub<S> {
+    /// Create a stub talking to `gdb` over the connection `conn`.
+    ///
+    /// The guest starts in the stopped state and waits for commands from the debugger.
+    pub fn new(conn: S) -> GdbStub<S> {
+        GdbStub {
+            conn: Some(conn),
+            sw_breakpoints: SwBreakpoints::new(),
+            stopped: true,
+            step: false,
+        }
+    }
+
+    /// Run the guest VCPU under control of the debugger until it exits with an exit reason not
+    /// handled by the debugger.
+    ///
+    /// When `gdb` kills the guest, the stub detaches and reports
+    /// [`KvmExit::Shutdown`](crate::vcpu::KvmExit::Shutdown). Once the debugger detached, this
+    /// is the same as [`Vcpu::run`](crate::vcpu::Vcpu::run).
+    pub fn run<'cpu>(&mut self, vcpu: &'cpu mut Vcpu, mem: &mut UserMem) -> Result<KvmExit<'cpu>> {
+        loop {
+            if self.conn.is_none() {
+                return vcpu.run();
+            }
+
+            if self.stopped {
+                match self.serve(vcpu, mem)? {
+                    Resume::Continue => self.step = false,
+                    Resume::Step => self.step = true,
+                    Resume::Detach => continue,
+                    Resume::Kill => return Ok(KvmExit::Shutdown),
+                }
+                self.stopped = false;
+            }
+
+            self.resume(vcpu, mem)?;
+
+            if let Some(debug) = vcpu.debug_exit() {
+                let reply = match debug
+                    .hw_breakpoint()
+                    .and_then(|idx| vcpu.hw_breakpoints()[idx])
+                {
+                    Some(HwBreakpoint::Write(addr, _)) => format!("T05watch:{:x};", addr),
+                    Some(HwBreakpoint::ReadWrite(addr, _)) => format!("T05awatch:{:x};", addr),
+                    _ => "S05".into(),
+                };
+                self.send(reply.as_bytes())?;
+                self.stopped = true;
+                continue;
+            }
+
+            return vcpu.exit();
+        }
+    }
+
+    /// Resume the guest VCPU and step over an exec hardware breakpoint at the current
+    /// instruction.
+    fn resume(&mut self, vcpu: &mut Vcpu, mem: &mut UserMem) -> Result<()> {
+        if vcpu.single_step() != self.step {
+            vcpu.set_single_step(self.step)?;
+        }
+
+        let pc = vcpu.get_regs()?.rip.wrapping_add(vcpu.get_sregs()?.cs.base);
+        let hw_bp = vcpu
+            .hw_breakpoints()
+            .iter()
+            .position(|bp| *bp == Some(HwBreakpoint::Exec(pc)));
+
+        if let Some(idx) = hw_bp {
+            // An exec hardware breakpoint fires again on resume, hence temporarily remove it and
+            // single step the instruction.
+            vcpu.set_hw_breakpoint(idx, None)?;
+            vcpu.set_single_step(true)?;
+            let ret = self.sw_breakpoints.enter(vcpu, mem);
+            vcpu.set_hw_breakpoint(idx, Some(HwBreakpoint::Exec(pc)))?;
+            vcpu.set_single_step(self.step)?;
+            ret?;
+
+            let stepped = matches!(vcpu.debug_exit(), Some(debug) if debug.is_single_step());
+            if self.step || !stepped {
+                return Ok(());
+            }
+        }
+
+        self.sw_breakpoints.enter(vcpu, mem)
+    }
+
+    /// Process debugger commands until the debugger resumes the guest.
+    fn serve(&mut self, vcpu: &mut Vcpu, mem: &mut UserMem) -> Result<Resume> {
+        loop {
+            let pkt = match self.recv()? {
+                Some(pkt) => pkt,
+                None => {
+                    // Connection closed by the debugger.
+                    self.detach(vcpu, mem)?;
+                    return Ok(Resume::Detach);
+                }
+            };
+
+            let reply = match pkt.first() {
+                Some(b'?') => "S05".into(),
+                Some(b'g') => self.read_regs(vcpu)?,
+                Some(b'G') => self.write_regs(vcpu, &pkt[1..])?,
+                Some(b'm') => self.read_mem(vcpu, mem, &pkt[1..])?,
+                Some(b'M') => self.write_mem(vcpu, mem, &pkt[1..])?,
+                Some(b'Z') => self.insert_breakpoint(vcpu, mem, &pkt[1..])?,
+                Some(b'z') => self.remove_breakpoint(vcpu, mem, &pkt[1..])?,
+                Some(b'c') | Some(b's') => {
+                    if pkt.len() > 1 {
+                        match parse_hex(&pkt[1..]) {
+                            Some(addr) => {
+                                let mut regs = vcpu.get_regs()?;
+                                regs.rip = addr;
+                                vcpu.set_regs(regs)?;
+                            }
+                            None => {
+                                self.send(b"E01")?;
+                                continue;
+                            }
+                        }
+                    }
+
+                    return Ok(if pkt[0] == b'c' {
+                        Resume::Continue
+                    } else {
+                        Resume::Step
+                    });
+                }
+                Some(b'D') => {
+                    self.send(b"OK")?;
+                    self.detach(vcpu, mem)?;
+                    return Ok(Resume::Detach);
+                }
+                Some(b'k') => {
+                    self.detach(vcpu, mem)?;
+                    return Ok(Resume::Kill);
+                }
+                Some(b'H') => "OK".into(),
+                _ if pkt.starts_with(b"qSupported") => format!("PacketSize={:x}", PACKET_SIZE),
+                _ if pkt == b"qAttached" => "1".into(),
+                // Empty reply for unsupported commands.
+                _ => String::new(),
+            };
+
+            self.send(reply.as_bytes())?;
+        }
+    }
+
+    /// Remove all breakpoints, disable guest debugging and close the connection.
+    fn detach(&mut self, vcpu: &mut Vcpu, mem: &mut UserMem) -> Result<()> {
+        self.conn = None;
+        self.sw_breakpoints.clear(mem)?;
+        for idx in 0..NR_HW_BREAKPOINTS {
+            vcpu.set_hw_breakpoint(idx, None)?;
+        }
+        vcpu.set_single_step(false)?;
+        vcpu.set_sw_breakpoints(false)
+    }
+
+    /// `g`: Read the registers in the `i386:x86-64` register layout.
+    fn read_regs(&self, vcpu: &Vcpu) -> Result<String> {
+        let regs = vcpu.get_regs()?;
+        let sregs = vcpu.get_sregs()?;
+
+        let gprs = [
+            regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+            regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+        ];
+        let segs = [
+            &sregs.cs, &sregs.ss, &sregs.ds, &sregs.es, &sregs.fs, &sregs.gs,
+        ];
+
+        let mut data = Vec::with_capacity(REG_BYTES);
+        gprs.iter().for_each(|r| data.extend(&r.to_le_bytes()));
+        data.extend(&(regs.rflags as u32).to_le_bytes());
+        segs.iter()
+            .for_each(|s| data.extend(&u32::from(s.selector).to_le_bytes()));
+
+        Ok(hex_encode(&data))
+    }
+
+    /// `G`: Write the registers in the `i386:x86-64` register layout.
+    ///
+    /// Segment registers are read-only, as they can not be loaded without their descriptors.
+    fn write_regs(&self, vcpu: &Vcpu, args: &[u8]) -> Result<String> {
+        let data = match hex_decode(args) {
+            Some(data) if data.len() >= REG_BYTES => data,
+            _ => return Ok("E01".into()),
+        };
+
+        let mut gprs = data[..GPR_BYTES]
+            .chunks_exact(8)
+            .map(|r| u64::from_le_bytes([r[0], r[1], r[2], r[3], r[4], r[5], r[6], r[7]]));
+        let mut next = || gprs.next().unwrap_or_default();
+
+        let mut regs = vcpu.get_regs()?;
+        regs.rax = next();
+        regs.rbx = next();
+        regs.rcx = next();
+        regs.rdx = next();
+        regs.rsi = next();
+        regs.rdi = next();
+        regs.rbp = next();
+        regs.rsp = next();
+        regs.r8 = next();
+        regs.r9 = next();
+        regs.r10 = next();
+        regs.r11 = next();
+        regs.r12 = next();
+        regs.r13 = next();
+        regs.r14 = next();
+        regs.r15 = next();
+        regs.rip = next();
+        let f = &data[GPR_BYTES..];
+        regs.rflags = u64::from(u32::from_le_bytes([f[0], f[1], f[2], f[3]]));
+        vcpu.set_regs(regs)?;
+
+        Ok("OK".into())
+    }
+
+    /// `m addr,len`: Read guest memory, software breakpoints are hidden from the debugger.
+    fn read_mem(&self, vcpu: &Vcpu, mem: &UserMem, args: &[u8]) -> Result<String> {
+        let (addr, len) = match parse_addr_len(args) {
+            Some(args) => args,
+            None => return Ok("E01".into()),
+        };
+        // Each byte is sent as two hex characters, the reply must fit into a packet.
+        let len = len.min(PACKET_SIZE / 2);
+
+        let mut data = Vec::with_capacity(len);
+        let mut addr = addr;
+        while data.len() < len {
+            let phys = match vcpu.translate(addr)? {
+                Some(phys) => phys,
+                None => break,
+            };
+
+            let mut buf = vec![0; page_chunk(addr, len - data.len())];
+            if mem.read(phys, &mut buf).is_err() {
+                break;
+            }
+
+            for (off, b) in buf.iter_mut().enumerate() {
+                if let Some(orig) = self.sw_breakpoints.orig_byte(PhysAddr(phys.0 + off as u64)) {
+                    *b = orig;
+                }
+            }
+
+            data.extend(&buf);
+            addr = match addr.checked_add(buf.len() as u64) {
+                Some(addr) => addr,
+                None => break,
+            };
+        }
+
+        // Partial reads are allowed, only fail if nothing could be read.
+        if data.is_empty() && len > 0 {
+            return Ok("E14".into());
+        }
+        Ok(hex_encode(&data))
+    }
+
+    /// `M addr,len:XX..`: Write guest memory.
+    fn write_mem(&self, vcpu: &Vcpu, mem: &mut UserMem, args: &[u8]) -> Result<String> {
+        let sep = args.iter().position(|&b| b == b':').unwrap_or(args.len());
+        let data = match (
+            parse_addr_len(&args[..sep]),
+            args.get(sep + 1..).and_then(hex_decode),
+        ) {
+            (Some((addr, len)), Some(data)) if data.len() == len => (addr, data),
+            _ => return Ok("E01".into()),
+        };
+        let (mut addr, data) = data;
+
+        // Translate all pages before writing, to not partially write the data.
+        let mut chunks = Vec::new();
+        let mut off = 0;
+        while off < data.len() {
+            let phys = match vcpu.translate(addr)? {
+                Some(phys) => phys,
+                None => return Ok("E14".into()),
+            };
+            let len = page_chunk(addr, data.len() - off);
+            chunks.push((phys, &data[off..off + len]));
+            off += len;
+            addr = match addr.checked_add(len as u64) {
+                Some(addr) => addr,
+                // Only the last chunk may end at the top of the address space.
+                None if off == data.len() => break,
+                None => return Ok("E14".into()),
+            };
+        }
+
+        for (phys, chunk) in chunks {
+            if mem.load(phys, chunk).is_err() {
+                return Ok("E14".into());
+            }
+        }
+        Ok("OK".into())
+    }
+
+    /// `Z type,addr,kind`: Insert a breakpoint or watchpoint.
+    fn insert_breakpoint(
+        &mut self,
+        vcpu: &mut Vcpu,
+        mem: &mut UserMem,
+        args: &[u8],
+    ) -> Result<String> {
+        let (type_, addr, kind) = match parse_breakpoint(args) {
+            Some(args) => args,
+            None => return Ok("E01".into()),
+        };
+
+        if type_ == 0 {
+            let phys = match vcpu.translate(addr)? {
+                Some(phys) => phys,
+                None => return Ok("E14".into()),
+            };
+            return Ok(match self.sw_breakpoints.insert(mem, addr, phys) {
+                Ok(()) => "OK".into(),
+                Err(_) => "E14".into(),
+            });
+        }
+
+        let bp = match hw_breakpoint(type_, addr, kind) {
+            Some(Some(bp)) => bp,
+            Some(None) => return Ok("E22".into()),
+            None => return Ok(String::new()),
+        };
+
+        if vcpu.hw_breakpoints().contains(&Some(bp)) {
+            return Ok("OK".into());
+        }
+        let idx = match vcpu.hw_breakpoints().iter().position(Option::is_none) {
+            Some(idx) => idx,
+            None => return Ok("E28".into()),
+        };
+        Ok(match vcpu.set_hw_breakpoint(idx, Some(bp)) {
+            Ok(()) => "OK".into(),
+            Err(Error::InvalidArgument(_)) => "E22".into(),
+            Err(err) => return Err(err),
+        })
+    }
+
+    /// `z type,addr,kind`: Remove a breakpoint or watchpoint.
+    fn remove_breakpoint(
+        &mut self,
+        vcpu: &mut Vcpu,
+        mem: &mut UserMem,
+        args: &[u8],
+    ) -> Result<String> {
+        let (type_, addr, kind) = match parse_breakpoint(args) {
+            Some(args) => args,
+            None => return Ok("E01".into()),
+        };
+
+        if type_ == 0 {
+            self.sw_breakpoints.remove(mem, addr)?;
+            return Ok("OK".into());
+        }
+
+        let bp = match hw_breakpoint(type_, addr, kind) {
+            Some(Some(bp)) => bp,
+            Some(None) => return Ok("E22".into()),
+            None => return Ok(String::new()),
+        };
+
+        if let Some(idx) = vcpu.hw_breakpoints().iter().position(|b| *b == Some(bp)) {
+            vcpu.set_hw_breakpoint(idx, None)?;
+        }
+        Ok("OK".into())
+    }
+
+    /// Receive the next packet from the debugger and acknowledge it.
+    ///
+    /// Returns `None` if the connection is closed.
+    fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            // Skip everything until the packet start, eg acknowledgements or interrupts.
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+
+            let mut pkt = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => pkt.push(b),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut csum = [0u8; 2];
+            for c in csum.iter_mut() {
+                *c = match self.read_byte()? {
+                    Some(b) => b,
+                    None => return Ok(None),
+                };
+            }
+
+            if parse_hex(&csum) == Some(u64::from(checksum(&pkt))) {
+                self.write(b"+")?;
+                return Ok(Some(pkt));
+            }
+            self.write(b"-")?;
+        }
+    }
+
+    /// Send the packet `data` to the debugger and wait for the acknowledgement.
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        let mut pkt = Vec::with_capacity(data.len() + 4);
+        pkt.push(b'$');
+        pkt.extend(data);
+        pkt.extend(format!("#{:02x}", checksum(data)).as_bytes());
+
+        loop {
+            self.write(&pkt)?;
+
+            loop {
+                match self.read_byte()? {
+                    Some(b'+') | None => return Ok(()),
+                    // Retransmit.
+                    Some(b'-') => break,
+                    Some(_) => continue,
+                }
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let conn = match self.conn.as_mut() {
+            Some(conn) => conn,
+            None => return Ok(None),
+        };
+
+        let mut b = [0u8; 1];
+        loop {
+            match conn.read(&mut b) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(b[0])),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(conn) = self.conn.as_mut() {
+            conn.write_all(data)?;
+            conn.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Map a `Z/z` packet breakpoint type to a hardware breakpoint.
+///
+/// Returns `None` for unsupported types and `Some(None)` for invalid watchpoint lengths.
+fn hw_breakpoint(type_: u64, addr: u64, kind: u64) -> Option<Option<HwBreakpoint>> {
+    let len = match kind {
+        1 => Some(WatchLen::One),
+        2 => Some(WatchLen::Two),
+        4 => Some(WatchLen::Four),
+        8 => Some(WatchLen::Eight),
+        _ => None,
+    };
+
+    match type_ {
+        1 => Some(Some(HwBreakpoint::Exec(addr))),
+        2 => Some(len.map(|len| HwBreakpoint::Write(addr, len))),
+        // Read-only watchpoints are not supported by x86.
+        4 => Some(len.map(|len| HwBreakpoint::ReadWrite(addr, len))),
+        _ => None,
+    }
+}
+
+/// Number of bytes of an access of `len` bytes at `addr` until the next page boundary.
+fn page_chunk(addr: u64, len: usize) -> usize {
+    len.min((PAGE_SIZE_4K - (addr & (PAGE_SIZE_4K - 1))) as usize)
+}
+
+/// Modulo 256 sum of the packet data.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn parse_hex(data: &[u8]) -> Option<u64> {
+    let s = std::str::from_utf8(data).ok()?;
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// Parse `addr,len`.
+fn parse_addr_len(data: &[u8]) -> Option<(u64, usize)> {
+    let mut args = data.split(|&b| b == b',');
+    let addr = parse_hex(args.next()?)?;
+    let len = parse_hex(args.next()?)?;
+    Some((addr, len as usize))
+}
+
+/// Parse `type,addr,kind`.
+fn parse_breakpoint(data: &[u8]) -> Option<(u64, u64, u64)> {
+    let mut args = data.split(|&b| b == b',');
+    let type_ = parse_hex(args.next()?)?;
+    let addr = parse_hex(args.next()?)?;
+    // Ignore optional conditions after `;`.
+    let kind = args.next()?.split(|&b| b == b';').next()?;
+    Some((type_, addr, parse_hex(kind)?))
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    let mut s = String::with_capacity(data.len() * 2);
+    data.iter().for_each(|b| write!(s, "{:02x}", b).unwrap());
+    s
+}
+
+fn hex_decode(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    data.chunks_exact(2)
+        .map(parse_hex)
+        .map(|b| b.map(|b| b as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Fake connection replaying `input` and recording everything written to `output`.
+    struct Conn {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Conn {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Conn {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn stub(input: &[u8]) -> GdbStub<Conn> {
+        GdbStub::new(Conn {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        })
+    }
+
+    fn output(stub: &GdbStub<Conn>) -> &[u8] {
+        &stub.conn.as_ref().unwrap().output
+    }
+
+    #[test]
+    fn packet_checksum() {
+        assert_eq!(checksum(b""), 0x00);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"qSupported"), 0x37);
+    }
+
+    #[test]
+    fn packet_recv() {
+        let mut gdb = stub(b"+$g#00$g#67$m10,4#2e");
+        assert_eq!(gdb.recv().unwrap(), Some(b"g".to_vec()));
+        assert_eq!(gdb.recv().unwrap(), Some(b"m10,4".to_vec()));
+        assert_eq!(gdb.recv().unwrap(), None);
+        assert_eq!(output(&gdb), b"-++");
+    }
+
+    #[test]
+    fn packet_send() {
+        let mut gdb = stub(b"-+");
+        gdb.send(b"OK").unwrap();
+        assert_eq!(output(&gdb), b"$OK#9a$OK#9a");
+    }
+
+    #[test]
+    fn packet_args() {
+        assert_eq!(parse_addr_len(b"7ff0,10"), Some((0x7ff0, 0x10)));
+        assert_eq!(parse_addr_len(b"7ff0"), None);
+        assert_eq!(parse_breakpoint(b"1,4000,1"), Some((1, 0x4000, 1)));
+        assert_eq!(parse_breakpoint(b"0,4000,1;X2,aa"), Some((0, 0x4000, 1)));
+        assert_eq!(hex_decode(b"00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(hex_decode(b"0"), None);
+        assert_eq!(hex_encode(&[0x00, 0xff, 0x10]), "00ff10");
+    }
+
+    #[test]
+    fn packet_hw_breakpoint() {
+        assert_eq!(
+            hw_breakpoint(1, 0x10, 1),
+            Some(Some(HwBreakpoint::Exec(0x10)))
+        );
+        assert_eq!(
+            hw_breakpoint(2, 0x10, 4),
+            Some(Some(HwBreakpoint::Write(0x10, WatchLen::Four)))
+        );
+        assert_eq!(hw_breakpoint(4, 0x10, 3), Some(None));
+        assert_eq!(hw_breakpoint(3, 0x10, 4), None);
+    }
+
+    #[test]
+    fn page_chunks() {
+        assert_eq!(page_chunk(0x1ff0, 0x100), 0x10);
+        assert_eq!(page_chunk(0x1000, 0x100), 0x100);
+    }
+}
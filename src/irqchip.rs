@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! In-kernel interrupt controller.
+//!
+//! The in-kernel irqchip created with [`Vm::create_irqchip`](crate::vm::Vm::create_irqchip)
+//! consists of two cascaded 8259 PICs, an IOAPIC and a local APIC per VCPU. Host devices raise
+//! interrupts with [`Vm::set_irq_line`](crate::vm::Vm::set_irq_line), IRQ lines `0-15` are
+//! connected to the PICs and the IOAPIC, lines `16-23` only to the IOAPIC.
+
+use std::convert::TryInto;
+use std::fs;
+
+use crate::kvm_sys::{
+    self, kvm_ioapic_state, kvm_irqchip, kvm_irqchip_union, kvm_lapic_state, kvm_pic_state,
+};
+use crate::Result;
+
+/// Number of IRQ lines (IOAPIC pins).
+pub const NR_IRQS: u32 = kvm_sys::KVM_IOAPIC_NUM_PINS as u32;
+
+/// Interrupt controller of the in-kernel irqchip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqChip {
+    /// Master PIC, IRQ lines `0-7`.
+    PicMaster,
+    /// Slave PIC, IRQ lines `8-15`, cascaded on IRQ line `2` of the master PIC.
+    PicSlave,
+    /// IOAPIC, IRQ lines `0-23`.
+    Ioapic,
+}
+
+impl IrqChip {
+    fn id(self) -> u32 {
+        match self {
+            IrqChip::PicMaster => kvm_sys::KVM_IRQCHIP_PIC_MASTER,
+            IrqChip::PicSlave => kvm_sys::KVM_IRQCHIP_PIC_SLAVE,
+            IrqChip::Ioapic => kvm_sys::KVM_IRQCHIP_IOAPIC,
+        }
+    }
+}
+
+/// State of an interrupt controller of the in-kernel irqchip, see
+/// [`Vm::get_irqchip`](crate::vm::Vm::get_irqchip) and
+/// [`Vm::set_irqchip`](crate::vm::Vm::set_irqchip).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqChipState {
+    PicMaster(kvm_pic_state),
+    PicSlave(kvm_pic_state),
+    Ioapic(kvm_ioapic_state),
+}
+
+impl IrqChipState {
+    /// Interrupt controller the state belongs to.
+    pub fn chip(&self) -> IrqChip {
+        match self {
+            IrqChipState::PicMaster(_) => IrqChip::PicMaster,
+            IrqChipState::PicSlave(_) => IrqChip::PicSlave,
+            IrqChipState::Ioapic(_) => IrqChip::Ioapic,
+        }
+    }
+
+    /// Decode the `struct kvm_irqchip` filled by `KVM_GET_IRQCHIP` for `chip`.
+    pub(crate) fn from_kvm(chip: IrqChip, irqchip: &kvm_irqchip) -> IrqChipState {
+        // Safe to use the union fields, as the Kernel filled the state of the requested `chip`.
+        unsafe {
+            match chip {
+                IrqChip::PicMaster => IrqChipState::PicMaster(irqchip.chip.pic),
+                IrqChip::PicSlave => IrqChipState::PicSlave(irqchip.chip.pic),
+                IrqChip::Ioapic => IrqChipState::Ioapic(irqchip.chip.ioapic),
+            }
+        }
+    }
+
+    /// Encode the state into a `struct kvm_irqchip` for `KVM_SET_IRQCHIP`.
+    pub(crate) fn as_kvm(&self) -> kvm_irqchip {
+        let mut irqchip = kvm_irqchip::default();
+        irqchip.chip_id = self.chip().id();
+        match self {
+            IrqChipState::PicMaster(pic) | IrqChipState::PicSlave(pic) => {
+                irqchip.chip = kvm_irqchip_union { pic: *pic };
+            }
+            IrqChipState::Ioapic(ioapic) => {
+                irqchip.chip = kvm_irqchip_union { ioapic: *ioapic };
+            }
+        }
+        irqchip
+    }
+
+    /// Empty `struct kvm_irqchip` for `KVM_GET_IRQCHIP` of `chip`.
+    pub(crate) fn kvm_for(chip: IrqChip) -> kvm_irqchip {
+        let mut irqchip = kvm_irqchip::default();
+        irqchip.chip_id = chip.id();
+        irqchip
+    }
+}
+
+/// Guest physical base address of the local APIC register page (`xAPIC` mode).
+pub const APIC_BASE: u64 = 0xfee0_0000;
+
+/// Local APIC ID register.
+pub const APIC_ID: usize = 0x20;
+/// Local APIC version register.
+pub const APIC_VERSION: usize = 0x30;
+/// Task priority register.
+pub const APIC_TPR: usize = 0x80;
+/// Processor priority register.
+pub const APIC_PPR: usize = 0xa0;
+/// End of interrupt register.
+pub const APIC_EOI: usize = 0xb0;
+/// Logical destination register.
+pub const APIC_LDR: usize = 0xd0;
+/// Destination format register.
+pub const APIC_DFR: usize = 0xe0;
+/// Spurious interrupt vector register.
+pub const APIC_SPIV: usize = 0xf0;
+/// In-service register, 8 registers of 32 bit.
+pub const APIC_ISR: usize = 0x100;
+/// Trigger mode register, 8 registers of 32 bit.
+pub const APIC_TMR: usize = 0x180;
+/// Interrupt request register, 8 registers of 32 bit.
+pub const APIC_IRR: usize = 0x200;
+/// Error status register.
+pub const APIC_ESR: usize = 0x280;
+/// Interrupt command register, bits `31:0`.
+pub const APIC_ICR_LO: usize = 0x300;
+/// Interrupt command register, bits `63:32`.
+pub const APIC_ICR_HI: usize = 0x310;
+/// Timer initial count register.
+pub const APIC_TIMER_INITIAL: usize = 0x380;
+/// Timer current count register.
+pub const APIC_TIMER_CURRENT: usize = 0x390;
+/// Timer divide configuration register.
+pub const APIC_TIMER_DIVIDE: usize = 0x3e0;
+
+/// `APIC_SPIV`: APIC software enable.
+pub const APIC_SPIV_ENABLE: u32 = 1 << 8;
+
+/// Local vector table (LVT) register of the local APIC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LvtReg {
+    Timer,
+    Thermal,
+    Perf,
+    Lint0,
+    Lint1,
+    Error,
+}
+
+impl LvtReg {
+    /// Offset of the register in the APIC register page.
+    pub fn offset(self) -> usize {
+        match self {
+            LvtReg::Timer => 0x320,
+            LvtReg::Thermal => 0x330,
+            LvtReg::Perf => 0x340,
+            LvtReg::Lint0 => 0x350,
+            LvtReg::Lint1 => 0x360,
+            LvtReg::Error => 0x370,
+        }
+    }
+}
+
+/// Mode of the local APIC timer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerMode {
+    OneShot,
+    Periodic,
+    TscDeadline,
+}
+
+/// Local vector table (LVT) entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lvt(pub u32);
+
+impl Lvt {
+    /// LVT entry is masked.
+    pub const MASKED: u32 = 1 << 16;
+
+    /// Interrupt vector.
+    pub fn vector(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Delivery mode, eg `0b000` fixed, `0b100` NMI, `0b111` ExtINT.
+    pub fn delivery_mode(self) -> u8 {
+        (self.0 >> 8 & 0b111) as u8
+    }
+
+    /// Check if the entry is masked.
+    pub fn is_masked(self) -> bool {
+        self.0 & Lvt::MASKED != 0
+    }
+
+    /// Timer mode, only meaningful for [`LvtReg::Timer`].
+    pub fn timer_mode(self) -> TimerMode {
+        match self.0 >> 17 & 0b11 {
+            0b01 => TimerMode::Periodic,
+            0b10 => TimerMode::TscDeadline,
+            _ => TimerMode::OneShot,
+        }
+    }
+}
+
+/// Typed view of the local APIC register page, see
+/// [`Vcpu::get_lapic`](crate::vcpu::Vcpu::get_lapic) and
+/// [`Vcpu::set_lapic`](crate::vcpu::Vcpu::set_lapic).
+///
+/// Registers are `32` bit wide and `16` byte aligned, the register offsets are defined by the
+/// `APIC_*` constants in this module.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Lapic {
+    state: kvm_lapic_state,
+}
+
+impl Lapic {
+    pub(crate) fn new(state: kvm_lapic_state) -> Lapic {
+        Lapic { state }
+    }
+
+    pub(crate) fn state(&self) -> &kvm_lapic_state {
+        &self.state
+    }
+
+    /// Read the register at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is outside of the register page.
+    pub fn reg(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.state.regs[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Write the register at `offset`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is outside of the register page.
+    pub fn set_reg(&mut self, offset: usize, val: u32) {
+        self.state.regs[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    /// Local APIC ID (`xAPIC` format, bits `31:24` of [`APIC_ID`]).
+    pub fn id(&self) -> u8 {
+        (self.reg(APIC_ID) >> 24) as u8
+    }
+
+    pub fn set_id(&mut self, id: u8) {
+        self.set_reg(APIC_ID, u32::from(id) << 24);
+    }
+
+    /// Task priority.
+    pub fn tpr(&self) -> u8 {
+        self.reg(APIC_TPR) as u8
+    }
+
+    pub fn set_tpr(&mut self, tpr: u8) {
+        self.set_reg(APIC_TPR, u32::from(tpr));
+    }
+
+    /// Check if the APIC is software enabled in [`APIC_SPIV`].
+    pub fn is_enabled(&self) -> bool {
+        self.reg(APIC_SPIV) & APIC_SPIV_ENABLE != 0
+    }
+
+    /// Software enable or disable the APIC and set the spurious interrupt `vector`.
+    pub fn set_enabled(&mut self, enable: bool, vector: u8) {
+        let spiv = if enable { APIC_SPIV_ENABLE } else { 0 };
+        self.set_reg(APIC_SPIV, spiv | u32::from(vector));
+    }
+
+    /// Read the LVT entry `lvt`.
+    pub fn lvt(&self, lvt: LvtReg) -> Lvt {
+        Lvt(self.reg(lvt.offset()))
+    }
+
+    pub fn set_lvt(&mut self, lvt: LvtReg, entry: Lvt) {
+        self.set_reg(lvt.offset(), entry.0);
+    }
+
+    /// Timer initial count.
+    pub fn timer_initial_count(&self) -> u32 {
+        self.reg(APIC_TIMER_INITIAL)
+    }
+
+    /// Timer current count.
+    pub fn timer_current_count(&self) -> u32 {
+        self.reg(APIC_TIMER_CURRENT)
+    }
+
+    /// Timer divide configuration.
+    pub fn timer_divide(&self) -> u32 {
+        self.reg(APIC_TIMER_DIVIDE)
+    }
+
+    /// Check if interrupt `vector` is requested (`IRR`).
+    pub fn is_requested(&self, vector: u8) -> bool {
+        self.vector_bit(APIC_IRR, vector)
+    }
+
+    /// Check if interrupt `vector` is in service (`ISR`).
+    pub fn is_in_service(&self, vector: u8) -> bool {
+        self.vector_bit(APIC_ISR, vector)
+    }
+
+    /// Get the bit of `vector` in the 256 bit register spread over 8 registers at `base`.
+    fn vector_bit(&self, base: usize, vector: u8) -> bool {
+        let vector = usize::from(vector);
+        self.reg(base + vector / 32 * 0x10) & (1 << (vector % 32)) != 0
+    }
+}
+
+/// Set the level of the IRQ line `irq` of the in-kernel irqchip of the VM `vm` with the
+/// `KVM_IRQ_LINE` ioctl.
+pub(crate) fn set_irq_line(vm: &fs::File, irq: u32, level: bool) -> Result<()> {
+    let irq_level = kvm_sys::kvm_irq_level {
+        irq,
+        level: level as u32,
+    };
+
+    ioctl!(vm, KVM_IRQ_LINE, &irq_level as *const _ as u64).map(|_| ())
+}
+
+/// Single IRQ line of the in-kernel irqchip, which can be handed to a device, obtained with
+/// [`Vm::irq_line`](crate::vm::Vm::irq_line).
+pub struct IrqLine {
+    vm: fs::File,
+    irq: u32,
+}
+
+impl IrqLine {
+    pub(crate) fn new(vm: fs::File, irq: u32) -> IrqLine {
+        IrqLine { vm, irq }
+    }
+
+    /// Get the IRQ number of the line.
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// Set the level of the IRQ line, see [`Vm::set_irq_line`](crate::vm::Vm::set_irq_line).
+    pub fn set_level(&self, level: bool) -> Result<()> {
+        set_irq_line(&self.vm, self.irq, level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn irqchip_state() {
+        let mut ioapic = kvm_ioapic_state::default();
+        ioapic.base_address = 0xfec0_0000;
+        ioapic.redirtbl[4] = 0x24;
+
+        let state = IrqChipState::Ioapic(ioapic);
+        let kvm = state.as_kvm();
+        assert_eq!(kvm.chip_id, kvm_sys::KVM_IRQCHIP_IOAPIC);
+        assert_eq!(IrqChipState::from_kvm(IrqChip::Ioapic, &kvm), state);
+
+        let pic = kvm_pic_state {
+            irq_base: 0x28,
+            ..Default::default()
+        };
+        let kvm = IrqChipState::PicSlave(pic).as_kvm();
+        assert_eq!(kvm.chip_id, kvm_sys::KVM_IRQCHIP_PIC_SLAVE);
+        assert_eq!(
+            IrqChipState::from_kvm(IrqChip::PicSlave, &kvm),
+            IrqChipState::PicSlave(pic)
+        );
+        assert_eq!(IrqChipState::kvm_for(IrqChip::PicMaster).chip_id, 0);
+    }
+
+    #[test]
+    fn lapic_regs() {
+        let mut lapic = Lapic::default();
+        lapic.set_id(3);
+        assert_eq!(lapic.reg(APIC_ID), 0x0300_0000);
+        assert_eq!(&lapic.state().regs[APIC_ID..APIC_ID + 4], &[0, 0, 0, 3]);
+        assert_eq!(lapic.id(), 3);
+
+        lapic.set_enabled(true, 0xff);
+        assert!(lapic.is_enabled());
+        assert_eq!(lapic.reg(APIC_SPIV), 0x1ff);
+
+        lapic.set_lvt(LvtReg::Timer, Lvt(1 << 17 | Lvt::MASKED | 0x30));
+        let timer = lapic.lvt(LvtReg::Timer);
+        assert_eq!(timer.vector(), 0x30);
+        assert!(timer.is_masked());
+        assert_eq!(timer.timer_mode(), TimerMode::Periodic);
+        assert_eq!(lapic.reg(0x320), timer.0);
+
+        // Vector 0x41 is bit 1 of the third IRR register.
+        lapic.set_reg(APIC_IRR + 0x20, 0b10);
+        assert!(lapic.is_requested(0x41));
+        assert!(!lapic.is_requested(0x40));
+        assert!(!lapic.is_in_service(0x41));
+    }
+}
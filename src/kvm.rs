@@ -5,12 +5,13 @@
 //! KVM system ioctls.
 
 use std::fs;
-use std::io;
 use std::os::unix::io::FromRawFd;
 
 use crate::cap::{CapBool, CapInt};
+use crate::cpuid::{self, Cpuid};
+use crate::msr::Msrs;
 use crate::vm::Vm;
-use crate::{ioctl, kvm_sys, libcret};
+use crate::{kvm_sys, libcret, Error, Result};
 
 /// Wrapper for `/dev/kvm` ioctls.
 ///
@@ -24,54 +25,123 @@ pub struct Kvm {
 
 impl Kvm {
     /// Open the `/dev/kvm` device.
-    pub fn new() -> io::Result<Kvm> {
+    ///
+    /// Returns [`Error::ApiVersion`](crate::Error::ApiVersion) if the KVM API version reported by
+    /// the kernel is not supported.
+    pub fn new() -> Result<Kvm> {
         let kvm = libcret(unsafe {
             libc::open("/dev/kvm\0".as_ptr().cast(), libc::O_RDWR | libc::O_CLOEXEC)
         })
         .map(|fd| unsafe { fs::File::from_raw_fd(fd) })?;
 
-        assert_eq!(
-            kvm_sys::KVM_API_VERSION,
-            ioctl(&kvm, kvm_sys::KVM_GET_API_VERSION, 0)?
-        );
+        let version = ioctl!(&kvm, KVM_GET_API_VERSION, 0)?;
+        if version != kvm_sys::KVM_API_VERSION {
+            return Err(Error::ApiVersion(version));
+        }
 
         Ok(Kvm { kvm })
     }
 
-    fn get_vpcu_mmap_size(&self) -> io::Result<usize> {
-        ioctl(&self.kvm, kvm_sys::KVM_GET_VCPU_MMAP_SIZE, 0).map(|size| size as usize)
+    fn get_vpcu_mmap_size(&self) -> Result<usize> {
+        ioctl!(&self.kvm, KVM_GET_VCPU_MMAP_SIZE, 0).map(|size| size as usize)
     }
 
     /// Create a new virtual machine with the [`KVM_CREATE_VM`][kvm-create-vm] ioctl.
     /// Returns a wrapper [`vm::Vm`][crate::vm::Vm] representing the VM.
     ///
     /// [kvm-create-vm]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-create-vm
-    pub fn create_vm(&self) -> io::Result<Vm> {
-        let vm = ioctl(&self.kvm, kvm_sys::KVM_CREATE_VM, 0 /* machine id */)
+    pub fn create_vm(&self) -> Result<Vm> {
+        let vm = ioctl!(&self.kvm, KVM_CREATE_VM, 0 /* machine id */)
             .map(|fd| unsafe { fs::File::from_raw_fd(fd) })?;
 
         let vcpu_mmap_size = self.get_vpcu_mmap_size()?;
+        let nr_memslots = self.check_extenstion_int(CapInt::NrMemslots)?;
 
-        Ok(Vm::new(vm, vcpu_mmap_size))
+        Ok(Vm::new(vm, vcpu_mmap_size, nr_memslots))
     }
 
     /// Check availability of an extension with the [`KVM_CHECK_EXTENSION`][kvm-check-extension]
     /// ioctl.
     ///
     /// [kvm-check-extension]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-check-extension
-    pub fn check_extenstion(&self, cap: CapBool) -> bool {
-        let ret = ioctl(&self.kvm, kvm_sys::KVM_CHECK_EXTENSION, cap.into());
-
-        matches!(ret, Ok(ret) if ret > 0)
+    pub fn check_extenstion(&self, cap: CapBool) -> Result<bool> {
+        ioctl!(&self.kvm, KVM_CHECK_EXTENSION, cap.into()).map(|ret| ret > 0)
     }
 
     /// Check availability of an extension with the [`KVM_CHECK_EXTENSION`][kvm-check-extension]
     /// ioctl.
     ///
     /// [kvm-check-extension]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-check-extension
-    pub fn check_extenstion_int(&self, cap: CapInt) -> i32 {
-        let ret = ioctl(&self.kvm, kvm_sys::KVM_CHECK_EXTENSION, cap.into());
+    pub fn check_extenstion_int(&self, cap: CapInt) -> Result<i32> {
+        ioctl!(&self.kvm, KVM_CHECK_EXTENSION, cap.into())
+    }
+
+    /// Get the list of MSR indices supported by [`Vcpu::get_msrs`](crate::vcpu::Vcpu::get_msrs)
+    /// and [`Vcpu::set_msrs`](crate::vcpu::Vcpu::set_msrs) with the
+    /// [`KVM_GET_MSR_INDEX_LIST`][kvm-get-msr-index-list] ioctl.
+    ///
+    /// [kvm-get-msr-index-list]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-msr-index-list-kvm-get-msr-feature-index-list
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_msr_index_list(&self) -> Result<Vec<u32>> {
+        get_msr_list(|list| ioctl!(&self.kvm, KVM_GET_MSR_INDEX_LIST, list))
+    }
+
+    /// Get the list of feature MSR indices supported by [`Kvm::get_msr_features`] with the
+    /// [`KVM_GET_MSR_FEATURE_INDEX_LIST`][kvm-get-msr-index-list] ioctl.
+    ///
+    /// Requires the [`GetMsrFeatures`](crate::cap::CapBool::GetMsrFeatures) capability.
+    ///
+    /// [kvm-get-msr-index-list]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-msr-index-list-kvm-get-msr-feature-index-list
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_msr_feature_index_list(&self) -> Result<Vec<u32>> {
+        get_msr_list(|list| ioctl!(&self.kvm, KVM_GET_MSR_FEATURE_INDEX_LIST, list))
+    }
+
+    /// Read the feature MSRs of all entries in `msrs` with the [`KVM_GET_MSRS`][kvm-get-msrs]
+    /// ioctl on `/dev/kvm`.
+    ///
+    /// Returns the number of MSRs read, reading stops at the first MSR that can not be read.
+    ///
+    /// Requires the [`GetMsrFeatures`](crate::cap::CapBool::GetMsrFeatures) capability.
+    ///
+    /// [kvm-get-msrs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-msrs
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_msr_features(&self, msrs: &mut Msrs) -> Result<usize> {
+        ioctl!(&self.kvm, KVM_GET_MSRS, msrs.as_mut_ptr() as u64).map(|n| n as usize)
+    }
 
-        ret.unwrap_or(0)
+    /// Get the CPUID leaves supported by KVM and the host with the
+    /// [`KVM_GET_SUPPORTED_CPUID`][kvm-get-supported-cpuid] ioctl.
+    ///
+    /// Requires the [`ExtCpuid`](crate::cap::CapBool::ExtCpuid) capability.
+    ///
+    /// [kvm-get-supported-cpuid]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-supported-cpuid
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_supported_cpuid(&self) -> Result<Cpuid> {
+        cpuid::get_cpuid(|cpuid| ioctl!(&self.kvm, KVM_GET_SUPPORTED_CPUID, cpuid))
     }
 }
+
+/// Get a MSR index list with `ioctl`, which is invoked with a pointer to a `struct kvm_msr_list`.
+#[cfg(target_arch = "x86_64")]
+fn get_msr_list<F>(ioctl: F) -> Result<Vec<u32>>
+where
+    F: Fn(u64) -> Result<libc::c_int>,
+{
+    // Query the number of MSRs first, the ioctl fails with `E2BIG` if `nmsrs` is too small and
+    // sets `nmsrs` to the required number of entries.
+    let mut list = vec![0u32; 1];
+    match ioctl(list.as_mut_ptr() as u64) {
+        Ok(_) => return Ok(Vec::new()),
+        Err(Error::Ioctl(_, err)) if err.raw_os_error() == Some(libc::E2BIG) => {}
+        Err(err) => return Err(err),
+    }
+
+    // Layout `struct kvm_msr_list`: `nmsrs` followed by the indices.
+    let nmsrs = list[0] as usize;
+    list.resize(nmsrs + 1, 0);
+    ioctl(list.as_mut_ptr() as u64)?;
+
+    let nmsrs = (list[0] as usize).min(nmsrs);
+    Ok(list[1..=nmsrs].to_vec())
+}
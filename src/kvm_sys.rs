@@ -37,7 +37,7 @@ pub struct kvm_regs {
 }
 
 #[repr(C)]
-#[derive(Default, Debug)]
+#[derive(Clone, Copy, Default, Debug)]
 pub struct kvm_segment {
     pub base: u64,
     pub limit: u32,
@@ -55,7 +55,7 @@ pub struct kvm_segment {
 }
 
 #[repr(C)]
-#[derive(Default, Debug)]
+#[derive(Clone, Copy, Default, Debug)]
 pub struct kvm_dtable {
     pub base: u64,
     pub limit: u16,
@@ -95,6 +95,43 @@ pub(crate) struct kvm_userspace_memory_region {
     pub userspace_addr: u64,
 }
 
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_dirty_log {
+    pub slot: u32,
+    padding1: u32,
+    pub dirty_bitmap: u64,
+}
+
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_clear_dirty_log {
+    pub slot: u32,
+    pub num_pages: u32,
+    pub first_page: u64,
+    pub dirty_bitmap: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct kvm_enable_cap {
+    pub cap: u32,
+    pub flags: u32,
+    pub args: [u64; 4],
+    pad: [u8; 64],
+}
+
+impl Default for kvm_enable_cap {
+    fn default() -> Self {
+        kvm_enable_cap {
+            cap: 0,
+            flags: 0,
+            args: [0; 4],
+            pad: [0; 64],
+        }
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[repr(C)]
 #[derive(Default, Debug)]
@@ -106,6 +143,319 @@ pub struct kvm_debugregs {
     pub reserved: [u64; 9],
 }
 
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Default, Debug)]
+pub struct kvm_fpu {
+    pub fpr: [[u8; 16]; 8],
+    pub fcw: u16,
+    pub fsw: u16,
+    /// Abridged tag word in `fxsave` format.
+    pub ftwx: u8,
+    pad1: u8,
+    pub last_opcode: u16,
+    pub last_ip: u64,
+    pub last_dp: u64,
+    pub xmm: [[u8; 16]; 16],
+    pub mxcsr: u32,
+    pad2: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Debug)]
+pub struct kvm_xsave {
+    /// `XSAVE` area in standard format (legacy region, header and extended region).
+    pub region: [u32; 1024],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for kvm_xsave {
+    fn default() -> Self {
+        kvm_xsave { region: [0; 1024] }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_xcr {
+    pub xcr: u32,
+    reserved: u32,
+    pub value: u64,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Default, Debug)]
+pub struct kvm_xcrs {
+    pub nr_xcrs: u32,
+    pub flags: u32,
+    pub xcrs: [kvm_xcr; KVM_MAX_XCRS],
+    padding: [u64; 16],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_msr_entry {
+    pub index: u32,
+    reserved: u32,
+    pub data: u64,
+}
+
+/// Variable length `struct kvm_msrs` with `nmsrs` entries.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_msrs {
+    pub nmsrs: u32,
+    pad: u32,
+    pub entries: [kvm_msr_entry; 0],
+}
+
+/// Variable length `struct kvm_msr_list` with `nmsrs` indices.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_msr_list {
+    pub nmsrs: u32,
+    pub indices: [u32; 0],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct kvm_cpuid_entry2 {
+    pub function: u32,
+    pub index: u32,
+    pub flags: u32,
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    padding: [u32; 3],
+}
+
+/// Variable length `struct kvm_cpuid2` with `nent` entries.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_cpuid2 {
+    pub nent: u32,
+    padding: u32,
+    pub entries: [kvm_cpuid_entry2; 0],
+}
+
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_irq_level {
+    pub irq: u32,
+    pub level: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct kvm_pic_state {
+    pub last_irr: u8,
+    pub irr: u8,
+    pub imr: u8,
+    pub isr: u8,
+    pub priority_add: u8,
+    pub irq_base: u8,
+    pub read_reg_select: u8,
+    pub poll: u8,
+    pub special_mask: u8,
+    pub init_state: u8,
+    pub auto_eoi: u8,
+    pub rotate_on_auto_eoi: u8,
+    pub special_fully_nested_mode: u8,
+    pub init4: u8,
+    pub elcr: u8,
+    pub elcr_mask: u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct kvm_ioapic_state {
+    pub base_address: u64,
+    pub ioregsel: u32,
+    pub id: u32,
+    pub irr: u32,
+    pad: u32,
+    pub redirtbl: [u64; KVM_IOAPIC_NUM_PINS],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub(crate) union kvm_irqchip_union {
+    pub dummy: [u8; 512],
+    pub pic: kvm_pic_state,
+    pub ioapic: kvm_ioapic_state,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub(crate) struct kvm_irqchip {
+    pub chip_id: u32,
+    pad: u32,
+    pub chip: kvm_irqchip_union,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for kvm_irqchip {
+    fn default() -> Self {
+        kvm_irqchip {
+            chip_id: 0,
+            pad: 0,
+            chip: kvm_irqchip_union { dummy: [0; 512] },
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct kvm_lapic_state {
+    pub regs: [u8; KVM_APIC_REG_SIZE],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for kvm_lapic_state {
+    fn default() -> Self {
+        kvm_lapic_state {
+            regs: [0; KVM_APIC_REG_SIZE],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_interrupt {
+    pub irq: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_vcpu_events_exception {
+    pub injected: u8,
+    pub nr: u8,
+    pub has_error_code: u8,
+    pub pending: u8,
+    pub error_code: u32,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_vcpu_events_interrupt {
+    pub injected: u8,
+    pub nr: u8,
+    pub soft: u8,
+    pub shadow: u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_vcpu_events_nmi {
+    pub injected: u8,
+    pub pending: u8,
+    pub masked: u8,
+    pad: u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_vcpu_events_smi {
+    pub smm: u8,
+    pub pending: u8,
+    pub smm_inside_nmi: u8,
+    pub latched_init: u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_vcpu_events_triple_fault {
+    pub pending: u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct kvm_vcpu_events {
+    pub exception: kvm_vcpu_events_exception,
+    pub interrupt: kvm_vcpu_events_interrupt,
+    pub nmi: kvm_vcpu_events_nmi,
+    pub sipi_vector: u32,
+    pub flags: u32,
+    pub smi: kvm_vcpu_events_smi,
+    pub triple_fault: kvm_vcpu_events_triple_fault,
+    reserved: [u8; 26],
+    pub exception_has_payload: u8,
+    pub exception_payload: u64,
+}
+
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_mp_state {
+    pub mp_state: u32,
+}
+
+/// Variable length `struct kvm_signal_mask` with `len` bytes of signal set.
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_signal_mask {
+    pub len: u32,
+    pub sigset: [u8; 0],
+}
+
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_msi {
+    pub address_lo: u32,
+    pub address_hi: u32,
+    pub data: u32,
+    pub flags: u32,
+    pub devid: u32,
+    pad: [u8; 12],
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct kvm_msr_filter_range {
+    pub flags: u32,
+    pub nmsrs: u32,
+    pub base: u32,
+    pub bitmap: *const u8,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for kvm_msr_filter_range {
+    fn default() -> Self {
+        kvm_msr_filter_range {
+            flags: 0,
+            nmsrs: 0,
+            base: 0,
+            bitmap: std::ptr::null(),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_msr_filter {
+    pub flags: u32,
+    pub ranges: [kvm_msr_filter_range; KVM_MSR_FILTER_MAX_RANGES],
+}
+
 #[repr(C)]
 #[derive(Default, Debug)]
 pub(crate) struct kvm_guest_debug {
@@ -121,14 +471,25 @@ pub(crate) struct kvm_guest_debug_arch {
     pub debugreg: [u64; 8],
 }
 
+#[repr(C)]
+#[derive(Default, Debug)]
+pub(crate) struct kvm_translation {
+    pub linear_address: u64,
+    pub physical_address: u64,
+    pub valid: u8,
+    pub writeable: u8,
+    pub usermode: u8,
+    pad: [u8; 5],
+}
+
 #[repr(C)]
 pub(crate) struct kvm_run {
-    request_interrupt_window: u8,
-    immediate_exit: u8,
+    pub request_interrupt_window: u8,
+    pub immediate_exit: u8,
     padding1: [u8; 6],
     pub exit_reason: u32,
-    ready_for_interrupt_injection: u8,
-    if_flag: u8,
+    pub ready_for_interrupt_injection: u8,
+    pub if_flag: u8,
     flags: u16,
     cr8: u64,
     apic_base: u64,
@@ -168,12 +529,68 @@ pub(crate) struct kvm_run_debug {
     pub dr7: u64,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct kvm_run_fail_entry {
+    pub hardware_entry_failure_reason: u64,
+    pub cpu: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct kvm_run_ex {
+    pub exception: u32,
+    pub error_code: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct kvm_run_hypercall {
+    pub nr: u64,
+    pub args: [u64; 6],
+    pub ret: u64,
+    pub longmode: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct kvm_run_internal {
+    pub suberror: u32,
+    pub ndata: u32,
+    pub data: [u64; 16],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct kvm_run_system_event {
+    pub type_: u32,
+    pub ndata: u32,
+    pub data: [u64; 16],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct kvm_run_msr {
+    pub error: u8,
+    pad: [u8; 7],
+    pub reason: u32,
+    pub index: u32,
+    pub data: u64,
+}
+
 // Only add the union fields used here.
 #[repr(C)]
 pub(crate) union kvm_run_union {
+    pub fail_entry: kvm_run_fail_entry,
+    pub ex: kvm_run_ex,
     pub io: kvm_run_io,
-    pub mmio: kvm_run_mmio,
     pub debug: kvm_run_debug,
+    pub mmio: kvm_run_mmio,
+    pub hypercall: kvm_run_hypercall,
+    pub internal: kvm_run_internal,
+    pub system_event: kvm_run_system_event,
+    pub msr: kvm_run_msr,
     padding: [u8; 256],
 }
 
@@ -228,12 +645,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_kvm_dirty_log() {
+        assert_eq!(mem::size_of::<kvm_dirty_log>(), TEST_KVM_DIRTY_LOG_SIZE);
+        assert_eq!(mem::align_of::<kvm_dirty_log>(), TEST_KVM_DIRTY_LOG_ALIGN);
+        assert_eq!(
+            mem::size_of::<kvm_clear_dirty_log>(),
+            TEST_KVM_CLEAR_DIRTY_LOG_SIZE
+        );
+        assert_eq!(
+            mem::align_of::<kvm_clear_dirty_log>(),
+            TEST_KVM_CLEAR_DIRTY_LOG_ALIGN
+        );
+    }
+
+    #[test]
+    fn check_kvm_enable_cap() {
+        assert_eq!(mem::size_of::<kvm_enable_cap>(), TEST_KVM_ENABLE_CAP_SIZE);
+        assert_eq!(mem::align_of::<kvm_enable_cap>(), TEST_KVM_ENABLE_CAP_ALIGN);
+    }
+
     #[test]
     fn check_kvm_run() {
         assert_eq!(mem::size_of::<kvm_run>(), TEST_KVM_RUN_SIZE);
         assert_eq!(mem::align_of::<kvm_run>(), TEST_KVM_RUN_ALIGN);
         assert_eq!(mem::size_of::<kvm_run_io>(), TEST_KVM_RUN_IO_SIZE);
         assert_eq!(mem::size_of::<kvm_run_mmio>(), TEST_KVM_RUN_MMIO_SIZE);
+        assert_eq!(
+            mem::size_of::<kvm_run_fail_entry>(),
+            TEST_KVM_RUN_FAIL_ENTRY_SIZE
+        );
+        assert_eq!(mem::size_of::<kvm_run_ex>(), TEST_KVM_RUN_EX_SIZE);
+        assert_eq!(
+            mem::size_of::<kvm_run_hypercall>(),
+            TEST_KVM_RUN_HYPERCALL_SIZE
+        );
+        assert_eq!(
+            mem::size_of::<kvm_run_internal>(),
+            TEST_KVM_RUN_INTERNAL_SIZE
+        );
+        assert_eq!(
+            mem::size_of::<kvm_run_system_event>(),
+            TEST_KVM_RUN_SYSTEM_EVENT_SIZE
+        );
+        assert_eq!(mem::size_of::<kvm_run_msr>(), TEST_KVM_RUN_MSR_SIZE);
         assert_eq!(mem::size_of::<kvm_run_union_s>(), TEST_KVM_RUN_UNION_S_SIZE);
     }
 
@@ -271,4 +726,112 @@ mod tests {
             TEST_KVM_GUEST_DEBUG_ARCH_ALIGN
         );
     }
+
+    #[test]
+    fn check_kvm_translation() {
+        assert_eq!(mem::size_of::<kvm_translation>(), TEST_KVM_TRANSLATION_SIZE);
+        assert_eq!(
+            mem::align_of::<kvm_translation>(),
+            TEST_KVM_TRANSLATION_ALIGN
+        );
+    }
+
+    #[test]
+    fn check_kvm_fpu() {
+        assert_eq!(mem::size_of::<kvm_fpu>(), TEST_KVM_FPU_SIZE);
+        assert_eq!(mem::align_of::<kvm_fpu>(), TEST_KVM_FPU_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_xsave() {
+        assert_eq!(mem::size_of::<kvm_xsave>(), TEST_KVM_XSAVE_SIZE);
+        assert_eq!(mem::align_of::<kvm_xsave>(), TEST_KVM_XSAVE_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_xcrs() {
+        assert_eq!(mem::size_of::<kvm_xcr>(), TEST_KVM_XCR_SIZE);
+        assert_eq!(mem::align_of::<kvm_xcr>(), TEST_KVM_XCR_ALIGN);
+        assert_eq!(mem::size_of::<kvm_xcrs>(), TEST_KVM_XCRS_SIZE);
+        assert_eq!(mem::align_of::<kvm_xcrs>(), TEST_KVM_XCRS_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_msrs() {
+        assert_eq!(mem::size_of::<kvm_msr_entry>(), TEST_KVM_MSR_ENTRY_SIZE);
+        assert_eq!(mem::align_of::<kvm_msr_entry>(), TEST_KVM_MSR_ENTRY_ALIGN);
+        assert_eq!(mem::size_of::<kvm_msrs>(), TEST_KVM_MSRS_SIZE);
+        assert_eq!(mem::align_of::<kvm_msrs>(), TEST_KVM_MSRS_ALIGN);
+        assert_eq!(mem::size_of::<kvm_msr_list>(), TEST_KVM_MSR_LIST_SIZE);
+        assert_eq!(mem::align_of::<kvm_msr_list>(), TEST_KVM_MSR_LIST_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_msr_filter() {
+        assert_eq!(
+            mem::size_of::<kvm_msr_filter_range>(),
+            TEST_KVM_MSR_FILTER_RANGE_SIZE
+        );
+        assert_eq!(
+            mem::align_of::<kvm_msr_filter_range>(),
+            TEST_KVM_MSR_FILTER_RANGE_ALIGN
+        );
+        assert_eq!(mem::size_of::<kvm_msr_filter>(), TEST_KVM_MSR_FILTER_SIZE);
+        assert_eq!(mem::align_of::<kvm_msr_filter>(), TEST_KVM_MSR_FILTER_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_cpuid() {
+        assert_eq!(
+            mem::size_of::<kvm_cpuid_entry2>(),
+            TEST_KVM_CPUID_ENTRY2_SIZE
+        );
+        assert_eq!(
+            mem::align_of::<kvm_cpuid_entry2>(),
+            TEST_KVM_CPUID_ENTRY2_ALIGN
+        );
+        assert_eq!(mem::size_of::<kvm_cpuid2>(), TEST_KVM_CPUID2_SIZE);
+        assert_eq!(mem::align_of::<kvm_cpuid2>(), TEST_KVM_CPUID2_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_irqchip() {
+        assert_eq!(mem::size_of::<kvm_irq_level>(), TEST_KVM_IRQ_LEVEL_SIZE);
+        assert_eq!(mem::size_of::<kvm_pic_state>(), TEST_KVM_PIC_STATE_SIZE);
+        assert_eq!(
+            mem::size_of::<kvm_ioapic_state>(),
+            TEST_KVM_IOAPIC_STATE_SIZE
+        );
+        assert_eq!(
+            mem::align_of::<kvm_ioapic_state>(),
+            TEST_KVM_IOAPIC_STATE_ALIGN
+        );
+        assert_eq!(mem::size_of::<kvm_irqchip>(), TEST_KVM_IRQCHIP_SIZE);
+        assert_eq!(mem::align_of::<kvm_irqchip>(), TEST_KVM_IRQCHIP_ALIGN);
+    }
+
+    #[test]
+    fn check_kvm_lapic_state() {
+        assert_eq!(mem::size_of::<kvm_lapic_state>(), TEST_KVM_LAPIC_STATE_SIZE);
+        assert_eq!(mem::size_of::<kvm_msi>(), TEST_KVM_MSI_SIZE);
+        assert_eq!(mem::size_of::<kvm_interrupt>(), TEST_KVM_INTERRUPT_SIZE);
+    }
+
+    #[test]
+    fn check_kvm_vcpu_events() {
+        assert_eq!(mem::size_of::<kvm_vcpu_events>(), TEST_KVM_VCPU_EVENTS_SIZE);
+        assert_eq!(
+            mem::align_of::<kvm_vcpu_events>(),
+            TEST_KVM_VCPU_EVENTS_ALIGN
+        );
+    }
+
+    #[test]
+    fn check_kvm_signal_mask() {
+        assert_eq!(mem::size_of::<kvm_signal_mask>(), TEST_KVM_SIGNAL_MASK_SIZE);
+        assert_eq!(
+            mem::align_of::<kvm_signal_mask>(),
+            TEST_KVM_SIGNAL_MASK_ALIGN
+        );
+    }
 }
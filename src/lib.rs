@@ -7,15 +7,36 @@ use std::io;
 use std::ops;
 use std::os::unix::io::AsRawFd;
 
+/// Wrapper of [`ioctl`] for the KVM ioctl `$cmd` defined in [`kvm_sys`], returning a
+/// [`Result`](crate::Result) which records the name of the ioctl on failure.
+macro_rules! ioctl {
+    ($fd:expr, $cmd:ident, $arg:expr $(,)?) => {
+        $crate::ioctl($fd, $crate::kvm_sys::$cmd, $arg)
+            .map_err(|err| $crate::Error::Ioctl(stringify!($cmd), err))
+    };
+}
+
+pub mod bus;
 pub mod cap;
+pub mod cpuid;
+pub mod debug;
+mod error;
 mod fmt;
+pub mod gdbstub;
+pub mod irqchip;
 pub mod kvm;
 pub mod kvm_sys;
+pub mod msr;
+pub mod runner;
+pub mod serial;
 pub mod vcpu;
 pub mod vm;
 pub mod x86_64;
 
+pub use error::{Error, Result};
+
 /// Strong type representing physical addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PhysAddr(pub u64);
 
 /// Helper to turn libc return values into an [io::Result](std::io::Result). Returns
@@ -47,7 +68,7 @@ pub struct UserMem {
 
 impl UserMem {
     /// Allocate a zero-initialized memory region of `len` bytes.
-    pub fn new(len: usize) -> io::Result<UserMem> {
+    pub fn new(len: usize) -> Result<UserMem> {
         let ptr = unsafe {
             libc::mmap(
                 std::ptr::null_mut(),
@@ -60,7 +81,7 @@ impl UserMem {
         };
 
         if ptr == libc::MAP_FAILED {
-            Err(io::Error::last_os_error())
+            Err(io::Error::last_os_error().into())
         } else {
             Ok(UserMem {
                 ptr: ptr.cast(),
@@ -72,27 +93,48 @@ impl UserMem {
     /// Allocate a zero-initialized memory region of `len` bytes and initialize the first bytes
     /// with `init_from`.
     ///
-    /// # Panics
-    ///
-    /// Panics if `init_from` is larger than the memory size `len`.
-    pub fn with_init(len: usize, init_from: &[u8]) -> io::Result<UserMem> {
-        assert!(len >= init_from.len());
-
+    /// Returns [`Error::GuestMemOutOfBounds`](crate::Error::GuestMemOutOfBounds) if `init_from`
+    /// is larger than the memory size `len`.
+    pub fn with_init(len: usize, init_from: &[u8]) -> Result<UserMem> {
         let mut m = UserMem::new(len)?;
-        m.load(PhysAddr(0), init_from);
+        m.load(PhysAddr(0), init_from)?;
         Ok(m)
     }
 
     /// Load the bytes stored in `data` into memory at physical address `addr`.
     ///
-    /// # Panics
-    ///
-    /// Panics if `addr + data.len` is larger than the memory size `len`.
-    pub fn load(&mut self, addr: PhysAddr, data: &[u8]) {
-        assert!(self.len >= addr.0 as usize + data.len());
+    /// Returns [`Error::GuestMemOutOfBounds`](crate::Error::GuestMemOutOfBounds) if `addr +
+    /// data.len` is larger than the memory size `len`.
+    pub fn load(&mut self, addr: PhysAddr, data: &[u8]) -> Result<()> {
+        self.check_bounds(addr, data.len())?;
 
         let addr = addr.0 as usize;
         self.as_mut()[addr..addr + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Read `data.len` bytes from memory at physical address `addr` into `data`.
+    ///
+    /// Returns [`Error::GuestMemOutOfBounds`](crate::Error::GuestMemOutOfBounds) if `addr +
+    /// data.len` is larger than the memory size `len`.
+    pub fn read(&self, addr: PhysAddr, data: &mut [u8]) -> Result<()> {
+        self.check_bounds(addr, data.len())?;
+
+        let addr = addr.0 as usize;
+        data.copy_from_slice(&self.as_ref()[addr..addr + data.len()]);
+        Ok(())
+    }
+
+    /// Check if an access of `len` bytes at physical address `addr` is within the memory region.
+    fn check_bounds(&self, addr: PhysAddr, len: usize) -> Result<()> {
+        match (addr.0 as usize).checked_add(len) {
+            Some(end) if end <= self.len => Ok(()),
+            _ => Err(Error::GuestMemOutOfBounds {
+                addr: addr.0,
+                len,
+                size: self.len,
+            }),
+        }
     }
 }
 
@@ -127,7 +169,7 @@ struct KvmRun {
 impl KvmRun {
     /// Mmap the `struct kvm_run` for a given `VCPU` referenced by the argument file descriptor
     /// `vcpu`.
-    fn new<F: AsRawFd>(vcpu: &F, len: usize) -> io::Result<KvmRun> {
+    fn new<F: AsRawFd>(vcpu: &F, len: usize) -> Result<KvmRun> {
         let ptr = unsafe {
             libc::mmap(
                 std::ptr::null_mut(),
@@ -140,7 +182,7 @@ impl KvmRun {
         };
 
         if ptr == libc::MAP_FAILED {
-            Err(io::Error::last_os_error())
+            Err(io::Error::last_os_error().into())
         } else {
             Ok(KvmRun {
                 ptr: ptr.cast(),
@@ -150,6 +192,10 @@ impl KvmRun {
     }
 }
 
+// Safe as the mapping is owned by a single `Vcpu` and only accessed through it, which allows to
+// move the `Vcpu` to the thread running it.
+unsafe impl Send for KvmRun {}
+
 impl ops::Drop for KvmRun {
     /// Munmap the mmaped `struct kvm_run`.
     fn drop(&mut self) {
@@ -165,6 +211,6 @@ impl AsRef<kvm_sys::kvm_run> for KvmRun {
 
 impl AsMut<kvm_sys::kvm_run> for KvmRun {
     fn as_mut(&mut self) -> &mut kvm_sys::kvm_run {
-        unsafe { &mut *(self.ptr as *mut kvm_sys::kvm_run) }
+        unsafe { &mut *self.ptr }
     }
 }
@@ -0,0 +1,382 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Model specific register (MSR) access.
+
+use std::ops;
+
+use crate::kvm_sys::{self, kvm_msr_entry, kvm_msr_filter, kvm_msrs, kvm_run_msr};
+use crate::{Error, Result};
+
+/// Safe wrapper of the variable length `struct kvm_msrs` used to read and write MSRs with
+/// [`Vcpu::get_msrs`](crate::vcpu::Vcpu::get_msrs) and
+/// [`Vcpu::set_msrs`](crate::vcpu::Vcpu::set_msrs).
+///
+/// MSR indices are defined in [`x86_64`](crate::x86_64), eg
+/// [`MSR_EFER`](crate::x86_64::MSR_EFER).
+#[derive(Clone, Debug)]
+pub struct Msrs {
+    /// Backing storage of the `struct kvm_msrs` header followed by the entries, `u64` words to
+    /// satisfy the alignment of [`kvm_msr_entry`](crate::kvm_sys::kvm_msr_entry).
+    buf: Vec<u64>,
+}
+
+/// Number of `u64` words of a [`kvm_msr_entry`](crate::kvm_sys::kvm_msr_entry).
+const ENTRY_WORDS: usize = std::mem::size_of::<kvm_msr_entry>() / 8;
+
+impl Default for Msrs {
+    fn default() -> Msrs {
+        Msrs::new()
+    }
+}
+
+impl Msrs {
+    pub fn new() -> Msrs {
+        Msrs { buf: vec![0] }
+    }
+
+    /// Create entries for the MSRs `indices` with data initialized to `0`, eg to read the MSRs
+    /// with [`Vcpu::get_msrs`](crate::vcpu::Vcpu::get_msrs).
+    pub fn from_indices(indices: &[u32]) -> Msrs {
+        let mut msrs = Msrs::new();
+        for &index in indices {
+            msrs.push(index, 0);
+        }
+        msrs
+    }
+
+    /// Append an entry for the MSR `index` with `data`.
+    pub fn push(&mut self, index: u32, data: u64) {
+        // Entry layout: `index: u32`, `reserved: u32`, `data: u64`.
+        self.buf.push(u64::from(index));
+        self.buf.push(data);
+        // Update `nmsrs` in the header.
+        self.buf[0] = self.len() as u64;
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        (self.buf.len() - 1) / ENTRY_WORDS
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the entries.
+    pub fn entries(&self) -> &[kvm_msr_entry] {
+        if self.is_empty() {
+            return &[];
+        }
+
+        // Safe as the buffer holds `len` entries after the header and is properly aligned.
+        unsafe { std::slice::from_raw_parts(self.buf[1..].as_ptr().cast(), self.len()) }
+    }
+
+    /// Get the entries as mutable slice, eg to update the data before
+    /// [`Vcpu::set_msrs`](crate::vcpu::Vcpu::set_msrs).
+    pub fn entries_mut(&mut self) -> &mut [kvm_msr_entry] {
+        if self.is_empty() {
+            return &mut [];
+        }
+
+        let len = self.len();
+        // Safe as the buffer holds `len` entries after the header and is properly aligned.
+        unsafe { std::slice::from_raw_parts_mut(self.buf[1..].as_mut_ptr().cast(), len) }
+    }
+
+    /// Get the data of the first entry for the MSR `index`.
+    pub fn get(&self, index: u32) -> Option<u64> {
+        self.entries()
+            .iter()
+            .find(|e| e.index == index)
+            .map(|e| e.data)
+    }
+
+    /// Pointer to the `struct kvm_msrs` passed to the ioctls.
+    pub(crate) fn as_ptr(&self) -> *const kvm_msrs {
+        self.buf.as_ptr().cast()
+    }
+
+    /// Mutable pointer to the `struct kvm_msrs` passed to the ioctls.
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut kvm_msrs {
+        self.buf.as_mut_ptr().cast()
+    }
+}
+
+/// Reasons for which guest MSR accesses are forwarded to user space as
+/// [`KvmExit::RdMsr`](crate::vcpu::KvmExit::RdMsr) and
+/// [`KvmExit::WrMsr`](crate::vcpu::KvmExit::WrMsr) exits, see
+/// [`Vm::enable_user_space_msr`](crate::vm::Vm::enable_user_space_msr).
+///
+/// Flags can be combined with the `|` operator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MsrExitReason(u32);
+
+impl MsrExitReason {
+    /// Access to an MSR which KVM would reject with a #GP (`KVM_MSR_EXIT_REASON_INVAL`).
+    pub const INVAL: MsrExitReason = MsrExitReason(kvm_sys::KVM_MSR_EXIT_REASON_INVAL);
+    /// Access to an MSR unknown to KVM (`KVM_MSR_EXIT_REASON_UNKNOWN`).
+    pub const UNKNOWN: MsrExitReason = MsrExitReason(kvm_sys::KVM_MSR_EXIT_REASON_UNKNOWN);
+    /// Access denied by the MSR filter installed with
+    /// [`Vm::set_msr_filter`](crate::vm::Vm::set_msr_filter) (`KVM_MSR_EXIT_REASON_FILTER`).
+    pub const FILTER: MsrExitReason = MsrExitReason(kvm_sys::KVM_MSR_EXIT_REASON_FILTER);
+
+    /// Check if all flags in `other` are set.
+    pub fn contains(self, other: MsrExitReason) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub(crate) fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl ops::BitOr for MsrExitReason {
+    type Output = MsrExitReason;
+
+    fn bitor(self, rhs: MsrExitReason) -> MsrExitReason {
+        MsrExitReason(self.0 | rhs.0)
+    }
+}
+
+/// Access types an [`MsrFilter`] range applies to.
+///
+/// Flags can be combined with the `|` operator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsrAccess(u32);
+
+impl MsrAccess {
+    /// Guest `rdmsr` (`KVM_MSR_FILTER_READ`).
+    pub const READ: MsrAccess = MsrAccess(kvm_sys::KVM_MSR_FILTER_READ);
+    /// Guest `wrmsr` (`KVM_MSR_FILTER_WRITE`).
+    pub const WRITE: MsrAccess = MsrAccess(kvm_sys::KVM_MSR_FILTER_WRITE);
+
+    /// Check if all flags in `other` are set.
+    pub fn contains(self, other: MsrAccess) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for MsrAccess {
+    type Output = MsrAccess;
+
+    fn bitor(self, rhs: MsrAccess) -> MsrAccess {
+        MsrAccess(self.0 | rhs.0)
+    }
+}
+
+/// Range of an [`MsrFilter`].
+#[derive(Clone, Debug)]
+struct MsrFilterRange {
+    access: MsrAccess,
+    base: u32,
+    nmsrs: u32,
+    /// One bit per MSR, `1` allows and `0` denies the access.
+    bitmap: Vec<u8>,
+}
+
+/// Builder for an MSR filter installed with
+/// [`Vm::set_msr_filter`](crate::vm::Vm::set_msr_filter).
+///
+/// Guest MSR accesses are checked against the ranges in the order they were added, the first
+/// range covering the MSR and access type decides. Accesses not covered by any range are handled
+/// according to the default action. Denied accesses inject a #GP into the guest, unless the
+/// [`MsrExitReason::FILTER`] exit is enabled.
+#[derive(Clone, Debug)]
+pub struct MsrFilter {
+    default_deny: bool,
+    ranges: Vec<MsrFilterRange>,
+}
+
+impl MsrFilter {
+    /// Filter which allows accesses not covered by any range.
+    pub fn default_allow() -> MsrFilter {
+        MsrFilter {
+            default_deny: false,
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Filter which denies accesses not covered by any range.
+    pub fn default_deny() -> MsrFilter {
+        MsrFilter {
+            default_deny: true,
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Add a range allowing `access` to the MSRs in `msrs`.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if the filter is full or
+    /// the range is too large.
+    pub fn allow(&mut self, access: MsrAccess, msrs: ops::Range<u32>) -> Result<&mut MsrFilter> {
+        self.add(access, msrs, true)
+    }
+
+    /// Add a range denying `access` to the MSRs in `msrs`.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if the filter is full or
+    /// the range is too large.
+    pub fn deny(&mut self, access: MsrAccess, msrs: ops::Range<u32>) -> Result<&mut MsrFilter> {
+        self.add(access, msrs, false)
+    }
+
+    fn add(
+        &mut self,
+        access: MsrAccess,
+        msrs: ops::Range<u32>,
+        allow: bool,
+    ) -> Result<&mut MsrFilter> {
+        if self.ranges.len() == kvm_sys::KVM_MSR_FILTER_MAX_RANGES {
+            return Err(Error::InvalidArgument("too many MSR filter ranges"));
+        }
+        if msrs.is_empty() {
+            return Err(Error::InvalidArgument("empty MSR filter range"));
+        }
+
+        let nmsrs = msrs.end - msrs.start;
+        let bytes = (nmsrs as usize).div_ceil(8);
+        if bytes > kvm_sys::KVM_MSR_FILTER_MAX_BITMAP_SIZE {
+            return Err(Error::InvalidArgument("MSR filter range too large"));
+        }
+
+        self.ranges.push(MsrFilterRange {
+            access,
+            base: msrs.start,
+            nmsrs,
+            bitmap: vec![if allow { 0xff } else { 0x00 }; bytes],
+        });
+        Ok(self)
+    }
+
+    /// Build the `struct kvm_msr_filter`, the bitmaps are borrowed from `self`.
+    pub(crate) fn as_kvm(&self) -> kvm_msr_filter {
+        let mut filter = kvm_msr_filter {
+            flags: if self.default_deny {
+                kvm_sys::KVM_MSR_FILTER_DEFAULT_DENY
+            } else {
+                kvm_sys::KVM_MSR_FILTER_DEFAULT_ALLOW
+            },
+            ..Default::default()
+        };
+
+        for (kvm, range) in filter.ranges.iter_mut().zip(self.ranges.iter()) {
+            kvm.flags = range.access.0;
+            kvm.nmsrs = range.nmsrs;
+            kvm.base = range.base;
+            kvm.bitmap = range.bitmap.as_ptr();
+        }
+        filter
+    }
+}
+
+/// Guest MSR access forwarded to user space, see
+/// [`KvmExit::RdMsr`](crate::vcpu::KvmExit::RdMsr) and
+/// [`KvmExit::WrMsr`](crate::vcpu::KvmExit::WrMsr).
+///
+/// The access completes with the next [`Vcpu::run`](crate::vcpu::Vcpu::run), either with the
+/// data provided by [`set_data`](MsrExit::set_data) for reads, or by injecting a #GP with
+/// [`inject_gp`](MsrExit::inject_gp).
+#[derive(Debug)]
+pub struct MsrExit<'cpu> {
+    msr: &'cpu mut kvm_run_msr,
+}
+
+impl<'cpu> MsrExit<'cpu> {
+    pub(crate) fn new(msr: &'cpu mut kvm_run_msr) -> MsrExit<'cpu> {
+        MsrExit { msr }
+    }
+
+    /// MSR index accessed by the guest.
+    pub fn index(&self) -> u32 {
+        self.msr.index
+    }
+
+    /// Reason the access was forwarded to user space.
+    pub fn reason(&self) -> MsrExitReason {
+        MsrExitReason(self.msr.reason)
+    }
+
+    /// Value written by the guest for `wrmsr`, or the value returned to the guest for `rdmsr`.
+    pub fn data(&self) -> u64 {
+        self.msr.data
+    }
+
+    /// Set the value returned to the guest for `rdmsr`.
+    pub fn set_data(&mut self, data: u64) {
+        self.msr.data = data;
+    }
+
+    /// Fail the access and inject a #GP into the guest.
+    pub fn inject_gp(&mut self) {
+        self.msr.error = 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msrs_layout() {
+        let mut msrs = Msrs::from_indices(&[0x10, 0xc000_0080]);
+        msrs.push(0x1b, 0xfee0_0900);
+        assert_eq!(msrs.len(), 3);
+
+        msrs.entries_mut()[0].data = 0x1234;
+        assert_eq!(msrs.get(0x10), Some(0x1234));
+        assert_eq!(msrs.get(0xc000_0080), Some(0));
+        assert_eq!(msrs.get(0x1b), Some(0xfee0_0900));
+        assert_eq!(msrs.get(0x1c), None);
+
+        // Header `nmsrs` followed by the entries as `struct kvm_msrs`.
+        assert_eq!(
+            msrs.buf,
+            vec![3, 0x10, 0x1234, 0xc000_0080, 0, 0x1b, 0xfee0_0900]
+        );
+        assert_eq!(unsafe { (*msrs.as_ptr()).nmsrs }, 3);
+
+        let empty = Msrs::default();
+        assert!(empty.is_empty());
+        assert!(empty.entries().is_empty());
+        assert_eq!(unsafe { (*empty.as_ptr()).nmsrs }, 0);
+    }
+
+    #[test]
+    fn msr_filter() {
+        let mut filter = MsrFilter::default_deny();
+        filter
+            .allow(MsrAccess::READ | MsrAccess::WRITE, 0x10..0x11)
+            .unwrap()
+            .deny(MsrAccess::WRITE, 0xc000_0080..0xc000_0090)
+            .unwrap();
+
+        let kvm = filter.as_kvm();
+        assert_eq!(kvm.flags, kvm_sys::KVM_MSR_FILTER_DEFAULT_DENY);
+        assert_eq!(kvm.ranges[0].flags, 0x3);
+        assert_eq!((kvm.ranges[0].base, kvm.ranges[0].nmsrs), (0x10, 1));
+        assert_eq!(unsafe { *kvm.ranges[0].bitmap }, 0xff);
+        assert_eq!(kvm.ranges[1].flags, kvm_sys::KVM_MSR_FILTER_WRITE);
+        assert_eq!((kvm.ranges[1].base, kvm.ranges[1].nmsrs), (0xc000_0080, 16));
+        assert_eq!(filter.ranges[1].bitmap, vec![0, 0]);
+        assert_eq!(kvm.ranges[2].nmsrs, 0);
+
+        assert!(matches!(
+            filter.allow(MsrAccess::READ, 0..0x10000),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            filter.allow(MsrAccess::READ, 0x10..0x10),
+            Err(Error::InvalidArgument(_))
+        ));
+        for i in 2..kvm_sys::KVM_MSR_FILTER_MAX_RANGES as u32 {
+            filter.allow(MsrAccess::READ, i..i + 1).unwrap();
+        }
+        assert!(matches!(
+            filter.allow(MsrAccess::READ, 0x20..0x21),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! High-level VCPU run loop with deadline and budget.
+//!
+//! A [`Runner`] repeatedly runs a VCPU with [`Vcpu::run`](crate::vcpu::Vcpu::run), passes the
+//! exits to a handler and stops the guest once it halts, shuts down, exceeds the wall-clock
+//! deadline or exhausts the exit or instruction budget. The reason is reported as
+//! [`RunOutcome`].
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::vcpu::{KvmExit, Vcpu};
+use crate::{Error, Result};
+
+/// Reason why [`Runner::run`] returned.
+#[derive(Debug)]
+pub enum RunOutcome {
+    /// Guest executed `hlt` ([`KvmExit::Halt`]).
+    Halted,
+    /// Guest did not stop before the deadline.
+    TimedOut,
+    /// Guest exhausted the exit or instruction budget.
+    BudgetExhausted,
+    /// Guest triple fault or INIT ([`KvmExit::Shutdown`]).
+    Shutdown,
+    /// Exit handler returned `Ok(false)`.
+    Stopped,
+    /// Running the VCPU or the exit handler failed.
+    Error(Error),
+}
+
+/// Run loop for a single VCPU with an optional deadline and budget.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use kvm_rs::runner::{Runner, RunOutcome};
+/// # use kvm_rs::vcpu::KvmExit;
+/// # fn main() -> kvm_rs::Result<()> {
+/// # let vm = kvm_rs::kvm::Kvm::new()?.create_vm()?;
+/// let mut vcpu = vm.create_vpcu(0)?;
+/// // .. setup guest memory and registers.
+/// let outcome = Runner::new()
+///     .timeout(Duration::from_secs(5))
+///     .max_exits(1000)
+///     .run(&mut vcpu, |exit| match exit {
+///         KvmExit::IoOut(port, data) => {
+///             println!("out {:#x} {:x?}", port, data);
+///             Ok(true)
+///         }
+///         _ => Ok(false),
+///     });
+/// assert!(matches!(outcome, RunOutcome::Halted));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Runner {
+    deadline: Option<Instant>,
+    max_exits: Option<u64>,
+    max_instructions: Option<u64>,
+}
+
+impl Runner {
+    /// Runner without deadline and budget.
+    pub fn new() -> Runner {
+        Runner::default()
+    }
+
+    /// Stop the guest with [`RunOutcome::TimedOut`] once the wall-clock `deadline` passed.
+    ///
+    /// A guest spinning without exits is kicked out of `KVM_RUN` with a
+    /// [`VcpuHandle`](crate::vcpu::VcpuHandle).
+    pub fn deadline(&mut self, deadline: Instant) -> &mut Runner {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Same as [`Runner::deadline`] with the deadline `timeout` from now.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Runner {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    /// Stop the guest with [`RunOutcome::BudgetExhausted`] after `max` exits were passed to the
+    /// exit handler.
+    pub fn max_exits(&mut self, max: u64) -> &mut Runner {
+        self.max_exits = Some(max);
+        self
+    }
+
+    /// Stop the guest with [`RunOutcome::BudgetExhausted`] after `max` instructions were
+    /// executed.
+    ///
+    /// Instructions are counted by single stepping the VCPU, see
+    /// [`Vcpu::set_single_step`](crate::vcpu::Vcpu::set_single_step). The single step exits are
+    /// only passed to the exit handler if single stepping was already enabled on the VCPU.
+    pub fn max_instructions(&mut self, max: u64) -> &mut Runner {
+        self.max_instructions = Some(max);
+        self
+    }
+
+    /// Run the `vcpu` and pass all exits other than [`KvmExit::Halt`] and [`KvmExit::Shutdown`]
+    /// to the `handler`, until the `handler` returns `Ok(false)` or one of the other
+    /// [`RunOutcome`]s occurs.
+    ///
+    /// [`KvmExit::Interrupted`] exits not caused by the deadline are passed to the `handler`.
+    pub fn run<F>(&self, vcpu: &mut Vcpu, handler: F) -> RunOutcome
+    where
+        F: FnMut(KvmExit<'_>) -> Result<bool>,
+    {
+        let single_step = vcpu.single_step();
+        if self.max_instructions.is_some() && !single_step {
+            if let Err(err) = vcpu.set_single_step(true) {
+                return RunOutcome::Error(err);
+            }
+        }
+
+        let outcome = match self.deadline {
+            Some(deadline) => self.run_with_deadline(vcpu, deadline, single_step, handler),
+            None => self.run_loop(vcpu, None, single_step, handler),
+        };
+
+        if vcpu.single_step() != single_step {
+            if let Err(err) = vcpu.set_single_step(single_step) {
+                return RunOutcome::Error(err);
+            }
+        }
+        outcome
+    }
+
+    /// Run the `vcpu` with a timer thread kicking it once the `deadline` passed.
+    fn run_with_deadline<F>(
+        &self,
+        vcpu: &mut Vcpu,
+        deadline: Instant,
+        single_step: bool,
+        handler: F,
+    ) -> RunOutcome
+    where
+        F: FnMut(KvmExit<'_>) -> Result<bool>,
+    {
+        let handle = match vcpu.handle() {
+            Ok(handle) => handle,
+            Err(err) => return RunOutcome::Error(err),
+        };
+
+        // The timer is cancelled by dropping the sender once the run loop returned.
+        let (cancel, cancelled) = mpsc::channel::<()>();
+        let timer = thread::spawn(move || {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match cancelled.recv_timeout(timeout) {
+                Err(mpsc::RecvTimeoutError::Timeout) => handle.kick().map(|_| true),
+                _ => Ok(false),
+            }
+        });
+
+        let outcome = self.run_loop(vcpu, Some(deadline), single_step, handler);
+
+        drop(cancel);
+        match timer
+            .join()
+            .unwrap_or_else(|err| std::panic::resume_unwind(err))
+        {
+            // Drop a kick which raced with the run loop returning for another reason.
+            Ok(true) => vcpu.clear_kick(),
+            Ok(false) => {}
+            Err(err) => return RunOutcome::Error(err),
+        }
+        outcome
+    }
+
+    /// Run the `vcpu` and pass the exits to the `handler`, single step exits are only passed on
+    /// if `single_step` was enabled by the user.
+    fn run_loop<F>(
+        &self,
+        vcpu: &mut Vcpu,
+        deadline: Option<Instant>,
+        single_step: bool,
+        mut handler: F,
+    ) -> RunOutcome
+    where
+        F: FnMut(KvmExit<'_>) -> Result<bool>,
+    {
+        let timed_out = || matches!(deadline, Some(deadline) if Instant::now() >= deadline);
+        let mut exits = 0;
+        let mut instructions = 0;
+
+        loop {
+            if timed_out() {
+                return RunOutcome::TimedOut;
+            }
+            if matches!(self.max_exits, Some(max) if exits >= max)
+                || matches!(self.max_instructions, Some(max) if instructions >= max)
+            {
+                return RunOutcome::BudgetExhausted;
+            }
+
+            let exit = match vcpu.run() {
+                Ok(exit) => exit,
+                Err(err) => return RunOutcome::Error(err),
+            };
+
+            match exit {
+                KvmExit::Halt => return RunOutcome::Halted,
+                KvmExit::Shutdown => return RunOutcome::Shutdown,
+                KvmExit::Interrupted if timed_out() => return RunOutcome::TimedOut,
+                KvmExit::Debug(debug)
+                    if self.max_instructions.is_some() && debug.is_single_step() =>
+                {
+                    instructions += 1;
+                    if !single_step {
+                        continue;
+                    }
+                }
+                // Instructions exiting to user space complete on the next entry without a
+                // single step exit.
+                KvmExit::IoIn(..)
+                | KvmExit::IoOut(..)
+                | KvmExit::MmioRead(..)
+                | KvmExit::MmioWrite(..)
+                    if self.max_instructions.is_some() =>
+                {
+                    instructions += 1;
+                }
+                _ => {}
+            }
+
+            exits += 1;
+            match handler(exit) {
+                Ok(true) => {}
+                Ok(false) => return RunOutcome::Stopped,
+                Err(err) => return RunOutcome::Error(err),
+            }
+        }
+    }
+}
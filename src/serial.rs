@@ -0,0 +1,436 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! 16550A UART serial port.
+//!
+//! The [`Serial`] device emulates the registers of a 16550A UART and is registered on an
+//! [`IoBus`](crate::bus::IoBus), typically at [`COM1_PORT`]. Guest output is written to any host
+//! [`Write`](std::io::Write), host input is queued with [`Serial::queue_input`] or read from any
+//! host [`Read`](std::io::Read) with [`spawn_input`]. Interrupts are raised on an
+//! [`IrqLine`](crate::irqchip::IrqLine), typically [`COM1_IRQ`].
+//!
+//! Data is transmitted without delay and the baud rate configured in the divisor latch is
+//! ignored. Host input is buffered without limit, hence receiver overruns do not occur.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::bus::Device;
+use crate::irqchip::IrqLine;
+
+/// IO port base of the first serial port.
+pub const COM1_PORT: u16 = 0x3f8;
+/// IRQ line of the first serial port.
+pub const COM1_IRQ: u32 = 4;
+/// Number of IO ports occupied by a serial port.
+pub const NR_PORTS: u16 = 8;
+
+// Register offsets, `DLL` and `DLM` are accessed with `LCR_DLAB` set.
+const DATA: u64 = 0; // RBR (r) / THR (w) / DLL
+const IER: u64 = 1; // DLM
+const IIR: u64 = 2; // IIR (r) / FCR (w)
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+const IER_RDA: u8 = 1 << 0;
+const IER_THRE: u8 = 1 << 1;
+const IER_MSI: u8 = 1 << 3;
+const IER_MASK: u8 = 0x0f;
+
+const IIR_NONE: u8 = 0x01;
+const IIR_MSI: u8 = 0x00;
+const IIR_THRE: u8 = 0x02;
+const IIR_RDA: u8 = 0x04;
+const IIR_FIFO: u8 = 0xc0;
+
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT1: u8 = 1 << 2;
+const MCR_OUT2: u8 = 1 << 3;
+const MCR_LOOP: u8 = 1 << 4;
+const MCR_MASK: u8 = 0x1f;
+
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+const MSR_CTS: u8 = 1 << 4;
+const MSR_DSR: u8 = 1 << 5;
+const MSR_RI: u8 = 1 << 6;
+const MSR_DCD: u8 = 1 << 7;
+const MSR_DELTA_MASK: u8 = 0x0f;
+
+/// Default divisor latch, `9600` baud.
+const DEFAULT_DIVISOR: u16 = 12;
+
+/// 16550A UART, see the [module](crate::serial) documentation.
+///
+/// ```no_run
+/// # use kvm_rs::bus::Dispatcher;
+/// # use kvm_rs::serial::{Serial, COM1_IRQ, COM1_PORT, NR_PORTS};
+/// # fn main() -> kvm_rs::Result<()> {
+/// let vm = kvm_rs::kvm::Kvm::new()?.create_vm()?;
+/// vm.create_irqchip()?;
+///
+/// let mut serial = Serial::new(std::io::stdout());
+/// serial.set_irq_line(vm.irq_line(COM1_IRQ)?);
+///
+/// let mut dispatcher = Dispatcher::new();
+/// dispatcher.io.insert(COM1_PORT, NR_PORTS, Box::new(serial))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Serial {
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    /// Modem status lines and delta bits.
+    msr: u8,
+    scr: u8,
+    divisor: u16,
+    /// Transmitter holding register empty interrupt pending.
+    thre_pending: bool,
+    rx: VecDeque<u8>,
+    output: Box<dyn Write + Send>,
+    irq: Option<IrqLine>,
+    /// Current level of the IRQ line.
+    irq_level: bool,
+}
+
+impl Serial {
+    /// Create a serial port in reset state, writing the guest output to `output`.
+    ///
+    /// The modem status reports a connected peer (`CTS`, `DSR`, `DCD`).
+    pub fn new<W: Write + Send + 'static>(output: W) -> Serial {
+        Serial {
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            msr: MSR_CTS | MSR_DSR | MSR_DCD,
+            scr: 0,
+            divisor: DEFAULT_DIVISOR,
+            thre_pending: false,
+            rx: VecDeque::new(),
+            output: Box::new(output),
+            irq: None,
+            irq_level: false,
+        }
+    }
+
+    /// Raise interrupts on `irq`, eg [`COM1_IRQ`].
+    ///
+    /// As on PCs, the interrupt is only asserted while the guest sets the `OUT2` bit in the modem
+    /// control register.
+    pub fn set_irq_line(&mut self, irq: IrqLine) {
+        self.irq = Some(irq);
+        self.update_irq();
+    }
+
+    /// Queue `data` as host input to be received by the guest.
+    pub fn queue_input(&mut self, data: &[u8]) {
+        // The receiver is disconnected from the host in loopback mode.
+        if self.mcr & MCR_LOOP == 0 {
+            self.rx.extend(data);
+            self.update_irq();
+        }
+    }
+
+    /// Get the number of host input bytes not yet received by the guest.
+    pub fn pending_input(&self) -> usize {
+        self.rx.len()
+    }
+
+    /// Get the baud rate divisor configured by the guest.
+    pub fn divisor(&self) -> u16 {
+        self.divisor
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+
+    /// Get the highest priority pending interrupt (`IIR` identification bits).
+    fn interrupt_id(&self) -> u8 {
+        if self.ier & IER_RDA != 0 && !self.rx.is_empty() {
+            IIR_RDA
+        } else if self.ier & IER_THRE != 0 && self.thre_pending {
+            IIR_THRE
+        } else if self.ier & IER_MSI != 0 && self.msr & MSR_DELTA_MASK != 0 {
+            IIR_MSI
+        } else {
+            IIR_NONE
+        }
+    }
+
+    /// Update the level of the IRQ line according to the pending interrupts.
+    fn update_irq(&mut self) {
+        let level = self.interrupt_id() != IIR_NONE && self.mcr & MCR_OUT2 != 0;
+
+        if let Some(irq) = &self.irq {
+            if level != self.irq_level {
+                // Device accesses can not fail, a failed KVM_IRQ_LINE leaves the line unchanged.
+                if irq.set_level(level).is_ok() {
+                    self.irq_level = level;
+                }
+            }
+        }
+    }
+
+    /// Modem status lines looped back from the modem control register.
+    fn loop_msr(mcr: u8) -> u8 {
+        let mut msr = 0;
+        for (mcr_bit, msr_bit) in [
+            (MCR_RTS, MSR_CTS),
+            (MCR_DTR, MSR_DSR),
+            (MCR_OUT1, MSR_RI),
+            (MCR_OUT2, MSR_DCD),
+        ] {
+            if mcr & mcr_bit != 0 {
+                msr |= msr_bit;
+            }
+        }
+        msr
+    }
+
+    /// Set the modem status lines and the delta bits for changed lines (`DCTS`, `DDSR`,
+    /// `TERI` on the falling edge of `RI`, `DDCD`).
+    fn set_msr_lines(&mut self, lines: u8) {
+        let changed = (self.msr ^ lines) & !MSR_DELTA_MASK;
+        let mut delta = (changed >> 4) & !(MSR_RI >> 4);
+        if changed & MSR_RI != 0 && lines & MSR_RI == 0 {
+            delta |= MSR_RI >> 4;
+        }
+        self.msr = lines | (self.msr & MSR_DELTA_MASK) | delta;
+    }
+
+    fn read_reg(&mut self, offset: u64) -> u8 {
+        match offset {
+            DATA if self.dlab() => self.divisor as u8,
+            DATA => self.rx.pop_front().unwrap_or(0),
+            IER if self.dlab() => (self.divisor >> 8) as u8,
+            IER => self.ier,
+            IIR => {
+                let id = self.interrupt_id();
+                // Reading the identification of the THRE interrupt acknowledges it.
+                if id == IIR_THRE {
+                    self.thre_pending = false;
+                }
+                let fifo = if self.fcr & FCR_ENABLE != 0 {
+                    IIR_FIFO
+                } else {
+                    0
+                };
+                id | fifo
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                let dr = if self.rx.is_empty() { 0 } else { LSR_DR };
+                dr | LSR_THRE | LSR_TEMT
+            }
+            MSR => {
+                let msr = self.msr;
+                self.msr &= !MSR_DELTA_MASK;
+                msr
+            }
+            SCR => self.scr,
+            _ => 0,
+        }
+    }
+
+    fn write_reg(&mut self, offset: u64, val: u8) {
+        match offset {
+            DATA if self.dlab() => self.divisor = (self.divisor & 0xff00) | u16::from(val),
+            DATA => {
+                if self.mcr & MCR_LOOP != 0 {
+                    self.rx.push_back(val);
+                } else {
+                    // Device accesses can not fail, output errors drop the character.
+                    let _ = self
+                        .output
+                        .write_all(&[val])
+                        .and_then(|_| self.output.flush());
+                }
+                self.thre_pending = true;
+            }
+            IER if self.dlab() => {
+                self.divisor = (self.divisor & 0x00ff) | (u16::from(val) << 8);
+            }
+            IER => {
+                // Enabling the THRE interrupt raises it, as the transmitter is always empty.
+                if val & IER_THRE != 0 && self.ier & IER_THRE == 0 {
+                    self.thre_pending = true;
+                }
+                self.ier = val & IER_MASK;
+            }
+            IIR => {
+                if val & FCR_ENABLE == 0 || val & FCR_CLEAR_RX != 0 {
+                    self.rx.clear();
+                }
+                self.fcr = val;
+            }
+            LCR => self.lcr = val,
+            MCR => {
+                self.mcr = val & MCR_MASK;
+                let lines = if self.mcr & MCR_LOOP != 0 {
+                    Serial::loop_msr(self.mcr)
+                } else {
+                    MSR_CTS | MSR_DSR | MSR_DCD
+                };
+                self.set_msr_lines(lines);
+            }
+            SCR => self.scr = val,
+            // LSR and MSR are read-only.
+            _ => {}
+        }
+    }
+}
+
+impl Device for Serial {
+    /// Read register `offset`, string IO reads the register repeatedly.
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte = self.read_reg(offset);
+        }
+        self.update_irq();
+    }
+
+    /// Write register `offset`, string IO writes the register repeatedly.
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        for byte in data {
+            self.write_reg(offset, *byte);
+        }
+        self.update_irq();
+    }
+}
+
+/// Spawn a thread reading host `input` and queuing it on the shared `serial` port until `input`
+/// reaches end of file or fails.
+pub fn spawn_input<R: Read + Send + 'static>(
+    serial: Arc<Mutex<Serial>>,
+    mut input: R,
+) -> thread::JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        loop {
+            match input.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(len) => serial
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .queue_input(&buf[..len]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Host output shared with the test.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn read(serial: &mut Serial, offset: u64) -> u8 {
+        let mut data = [0u8];
+        serial.read(offset, &mut data);
+        data[0]
+    }
+
+    #[test]
+    fn serial_tx_rx() {
+        let output = Output::default();
+        let mut serial = Serial::new(output.clone());
+
+        assert_eq!(read(&mut serial, LSR), LSR_THRE | LSR_TEMT);
+        serial.write(DATA, b"hello");
+        assert_eq!(output.0.lock().unwrap().as_slice(), b"hello");
+
+        serial.queue_input(b"ab");
+        assert_eq!(read(&mut serial, LSR) & LSR_DR, LSR_DR);
+        let mut data = [0u8; 2];
+        serial.read(DATA, &mut data);
+        assert_eq!(&data, b"ab");
+        assert_eq!(read(&mut serial, LSR) & LSR_DR, 0);
+
+        // Divisor latch and scratch register.
+        serial.write(LCR, &[LCR_DLAB | 0x3]);
+        serial.write(DATA, &[0x01]);
+        serial.write(IER, &[0x00]);
+        serial.write(LCR, &[0x3]);
+        assert_eq!(serial.divisor(), 1);
+        serial.write(SCR, &[0x5a]);
+        assert_eq!(read(&mut serial, SCR), 0x5a);
+        assert_eq!(output.0.lock().unwrap().as_slice(), b"hello");
+
+        // FIFO enable and receiver reset.
+        serial.queue_input(b"x");
+        serial.write(IIR, &[FCR_ENABLE | FCR_CLEAR_RX]);
+        assert_eq!(read(&mut serial, IIR), IIR_FIFO | IIR_NONE);
+        assert_eq!(serial.pending_input(), 0);
+    }
+
+    #[test]
+    fn serial_interrupts() {
+        let mut serial = Serial::new(io::sink());
+        assert_eq!(read(&mut serial, IIR), IIR_NONE);
+
+        // Enabling the THRE interrupt raises it, reading IIR acknowledges it.
+        serial.write(IER, &[IER_RDA | IER_THRE]);
+        assert_eq!(read(&mut serial, IIR), IIR_THRE);
+        assert_eq!(read(&mut serial, IIR), IIR_NONE);
+
+        // Received data has priority over THRE.
+        serial.write(DATA, b"x");
+        serial.queue_input(b"y");
+        assert_eq!(read(&mut serial, IIR), IIR_RDA);
+        assert_eq!(read(&mut serial, DATA), b'y');
+        assert_eq!(read(&mut serial, IIR), IIR_THRE);
+        assert_eq!(read(&mut serial, IIR), IIR_NONE);
+    }
+
+    #[test]
+    fn serial_loopback() {
+        let output = Output::default();
+        let mut serial = Serial::new(output.clone());
+
+        serial.write(IER, &[IER_MSI]);
+        serial.write(MCR, &[MCR_LOOP | MCR_RTS]);
+        // CTS follows RTS, DSR and DCD dropped.
+        assert_eq!(read(&mut serial, IIR), IIR_MSI);
+        assert_eq!(read(&mut serial, MSR), MSR_CTS | 0b1010);
+        assert_eq!(read(&mut serial, IIR), IIR_NONE);
+
+        serial.write(DATA, b"z");
+        serial.queue_input(b"ignored");
+        assert_eq!(read(&mut serial, DATA), b'z');
+        assert_eq!(read(&mut serial, LSR) & LSR_DR, 0);
+        assert!(output.0.lock().unwrap().is_empty());
+    }
+}
@@ -4,10 +4,19 @@
 
 //! VCPU system ioctls.
 
+use std::cell::Cell;
 use std::fs;
 use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 
-use crate::{ioctl, kvm_sys, KvmRun};
+use crate::cpuid::{self, Cpuid};
+use crate::debug::{DebugExit, HwBreakpoint, NR_HW_BREAKPOINTS};
+use crate::irqchip::Lapic;
+use crate::msr::{MsrExit, Msrs};
+use crate::x86_64::idt::GuestException;
+use crate::x86_64::{exception_has_error_code, DR7_FIXED, EXCEPTION_NMI, NR_EXCEPTIONS};
+use crate::{kvm_sys, libcret, Error, KvmRun, PhysAddr, Result};
 
 /// Exit reasons for the [`Vcpu::run`][crate::vcpu::Vcpu::run] function.
 ///
@@ -16,13 +25,228 @@ use crate::{ioctl, kvm_sys, KvmRun};
 ///
 /// [kvm-run]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-run
 /// [kvm-run-struct]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#the-kvm-run-structure
+#[derive(Debug)]
 pub enum KvmExit<'cpu> {
     Halt,
     IoIn(u16, &'cpu mut [u8]),
     IoOut(u16, &'cpu [u8]),
     MmioRead(u64, &'cpu mut [u8]),
     MmioWrite(u64, &'cpu [u8]),
-    Debug(u64),
+    Debug(DebugExit),
+    /// Guest triple fault or INIT (`KVM_EXIT_SHUTDOWN`).
+    Shutdown,
+    /// VM entry failed with the hardware entry failure reason on the given host cpu
+    /// (`KVM_EXIT_FAIL_ENTRY`).
+    FailEntry(u64, u32),
+    /// KVM internal error with suberror and additional data (`KVM_EXIT_INTERNAL_ERROR`).
+    InternalError(u32, &'cpu [u64]),
+    /// System event (`KVM_SYSTEM_EVENT_*`) with additional data (`KVM_EXIT_SYSTEM_EVENT`).
+    SystemEvent(u32, &'cpu [u64]),
+    /// Guest is ready to accept an interrupt (`KVM_EXIT_IRQ_WINDOW_OPEN`), requested with
+    /// [`Vcpu::set_request_interrupt_window`](crate::vcpu::Vcpu::set_request_interrupt_window).
+    IrqWindowOpen,
+    /// `KVM_RUN` was interrupted by a signal or by
+    /// [`VcpuHandle::kick`](crate::vcpu::VcpuHandle::kick) (`KVM_EXIT_INTR`).
+    Interrupted,
+    /// Guest `rdmsr` forwarded to user space, the read value must be provided with
+    /// [`MsrExit::set_data`](crate::msr::MsrExit::set_data) (`KVM_EXIT_X86_RDMSR`).
+    RdMsr(MsrExit<'cpu>),
+    /// Guest `wrmsr` forwarded to user space (`KVM_EXIT_X86_WRMSR`).
+    WrMsr(MsrExit<'cpu>),
+    /// Guest hypercall with number and arguments, the return value must be provided in the last
+    /// argument (`KVM_EXIT_HYPERCALL`).
+    Hypercall(u64, [u64; 6], &'cpu mut u64),
+    /// Guest exception with vector and error code (`KVM_EXIT_EXCEPTION`).
+    Exception(u32, u32),
+    /// Guest exception reported by the default exception handlers, see
+    /// [`Vcpu::set_exception_port`](crate::vcpu::Vcpu::set_exception_port).
+    GuestException(GuestException),
+    /// Exit reason not decoded by this crate.
+    Unknown(u32),
+}
+
+/// Multiprocessing state of a VCPU (`KVM_MP_STATE_*`), see
+/// [`Vcpu::get_mp_state`](crate::vcpu::Vcpu::get_mp_state).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MpState {
+    /// VCPU is running (`KVM_MP_STATE_RUNNABLE`).
+    Runnable,
+    /// Application processor waiting for INIT (`KVM_MP_STATE_UNINITIALIZED`).
+    Uninitialized,
+    /// Application processor received INIT and waits for SIPI (`KVM_MP_STATE_INIT_RECEIVED`).
+    InitReceived,
+    /// VCPU executed `hlt` and waits for an interrupt (`KVM_MP_STATE_HALTED`).
+    Halted,
+    /// Application processor received SIPI (`KVM_MP_STATE_SIPI_RECEIVED`).
+    SipiReceived,
+    /// State not decoded by this crate.
+    Unknown(u32),
+}
+
+impl From<u32> for MpState {
+    fn from(state: u32) -> MpState {
+        match state {
+            kvm_sys::KVM_MP_STATE_RUNNABLE => MpState::Runnable,
+            kvm_sys::KVM_MP_STATE_UNINITIALIZED => MpState::Uninitialized,
+            kvm_sys::KVM_MP_STATE_INIT_RECEIVED => MpState::InitReceived,
+            kvm_sys::KVM_MP_STATE_HALTED => MpState::Halted,
+            kvm_sys::KVM_MP_STATE_SIPI_RECEIVED => MpState::SipiReceived,
+            _ => MpState::Unknown(state),
+        }
+    }
+}
+
+impl From<MpState> for u32 {
+    fn from(state: MpState) -> u32 {
+        match state {
+            MpState::Runnable => kvm_sys::KVM_MP_STATE_RUNNABLE,
+            MpState::Uninitialized => kvm_sys::KVM_MP_STATE_UNINITIALIZED,
+            MpState::InitReceived => kvm_sys::KVM_MP_STATE_INIT_RECEIVED,
+            MpState::Halted => kvm_sys::KVM_MP_STATE_HALTED,
+            MpState::SipiReceived => kvm_sys::KVM_MP_STATE_SIPI_RECEIVED,
+            MpState::Unknown(state) => state,
+        }
+    }
+}
+
+/// Size in bytes of the Kernel signal set passed to `KVM_SET_SIGNAL_MASK`.
+const KERNEL_SIGSET_SIZE: usize = 8;
+
+/// Signal sent by [`VcpuHandle::kick`] to interrupt the thread running a VCPU.
+fn kick_signal() -> libc::c_int {
+    libc::SIGRTMIN()
+}
+
+/// Signal set only containing the [`kick_signal`].
+fn kick_sigset() -> libc::sigset_t {
+    unsafe {
+        let mut set = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, kick_signal());
+        set
+    }
+}
+
+/// Install a no-op handler for the [`kick_signal`] once per process, such that the signal
+/// interrupts `KVM_RUN` instead of terminating the process.
+fn install_kick_handler() -> Result<()> {
+    extern "C" fn handler(_: libc::c_int) {}
+
+    static INSTALLED: OnceLock<Option<i32>> = OnceLock::new();
+
+    let errno = INSTALLED.get_or_init(|| {
+        let mut act: libc::sigaction = unsafe { mem::zeroed() };
+        act.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libcret(unsafe { libc::sigaction(kick_signal(), &act, std::ptr::null_mut()) })
+            .err()
+            .and_then(|err| err.raw_os_error())
+    });
+
+    match errno {
+        Some(errno) => Err(io::Error::from_raw_os_error(*errno).into()),
+        None => Ok(()),
+    }
+}
+
+/// Block the [`kick_signal`] on the calling thread, it is only unblocked during `KVM_RUN` by the
+/// signal mask set with `KVM_SET_SIGNAL_MASK`.
+fn block_kick_signal() -> Result<()> {
+    thread_local! {
+        static BLOCKED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    BLOCKED.with(|blocked| {
+        if !blocked.get() {
+            let set = kick_sigset();
+            match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) } {
+                0 => blocked.set(true),
+                errno => return Err(io::Error::from_raw_os_error(errno).into()),
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Consume a [`kick_signal`] still pending on the calling thread, eg if `KVM_RUN` returned early
+/// due to `immediate_exit` before the signal was delivered.
+fn drain_kick_signal() {
+    let set = kick_sigset();
+    let timeout = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::sigtimedwait(&set, std::ptr::null_mut(), &timeout) };
+}
+
+/// Kick state shared between a [`Vcpu`] and its [`VcpuHandle`]s.
+#[derive(Default)]
+struct KickState {
+    /// Thread currently running the VCPU in `KVM_RUN`.
+    thread: Option<libc::pthread_t>,
+    /// Kick requested but not yet reported as [`KvmExit::Interrupted`].
+    pending: bool,
+}
+
+/// Lock the shared kick state, which stays consistent even if a holder panicked.
+fn lock_kick(kick: &Mutex<KickState>) -> MutexGuard<'_, KickState> {
+    kick.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Handle to kick a [`Vcpu`] out of [`Vcpu::run`] from another thread, obtained with
+/// [`Vcpu::handle`].
+///
+/// ```no_run
+/// # use kvm_rs::vcpu::KvmExit;
+/// # fn main() -> kvm_rs::Result<()> {
+/// # let vm = kvm_rs::kvm::Kvm::new()?.create_vm()?;
+/// let mut vcpu = vm.create_vpcu(0)?;
+/// let handle = vcpu.handle()?;
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(1));
+///     handle.kick()
+/// });
+/// loop {
+///     match vcpu.run()? {
+///         KvmExit::Interrupted => break,
+///         // .. handle other exits.
+///         _ => {}
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct VcpuHandle {
+    id: u64,
+    kick: Arc<Mutex<KickState>>,
+}
+
+impl VcpuHandle {
+    /// Get the id of the VCPU referenced by this handle.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Request the VCPU to return from [`Vcpu::run`] with [`KvmExit::Interrupted`].
+    ///
+    /// If the VCPU is currently running, the running thread is sent a signal which interrupts
+    /// `KVM_RUN`. Otherwise the next [`Vcpu::run`] returns immediately by setting
+    /// `immediate_exit` in the [`kvm_run`][kvm-run-struct] structure. Multiple kicks before the
+    /// VCPU returns are reported as a single exit.
+    ///
+    /// [kvm-run-struct]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#the-kvm-run-structure
+    pub fn kick(&self) -> Result<()> {
+        let mut kick = lock_kick(&self.kick);
+        kick.pending = true;
+
+        if let Some(thread) = kick.thread {
+            match unsafe { libc::pthread_kill(thread, kick_signal()) } {
+                0 => {}
+                errno => return Err(io::Error::from_raw_os_error(errno).into()),
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Wrapper for VCPU ioctls.
@@ -35,24 +259,48 @@ pub enum KvmExit<'cpu> {
 pub struct Vcpu {
     vcpu: fs::File,
     kvm_run: KvmRun,
+    id: u64,
+    single_step: bool,
+    sw_breakpoints: bool,
+    hw_breakpoints: [Option<HwBreakpoint>; NR_HW_BREAKPOINTS],
+    exception_port: Option<u16>,
+    /// Size of the XSAVE state reported by `KVM_CAP_XSAVE2`, `0` if not supported.
+    xsave2_size: usize,
+    /// Kick state shared with the [`VcpuHandle`]s.
+    kick: Arc<Mutex<KickState>>,
+    /// Signal mask for kicks configured, see [`Vcpu::handle`].
+    kickable: bool,
 }
 
 impl Vcpu {
-    pub(crate) fn new(vcpu: fs::File, kvm_run: KvmRun) -> Vcpu {
-        Vcpu { vcpu, kvm_run }
+    pub(crate) fn new(vcpu: fs::File, kvm_run: KvmRun, id: u64, xsave2_size: usize) -> Vcpu {
+        Vcpu {
+            vcpu,
+            kvm_run,
+            id,
+            single_step: false,
+            sw_breakpoints: false,
+            hw_breakpoints: [None; NR_HW_BREAKPOINTS],
+            exception_port: None,
+            xsave2_size,
+            kick: Arc::default(),
+            kickable: false,
+        }
+    }
+
+    /// Get the VCPU id passed to [`Vm::create_vpcu`](crate::vm::Vm::create_vpcu), which is also
+    /// the initial APIC ID.
+    pub fn id(&self) -> u64 {
+        self.id
     }
 
     /// Get the general purpose registers with the [`KVM_GET_REGS`][kvm-get-regs] ioctl in form of
     /// [`kvm_regs`](crate::kvm_sys::kvm_regs).
     ///
     /// [kvm-get-regs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-regs
-    pub fn get_regs(&self) -> io::Result<kvm_sys::kvm_regs> {
+    pub fn get_regs(&self) -> Result<kvm_sys::kvm_regs> {
         let mut regs = kvm_sys::kvm_regs::default();
-        ioctl(
-            &self.vcpu,
-            kvm_sys::KVM_GET_REGS,
-            &mut regs as *mut _ as u64,
-        )?;
+        ioctl!(&self.vcpu, KVM_GET_REGS, &mut regs as *mut _ as u64)?;
         Ok(regs)
     }
 
@@ -60,21 +308,17 @@ impl Vcpu {
     /// [`kvm_regs`](crate::kvm_sys::kvm_regs).
     ///
     /// [kvm-set-regs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-regs
-    pub fn set_regs(&self, regs: kvm_sys::kvm_regs) -> io::Result<()> {
-        ioctl(&self.vcpu, kvm_sys::KVM_SET_REGS, &regs as *const _ as u64).map(|_| ())
+    pub fn set_regs(&self, regs: kvm_sys::kvm_regs) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_REGS, &regs as *const _ as u64).map(|_| ())
     }
 
     /// Get the special registers with the [`KVM_GET_SREGS`][kvm-get-sregs] ioctl in form of
     /// [`kvm_sregs`](crate::kvm_sys::kvm_sregs).
     ///
     /// [kvm-get-sregs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-sregs
-    pub fn get_sregs(&self) -> io::Result<kvm_sys::kvm_sregs> {
+    pub fn get_sregs(&self) -> Result<kvm_sys::kvm_sregs> {
         let mut sregs = kvm_sys::kvm_sregs::default();
-        ioctl(
-            &self.vcpu,
-            kvm_sys::KVM_GET_SREGS,
-            &mut sregs as *mut _ as u64,
-        )?;
+        ioctl!(&self.vcpu, KVM_GET_SREGS, &mut sregs as *mut _ as u64)?;
         Ok(sregs)
     }
 
@@ -82,13 +326,8 @@ impl Vcpu {
     /// [`kvm_sregs`](crate::kvm_sys::kvm_sregs).
     ///
     /// [kvm-set-sregs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-sregs
-    pub fn set_sregs(&self, sregs: kvm_sys::kvm_sregs) -> io::Result<()> {
-        ioctl(
-            &self.vcpu,
-            kvm_sys::KVM_SET_SREGS,
-            &sregs as *const _ as u64,
-        )
-        .map(|_| ())
+    pub fn set_sregs(&self, sregs: kvm_sys::kvm_sregs) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_SREGS, &sregs as *const _ as u64).map(|_| ())
     }
 
     /// Get the debug registers with the [`KVM_GET_DEBUGREGS`][kvm-get-debugregs] ioctl in form of
@@ -97,13 +336,9 @@ impl Vcpu {
     /// [kvm-get-debugregs]:
     /// https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-debugregs
     #[cfg(target_arch = "x86_64")]
-    pub fn get_debugregs(&self) -> io::Result<kvm_sys::kvm_debugregs> {
+    pub fn get_debugregs(&self) -> Result<kvm_sys::kvm_debugregs> {
         let mut dregs = kvm_sys::kvm_debugregs::default();
-        ioctl(
-            &self.vcpu,
-            kvm_sys::KVM_GET_DEBUGREGS,
-            &mut dregs as *mut _ as u64,
-        )?;
+        ioctl!(&self.vcpu, KVM_GET_DEBUGREGS, &mut dregs as *mut _ as u64)?;
         Ok(dregs)
     }
 
@@ -113,49 +348,688 @@ impl Vcpu {
     /// [kvm-set-debugregs]:
     /// https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-debugregs
     #[cfg(target_arch = "x86_64")]
-    pub fn set_debugregs(&self, dregs: kvm_sys::kvm_debugregs) -> io::Result<()> {
-        ioctl(
-            &self.vcpu,
-            kvm_sys::KVM_SET_DEBUGREGS,
-            &dregs as *const _ as u64,
-        )
-        .map(|_| ())
+    pub fn set_debugregs(&self, dregs: kvm_sys::kvm_debugregs) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_DEBUGREGS, &dregs as *const _ as u64).map(|_| ())
+    }
+
+    /// Get the FPU and SSE state with the [`KVM_GET_FPU`][kvm-get-fpu] ioctl in form of
+    /// [`kvm_fpu`](crate::kvm_sys::kvm_fpu).
+    ///
+    /// [kvm-get-fpu]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-fpu
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_fpu(&self) -> Result<kvm_sys::kvm_fpu> {
+        let mut fpu = kvm_sys::kvm_fpu::default();
+        ioctl!(&self.vcpu, KVM_GET_FPU, &mut fpu as *mut _ as u64)?;
+        Ok(fpu)
+    }
+
+    /// Set the FPU and SSE state with the [`KVM_SET_FPU`][kvm-set-fpu] ioctl in form of
+    /// [`kvm_fpu`](crate::kvm_sys::kvm_fpu).
+    ///
+    /// [kvm-set-fpu]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-fpu
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_fpu(&self, fpu: &kvm_sys::kvm_fpu) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_FPU, fpu as *const _ as u64).map(|_| ())
+    }
+
+    /// Get the XSAVE state with the [`KVM_GET_XSAVE`][kvm-get-xsave] ioctl in form of
+    /// [`kvm_xsave`](crate::kvm_sys::kvm_xsave).
+    ///
+    /// Only the first `4K` of the XSAVE state are returned, use [`Vcpu::get_xsave2`] for larger
+    /// states (eg `AMX`).
+    ///
+    /// Requires the [`Xsave`](crate::cap::CapBool::Xsave) capability.
+    ///
+    /// [kvm-get-xsave]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-xsave
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_xsave(&self) -> Result<kvm_sys::kvm_xsave> {
+        let mut xsave = kvm_sys::kvm_xsave::default();
+        ioctl!(&self.vcpu, KVM_GET_XSAVE, &mut xsave as *mut _ as u64)?;
+        Ok(xsave)
+    }
+
+    /// Set the XSAVE state with the [`KVM_SET_XSAVE`][kvm-set-xsave] ioctl in form of
+    /// [`kvm_xsave`](crate::kvm_sys::kvm_xsave).
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if the XSAVE state of the
+    /// VCPU is larger than [`kvm_xsave`](crate::kvm_sys::kvm_xsave), in which case
+    /// [`Vcpu::set_xsave2`] must be used.
+    ///
+    /// Requires the [`Xsave`](crate::cap::CapBool::Xsave) capability.
+    ///
+    /// [kvm-set-xsave]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-xsave
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_xsave(&self, xsave: &kvm_sys::kvm_xsave) -> Result<()> {
+        // KVM reads the full XSAVE state size from the argument.
+        if self.xsave_size() > mem::size_of::<kvm_sys::kvm_xsave>() {
+            return Err(Error::InvalidArgument("XSAVE state larger than kvm_xsave"));
+        }
+
+        ioctl!(&self.vcpu, KVM_SET_XSAVE, xsave as *const _ as u64).map(|_| ())
+    }
+
+    /// Size of the XSAVE state in bytes as used by [`Vcpu::get_xsave2`] and
+    /// [`Vcpu::set_xsave2`].
+    ///
+    /// This is the size reported by the [`Xsave2`](crate::cap::CapInt::Xsave2) capability, but at
+    /// least the size of [`kvm_xsave`](crate::kvm_sys::kvm_xsave).
+    #[cfg(target_arch = "x86_64")]
+    pub fn xsave_size(&self) -> usize {
+        self.xsave2_size.max(mem::size_of::<kvm_sys::kvm_xsave>())
+    }
+
+    /// Get the full XSAVE state of [`Vcpu::xsave_size`] bytes with the
+    /// [`KVM_GET_XSAVE2`][kvm-get-xsave2] ioctl, the layout of the first `4K` matches
+    /// [`kvm_xsave`](crate::kvm_sys::kvm_xsave).
+    ///
+    /// Falls back to the [`KVM_GET_XSAVE`][kvm-get-xsave] ioctl if the
+    /// [`Xsave2`](crate::cap::CapInt::Xsave2) capability is not available.
+    ///
+    /// [kvm-get-xsave2]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-xsave2
+    /// [kvm-get-xsave]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-xsave
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_xsave2(&self) -> Result<Vec<u8>> {
+        let mut xsave = vec![0u8; self.xsave_size()];
+
+        if self.xsave2_size > 0 {
+            ioctl!(&self.vcpu, KVM_GET_XSAVE2, xsave.as_mut_ptr() as u64)?;
+        } else {
+            ioctl!(&self.vcpu, KVM_GET_XSAVE, xsave.as_mut_ptr() as u64)?;
+        }
+        Ok(xsave)
+    }
+
+    /// Set the full XSAVE state as returned by [`Vcpu::get_xsave2`] with the
+    /// [`KVM_SET_XSAVE`][kvm-set-xsave] ioctl.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if `xsave` is not
+    /// [`Vcpu::xsave_size`] bytes.
+    ///
+    /// [kvm-set-xsave]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-xsave
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_xsave2(&self, xsave: &[u8]) -> Result<()> {
+        if xsave.len() != self.xsave_size() {
+            return Err(Error::InvalidArgument("XSAVE state size mismatch"));
+        }
+
+        ioctl!(&self.vcpu, KVM_SET_XSAVE, xsave.as_ptr() as u64).map(|_| ())
+    }
+
+    /// Get the extended control registers (eg `XCR0`) with the [`KVM_GET_XCRS`][kvm-get-xcrs]
+    /// ioctl in form of [`kvm_xcrs`](crate::kvm_sys::kvm_xcrs).
+    ///
+    /// Requires the [`Xcrs`](crate::cap::CapBool::Xcrs) capability.
+    ///
+    /// [kvm-get-xcrs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-xcrs
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_xcrs(&self) -> Result<kvm_sys::kvm_xcrs> {
+        let mut xcrs = kvm_sys::kvm_xcrs::default();
+        ioctl!(&self.vcpu, KVM_GET_XCRS, &mut xcrs as *mut _ as u64)?;
+        Ok(xcrs)
+    }
+
+    /// Set the extended control registers (eg `XCR0`) with the [`KVM_SET_XCRS`][kvm-set-xcrs]
+    /// ioctl in form of [`kvm_xcrs`](crate::kvm_sys::kvm_xcrs).
+    ///
+    /// Requires the [`Xcrs`](crate::cap::CapBool::Xcrs) capability.
+    ///
+    /// [kvm-set-xcrs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-xcrs
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_xcrs(&self, xcrs: &kvm_sys::kvm_xcrs) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_XCRS, xcrs as *const _ as u64).map(|_| ())
+    }
+
+    /// Read the MSRs of all entries in `msrs` with the [`KVM_GET_MSRS`][kvm-get-msrs] ioctl.
+    ///
+    /// Returns the number of MSRs read, reading stops at the first MSR that can not be read.
+    ///
+    /// [kvm-get-msrs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-msrs
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_msrs(&self, msrs: &mut Msrs) -> Result<usize> {
+        ioctl!(&self.vcpu, KVM_GET_MSRS, msrs.as_mut_ptr() as u64).map(|n| n as usize)
+    }
+
+    /// Write the MSRs of all entries in `msrs` with the [`KVM_SET_MSRS`][kvm-set-msrs] ioctl.
+    ///
+    /// Returns the number of MSRs written, writing stops at the first MSR that can not be
+    /// written.
+    ///
+    /// [kvm-set-msrs]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-msrs
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_msrs(&self, msrs: &Msrs) -> Result<usize> {
+        ioctl!(&self.vcpu, KVM_SET_MSRS, msrs.as_ptr() as u64).map(|n| n as usize)
+    }
+
+    /// Set the CPUID leaves reported to the guest with the [`KVM_SET_CPUID2`][kvm-set-cpuid2]
+    /// ioctl.
+    ///
+    /// The CPUID should be configured before the VCPU is run the first time, newer Kernels reject
+    /// changes afterwards.
+    ///
+    /// [kvm-set-cpuid2]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-cpuid2
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_cpuid2(&self, cpuid: &Cpuid) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_CPUID2, cpuid.as_ptr() as u64).map(|_| ())
+    }
+
+    /// Get the CPUID leaves reported to the guest with the [`KVM_GET_CPUID2`][kvm-get-cpuid2]
+    /// ioctl.
+    ///
+    /// [kvm-get-cpuid2]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-cpuid2
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_cpuid2(&self) -> Result<Cpuid> {
+        cpuid::get_cpuid(|cpuid| ioctl!(&self.vcpu, KVM_GET_CPUID2, cpuid))
+    }
+
+    /// Get the local APIC register page with the [`KVM_GET_LAPIC`][kvm-get-lapic] ioctl.
+    ///
+    /// Requires the in-kernel irqchip, see [`Vm::create_irqchip`](crate::vm::Vm::create_irqchip).
+    ///
+    /// [kvm-get-lapic]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-lapic
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_lapic(&self) -> Result<Lapic> {
+        let mut lapic = kvm_sys::kvm_lapic_state::default();
+        ioctl!(&self.vcpu, KVM_GET_LAPIC, &mut lapic as *mut _ as u64)?;
+        Ok(Lapic::new(lapic))
+    }
+
+    /// Set the local APIC register page with the [`KVM_SET_LAPIC`][kvm-set-lapic] ioctl.
+    ///
+    /// Requires the in-kernel irqchip, see [`Vm::create_irqchip`](crate::vm::Vm::create_irqchip).
+    ///
+    /// [kvm-set-lapic]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-lapic
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_lapic(&self, lapic: &Lapic) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_LAPIC, lapic.state() as *const _ as u64).map(|_| ())
+    }
+
+    /// Translate the guest linear address `addr` into a guest physical address with the
+    /// [`KVM_TRANSLATE`][kvm-translate] ioctl, based on the current VCPU paging mode.
+    ///
+    /// Returns `None` if the address is not mapped.
+    ///
+    /// [kvm-translate]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-translate
+    pub fn translate(&self, addr: u64) -> Result<Option<PhysAddr>> {
+        let mut tr = kvm_sys::kvm_translation::default();
+        tr.linear_address = addr;
+        ioctl!(&self.vcpu, KVM_TRANSLATE, &mut tr as *mut _ as u64)?;
+
+        Ok(if tr.valid != 0 {
+            Some(PhysAddr(tr.physical_address))
+        } else {
+            None
+        })
     }
 
     /// Enable or disable guest single steppig (debug) with the
-    /// [`KVM_GUESTDBG_ENABLE`][kvm-guest-debug] ioctl.
+    /// [`KVM_SET_GUEST_DEBUG`][kvm-guest-debug] ioctl.
     ///
     /// [kvm-guest-debug]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-guest-debug
     #[cfg(target_arch = "x86_64")]
-    pub fn set_single_step(&self, enable: bool) -> io::Result<()> {
+    pub fn set_single_step(&mut self, enable: bool) -> Result<()> {
+        self.set_guest_debug(enable, self.sw_breakpoints, &self.hw_breakpoints)?;
+        self.single_step = enable;
+        Ok(())
+    }
+
+    /// Check if guest single stepping is enabled.
+    pub fn single_step(&self) -> bool {
+        self.single_step
+    }
+
+    /// Enable or disable intercepting software breakpoints (`int3`) with the
+    /// [`KVM_SET_GUEST_DEBUG`][kvm-guest-debug] ioctl.
+    ///
+    /// When enabled, `int3` instructions executed by the guest are reported as
+    /// [`KvmExit::Debug`](crate::vcpu::KvmExit::Debug) exits instead of raising `#BP` in the
+    /// guest. Software breakpoints are usually managed with
+    /// [`SwBreakpoints`](crate::debug::SwBreakpoints).
+    ///
+    /// [kvm-guest-debug]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-guest-debug
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_sw_breakpoints(&mut self, enable: bool) -> Result<()> {
+        self.set_guest_debug(self.single_step, enable, &self.hw_breakpoints)?;
+        self.sw_breakpoints = enable;
+        Ok(())
+    }
+
+    /// Check if intercepting software breakpoints is enabled.
+    pub fn sw_breakpoints(&self) -> bool {
+        self.sw_breakpoints
+    }
+
+    /// Install (`Some`) or remove (`None`) the hardware breakpoint in debug register `idx`
+    /// (`DR0-DR3`) with the [`KVM_SET_GUEST_DEBUG`][kvm-guest-debug] ioctl.
+    ///
+    /// Hits are reported as [`KvmExit::Debug`](crate::vcpu::KvmExit::Debug) exits, see
+    /// [`DebugExit::hw_breakpoint`](crate::debug::DebugExit::hw_breakpoint). Execution
+    /// breakpoints trigger before the instruction is executed, hence to resume after a hit the
+    /// breakpoint must be removed while single stepping over the instruction.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if `idx` is not a valid
+    /// debug register index or if a watchpoint address is not aligned to its length.
+    ///
+    /// [kvm-guest-debug]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-guest-debug
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_hw_breakpoint(&mut self, idx: usize, bp: Option<HwBreakpoint>) -> Result<()> {
+        if idx >= NR_HW_BREAKPOINTS {
+            return Err(Error::InvalidArgument("invalid hardware breakpoint index"));
+        }
+        if matches!(bp, Some(bp) if !bp.is_aligned()) {
+            return Err(Error::InvalidArgument("unaligned hardware watchpoint"));
+        }
+
+        let mut hw_breakpoints = self.hw_breakpoints;
+        hw_breakpoints[idx] = bp;
+        self.set_guest_debug(self.single_step, self.sw_breakpoints, &hw_breakpoints)?;
+        self.hw_breakpoints = hw_breakpoints;
+        Ok(())
+    }
+
+    /// Get the hardware breakpoints currently installed in the debug registers `DR0-DR3`.
+    pub fn hw_breakpoints(&self) -> &[Option<HwBreakpoint>; NR_HW_BREAKPOINTS] {
+        &self.hw_breakpoints
+    }
+
+    /// Enable (`Some`) or disable (`None`) decoding guest exceptions reported on the IO `port`
+    /// by the default exception handlers (see [`idt`](crate::x86_64::idt)) into
+    /// [`KvmExit::GuestException`](crate::vcpu::KvmExit::GuestException) exits.
+    ///
+    /// Usually the port is [`EXCEPTION_PORT`](crate::x86_64::idt::EXCEPTION_PORT).
+    pub fn set_exception_port(&mut self, port: Option<u16>) {
+        self.exception_port = port;
+    }
+
+    /// Get the IO port used to decode guest exceptions.
+    pub fn exception_port(&self) -> Option<u16> {
+        self.exception_port
+    }
+
+    /// Apply the debug configuration (single stepping, software and hardware breakpoints) with
+    /// the [`KVM_SET_GUEST_DEBUG`][kvm-guest-debug] ioctl.
+    ///
+    /// The configuration is passed explicitly, such that callers only update the cached state
+    /// once the ioctl succeeded.
+    ///
+    /// [kvm-guest-debug]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-guest-debug
+    #[cfg(target_arch = "x86_64")]
+    fn set_guest_debug(
+        &self,
+        single_step: bool,
+        sw_breakpoints: bool,
+        hw_breakpoints: &[Option<HwBreakpoint>; NR_HW_BREAKPOINTS],
+    ) -> Result<()> {
         let mut dbg = kvm_sys::kvm_guest_debug::default();
 
-        if enable {
+        if single_step {
             // Enable guest debugging and single stepping.
-            dbg.control = kvm_sys::KVM_GUESTDBG_ENABLE | kvm_sys::KVM_GUESTDBG_SINGLESTEP;
+            dbg.control |= kvm_sys::KVM_GUESTDBG_ENABLE | kvm_sys::KVM_GUESTDBG_SINGLESTEP;
+        }
+
+        if sw_breakpoints {
+            // Enable guest debugging and intercept `int3` instructions.
+            dbg.control |= kvm_sys::KVM_GUESTDBG_ENABLE | kvm_sys::KVM_GUESTDBG_USE_SW_BP;
+        }
+
+        if hw_breakpoints.iter().any(Option::is_some) {
+            // Enable guest debugging with the hardware breakpoints from the debug registers.
+            dbg.control |= kvm_sys::KVM_GUESTDBG_ENABLE | kvm_sys::KVM_GUESTDBG_USE_HW_BP;
+
+            dbg.arch.debugreg[7] = DR7_FIXED;
+            for (idx, bp) in hw_breakpoints.iter().enumerate() {
+                if let Some(bp) = bp {
+                    dbg.arch.debugreg[idx] = bp.addr();
+                    dbg.arch.debugreg[7] |= bp.dr7(idx);
+                }
+            }
+        } else {
+            // Initialize debug registers based on current VCPUs debug register values.
+            let dregs = self.get_debugregs()?;
+            dbg.arch.debugreg[0..4].copy_from_slice(&dregs.db);
+            // DR4-DR5 are reserved.
+            dbg.arch.debugreg[6] = dregs.dr6;
+            dbg.arch.debugreg[7] = dregs.dr7;
         }
 
-        // Initialize debug registers based on current VCPUs debug register values.
-        let dregs = self.get_debugregs()?;
-        dbg.arch.debugreg[0..4].copy_from_slice(&dregs.db);
-        // DR4-DR5 are reserved.
-        dbg.arch.debugreg[6] = dregs.dr6;
-        dbg.arch.debugreg[7] = dregs.dr7;
+        ioctl!(&self.vcpu, KVM_SET_GUEST_DEBUG, &dbg as *const _ as u64).map(|_| ())
+    }
 
-        ioctl(
+    /// Inject the external interrupt `vector` with the [`KVM_INTERRUPT`][kvm-interrupt] ioctl.
+    ///
+    /// Only available without the in-kernel irqchip. The interrupt must only be injected if the
+    /// guest is ready to accept it, see [`ready_for_interrupt`](crate::vcpu::Vcpu::ready_for_interrupt).
+    ///
+    /// [kvm-interrupt]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-interrupt
+    pub fn interrupt(&self, vector: u8) -> Result<()> {
+        let irq = kvm_sys::kvm_interrupt {
+            irq: u32::from(vector),
+        };
+
+        ioctl!(&self.vcpu, KVM_INTERRUPT, &irq as *const _ as u64).map(|_| ())
+    }
+
+    /// Inject the external interrupt `vector` if the guest is ready to accept it, otherwise
+    /// request a [`KvmExit::IrqWindowOpen`](crate::vcpu::KvmExit::IrqWindowOpen) exit once the
+    /// guest can accept interrupts.
+    ///
+    /// Returns `true` if the interrupt was injected. Only available without the in-kernel
+    /// irqchip.
+    pub fn try_interrupt(&mut self, vector: u8) -> Result<bool> {
+        let ready = self.ready_for_interrupt();
+        if ready {
+            self.interrupt(vector)?;
+        }
+        self.set_request_interrupt_window(!ready);
+        Ok(ready)
+    }
+
+    /// Request a [`KvmExit::IrqWindowOpen`](crate::vcpu::KvmExit::IrqWindowOpen) exit as soon as
+    /// the guest can accept interrupts (`kvm_run.request_interrupt_window`).
+    ///
+    /// The request stays active for subsequent [`run`](crate::vcpu::Vcpu::run) calls until
+    /// cleared.
+    pub fn set_request_interrupt_window(&mut self, request: bool) {
+        self.kvm_run.as_mut().request_interrupt_window = request as u8;
+    }
+
+    /// Check if an interrupt can be injected with [`interrupt`](crate::vcpu::Vcpu::interrupt)
+    /// (`kvm_run.ready_for_interrupt_injection` and `kvm_run.if_flag`).
+    ///
+    /// Reflects the guest state of the last exit.
+    pub fn ready_for_interrupt(&self) -> bool {
+        let kvm_run = self.kvm_run.as_ref();
+        kvm_run.ready_for_interrupt_injection != 0 && kvm_run.if_flag != 0
+    }
+
+    /// Guest `rflags.IF` on the last exit (`kvm_run.if_flag`), only valid without the in-kernel
+    /// irqchip.
+    pub fn if_flag(&self) -> bool {
+        self.kvm_run.as_ref().if_flag != 0
+    }
+
+    /// Get pending and injected exceptions, interrupts and NMIs with the
+    /// [`KVM_GET_VCPU_EVENTS`][kvm-get-vcpu-events] ioctl.
+    ///
+    /// Requires the [`VcpuEvents`](crate::cap::CapBool::VcpuEvents) capability.
+    ///
+    /// [kvm-get-vcpu-events]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-vcpu-events
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_vcpu_events(&self) -> Result<kvm_sys::kvm_vcpu_events> {
+        let mut events = kvm_sys::kvm_vcpu_events::default();
+        ioctl!(
             &self.vcpu,
-            kvm_sys::KVM_SET_GUEST_DEBUG,
-            &dbg as *const _ as u64,
-        )
-        .map(|_| ())
+            KVM_GET_VCPU_EVENTS,
+            &mut events as *mut _ as u64
+        )?;
+        Ok(events)
+    }
+
+    /// Set pending and injected exceptions, interrupts and NMIs with the
+    /// [`KVM_SET_VCPU_EVENTS`][kvm-set-vcpu-events] ioctl.
+    ///
+    /// The NMI pending state, SIPI vector, interrupt shadow and SMM state are only applied if
+    /// the corresponding `KVM_VCPUEVENT_VALID_*` bit is set in `flags`, which is the case for
+    /// events obtained with [`get_vcpu_events`](crate::vcpu::Vcpu::get_vcpu_events).
+    ///
+    /// Requires the [`VcpuEvents`](crate::cap::CapBool::VcpuEvents) capability.
+    ///
+    /// [kvm-set-vcpu-events]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-vcpu-events
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_vcpu_events(&self, events: &kvm_sys::kvm_vcpu_events) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_SET_VCPU_EVENTS, events as *const _ as u64).map(|_| ())
+    }
+
+    /// Inject an NMI with the [`KVM_NMI`][kvm-nmi] ioctl, which is delivered on the next
+    /// [`run`](crate::vcpu::Vcpu::run) unless NMIs are blocked by the guest.
+    ///
+    /// [kvm-nmi]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-nmi
+    #[cfg(target_arch = "x86_64")]
+    pub fn inject_nmi(&self) -> Result<()> {
+        ioctl!(&self.vcpu, KVM_NMI, 0).map(|_| ())
+    }
+
+    /// Inject the exception `vector` with `error_code` into the guest, which is delivered on the
+    /// next [`run`](crate::vcpu::Vcpu::run), see
+    /// [`set_vcpu_events`](crate::vcpu::Vcpu::set_vcpu_events).
+    ///
+    /// For a page fault, the faulting address must be set in `cr2` with
+    /// [`set_sregs`](crate::vcpu::Vcpu::set_sregs) before.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if `vector` is not an
+    /// exception vector or `error_code` does not match whether the exception pushes an error
+    /// code.
+    #[cfg(target_arch = "x86_64")]
+    pub fn inject_exception(&self, vector: u8, error_code: Option<u32>) -> Result<()> {
+        if vector >= NR_EXCEPTIONS || vector == EXCEPTION_NMI {
+            return Err(Error::InvalidArgument("not an exception vector"));
+        }
+        if exception_has_error_code(vector) != error_code.is_some() {
+            return Err(Error::InvalidArgument(
+                "error code does not match exception vector",
+            ));
+        }
+
+        let mut events = self.get_vcpu_events()?;
+        events.exception.injected = 1;
+        events.exception.nr = vector;
+        events.exception.has_error_code = error_code.is_some() as u8;
+        events.exception.error_code = error_code.unwrap_or(0);
+        events.exception.pending = 0;
+
+        self.set_vcpu_events(&events)
+    }
+
+    /// Get the multiprocessing state with the [`KVM_GET_MP_STATE`][kvm-get-mp-state] ioctl.
+    ///
+    /// With the in-kernel irqchip, VCPU `0` starts as bootstrap processor in
+    /// [`MpState::Runnable`], all other VCPUs start as application processors in
+    /// [`MpState::Uninitialized`] and block in [`run`](crate::vcpu::Vcpu::run) until they are
+    /// started by INIT and SIPI IPIs sent through the local APIC. KVM only delivers the IPIs to
+    /// software enabled local APICs, see [`Lapic::set_enabled`](crate::irqchip::Lapic::set_enabled).
+    ///
+    /// Requires the [`MpState`](crate::cap::CapBool::MpState) capability.
+    ///
+    /// [kvm-get-mp-state]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-mp-state
+    pub fn get_mp_state(&self) -> Result<MpState> {
+        let mut mp_state = kvm_sys::kvm_mp_state::default();
+        ioctl!(&self.vcpu, KVM_GET_MP_STATE, &mut mp_state as *mut _ as u64)?;
+        Ok(mp_state.mp_state.into())
+    }
+
+    /// Set the multiprocessing state with the [`KVM_SET_MP_STATE`][kvm-set-mp-state] ioctl, eg
+    /// to start an application processor without INIT and SIPI.
+    ///
+    /// Requires the [`MpState`](crate::cap::CapBool::MpState) capability.
+    ///
+    /// [kvm-set-mp-state]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-mp-state
+    pub fn set_mp_state(&self, state: MpState) -> Result<()> {
+        let mp_state = kvm_sys::kvm_mp_state {
+            mp_state: state.into(),
+        };
+
+        ioctl!(&self.vcpu, KVM_SET_MP_STATE, &mp_state as *const _ as u64).map(|_| ())
+    }
+
+    /// Get a [`VcpuHandle`] to kick the VCPU out of [`run`](crate::vcpu::Vcpu::run) from another
+    /// thread.
+    ///
+    /// The first call installs a no-op handler for the kick signal (`SIGRTMIN`) and sets the
+    /// signal mask used during `KVM_RUN` with the [`KVM_SET_SIGNAL_MASK`][kvm-set-signal-mask]
+    /// ioctl to the signal mask of the calling thread with the kick signal unblocked. Threads
+    /// running the VCPU block the kick signal outside of `KVM_RUN`, such that a kick sent right
+    /// before entering the guest is not lost.
+    ///
+    /// [kvm-set-signal-mask]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-signal-mask
+    pub fn handle(&mut self) -> Result<VcpuHandle> {
+        if !self.kickable {
+            install_kick_handler()?;
+
+            let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+            match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, std::ptr::null(), &mut mask) } {
+                0 => {}
+                errno => return Err(io::Error::from_raw_os_error(errno).into()),
+            }
+            unsafe { libc::sigdelset(&mut mask, kick_signal()) };
+
+            #[repr(C)]
+            struct SignalMask {
+                mask: kvm_sys::kvm_signal_mask,
+                sigset: [u8; KERNEL_SIGSET_SIZE],
+            }
+
+            let mut sigmask = SignalMask {
+                mask: kvm_sys::kvm_signal_mask {
+                    len: KERNEL_SIGSET_SIZE as u32,
+                    sigset: [],
+                },
+                sigset: [0; KERNEL_SIGSET_SIZE],
+            };
+            // The libc `sigset_t` starts with the signal bits of the Kernel signal set.
+            sigmask.sigset.copy_from_slice(unsafe {
+                std::slice::from_raw_parts(&mask as *const _ as *const u8, KERNEL_SIGSET_SIZE)
+            });
+
+            ioctl!(&self.vcpu, KVM_SET_SIGNAL_MASK, &sigmask as *const _ as u64)?;
+            self.kickable = true;
+        }
+
+        Ok(VcpuHandle {
+            id: self.id,
+            kick: self.kick.clone(),
+        })
+    }
+
+    /// Drop a pending kick which was not yet reported as
+    /// [`KvmExit::Interrupted`](crate::vcpu::KvmExit::Interrupted).
+    ///
+    /// A kick signal which raced with `KVM_RUN` returning stays pending on the thread running the
+    /// VCPU, it is consumed as well if called on that thread.
+    pub(crate) fn clear_kick(&mut self) {
+        lock_kick(&self.kick).pending = false;
+        drain_kick_signal();
     }
 
     /// Run the guest VCPU with the [`KVM_RUN`][kvm-run] ioctl until it exits with one of the exit
     /// reasons described in [`KvmExit`](crate::vcpu::KvmExit).
     ///
+    /// A signal or [`VcpuHandle::kick`] interrupting `KVM_RUN` is reported as
+    /// [`KvmExit::Interrupted`](crate::vcpu::KvmExit::Interrupted).
+    ///
+    /// Exit reasons not known to this crate are reported as
+    /// [`KvmExit::Unknown`](crate::vcpu::KvmExit::Unknown). Returns
+    /// [`Error::UnknownExit`](crate::Error::UnknownExit) if the exit information of a known exit
+    /// reason can not be decoded.
+    ///
+    /// [kvm-run]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-run
+    pub fn run(&mut self) -> Result<KvmExit<'_>> {
+        self.enter()?;
+        self.exit()
+    }
+
+    /// Run the guest VCPU with the [`KVM_RUN`][kvm-run] ioctl without decoding the exit reason.
+    ///
+    /// [kvm-run]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-run
+    pub(crate) fn enter(&mut self) -> Result<()> {
+        if self.kickable {
+            block_kick_signal()?;
+
+            let mut kick = lock_kick(&self.kick);
+            kick.thread = Some(unsafe { libc::pthread_self() });
+            // Return right away for kicks requested while the VCPU was not running.
+            self.kvm_run.as_mut().immediate_exit = kick.pending as u8;
+        }
+
+        let ret = loop {
+            match ioctl!(&self.vcpu, KVM_RUN, 0) {
+                // An application processor blocked in `KVM_RUN` returns `EAGAIN` after it
+                // received INIT, re-enter to wait for SIPI.
+                Err(Error::Ioctl(_, err)) if err.raw_os_error() == Some(libc::EAGAIN) => {}
+                ret => break ret,
+            }
+        };
+
+        let interrupted =
+            matches!(&ret, Err(Error::Ioctl(_, err)) if err.raw_os_error() == Some(libc::EINTR));
+
+        if self.kickable {
+            let mut kick = lock_kick(&self.kick);
+            kick.thread = None;
+
+            if interrupted && kick.pending {
+                kick.pending = false;
+                self.kvm_run.as_mut().immediate_exit = 0;
+                drain_kick_signal();
+            }
+        }
+
+        if interrupted {
+            // `KVM_RUN` returns `EINTR` without guaranteeing the exit reason, eg for
+            // `immediate_exit`.
+            self.kvm_run.as_mut().exit_reason = kvm_sys::KVM_EXIT_INTR as u32;
+            return Ok(());
+        }
+        ret.map(|_| ())
+    }
+
+    /// Get the debug information if the VCPU last exited with `KVM_EXIT_DEBUG`.
+    pub(crate) fn debug_exit(&self) -> Option<DebugExit> {
+        let kvm_run = self.kvm_run.as_ref();
+
+        if kvm_run.exit_reason as u64 != kvm_sys::KVM_EXIT_DEBUG {
+            return None;
+        }
+
+        // Safe to use union `debug` field, as Kernel instructed us to.
+        let debug = unsafe { kvm_run.inner.debug };
+
+        Some(DebugExit {
+            exception: debug.exception,
+            pc: debug.pc,
+            dr6: debug.dr6,
+            dr7: debug.dr7,
+        })
+    }
+
+    /// Get the guest exception if the VCPU last exited with a 4 byte `KVM_EXIT_IO` write to the
+    /// exception port.
+    fn guest_exception(&self) -> Result<Option<GuestException>> {
+        let kvm_run = self.kvm_run.as_ref();
+
+        if kvm_run.exit_reason as u64 != kvm_sys::KVM_EXIT_IO {
+            return Ok(None);
+        }
+
+        // Safe to use union `io` field, as Kernel instructed us to.
+        let io = unsafe { kvm_run.inner.io };
+
+        if Some(io.port) != self.exception_port
+            || io.direction as u64 != kvm_sys::KVM_EXIT_IO_OUT
+            || (io.size, io.count) != (4, 1)
+        {
+            return Ok(None);
+        }
+
+        // The default exception handlers provide the vector in `eax`, the error code in `rsi`
+        // and the faulting instruction pointer in `rdi`.
+        let regs = self.get_regs()?;
+        Ok(Some(GuestException {
+            vector: regs.rax as u8,
+            error_code: regs.rsi,
+            rip: regs.rdi,
+        }))
+    }
+
+    /// Decode the exit reason of the last [`KVM_RUN`][kvm-run] ioctl.
+    ///
     /// [kvm-run]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-run
-    pub fn run(&mut self) -> io::Result<KvmExit<'_>> {
-        ioctl(&self.vcpu, kvm_sys::KVM_RUN, 0)?;
+    pub(crate) fn exit(&mut self) -> Result<KvmExit<'_>> {
+        if let Some(debug) = self.debug_exit() {
+            return Ok(KvmExit::Debug(debug));
+        }
+
+        if let Some(exception) = self.guest_exception()? {
+            return Ok(KvmExit::GuestException(exception));
+        }
 
         let kvm_run = self.kvm_run.as_mut();
 
@@ -178,7 +1052,7 @@ impl Vcpu {
                 match io.direction as u64 {
                     kvm_sys::KVM_EXIT_IO_IN => Ok(KvmExit::IoIn(io.port, data)),
                     kvm_sys::KVM_EXIT_IO_OUT => Ok(KvmExit::IoOut(io.port, data)),
-                    _ => unreachable!(),
+                    _ => Err(Error::UnknownExit(kvm_run.exit_reason)),
                 }
             }
             kvm_sys::KVM_EXIT_MMIO => {
@@ -189,18 +1063,99 @@ impl Vcpu {
                 match mmio.is_write {
                     0 => Ok(KvmExit::MmioRead(mmio.phys_addr, &mut mmio.data[..len])),
                     1 => Ok(KvmExit::MmioWrite(mmio.phys_addr, &mmio.data[..len])),
-                    _ => unreachable!(),
+                    _ => Err(Error::UnknownExit(kvm_run.exit_reason)),
                 }
             }
-            kvm_sys::KVM_EXIT_DEBUG => {
-                // Safe to use union `debug` field, as Kernel instructed us to.
-                let debug = unsafe { kvm_run.inner.debug };
+            kvm_sys::KVM_EXIT_SHUTDOWN => Ok(KvmExit::Shutdown),
+            kvm_sys::KVM_EXIT_FAIL_ENTRY => {
+                // Safe to use union `fail_entry` field, as Kernel instructed us to.
+                let fail_entry = unsafe { kvm_run.inner.fail_entry };
+
+                Ok(KvmExit::FailEntry(
+                    fail_entry.hardware_entry_failure_reason,
+                    fail_entry.cpu,
+                ))
+            }
+            kvm_sys::KVM_EXIT_INTERNAL_ERROR => {
+                // Safe to use union `internal` field, as Kernel instructed us to.
+                let internal = unsafe { &kvm_run.inner.internal };
+                let ndata = (internal.ndata as usize).min(internal.data.len());
 
-                Ok(KvmExit::Debug(debug.pc))
+                Ok(KvmExit::InternalError(
+                    internal.suberror,
+                    &internal.data[..ndata],
+                ))
             }
-            r @ _ => {
-                todo!("KVM_EXIT_... (exit_reason={}) not implemented!", r)
+            kvm_sys::KVM_EXIT_SYSTEM_EVENT => {
+                // Safe to use union `system_event` field, as Kernel instructed us to.
+                let event = unsafe { &kvm_run.inner.system_event };
+                let ndata = (event.ndata as usize).min(event.data.len());
+
+                Ok(KvmExit::SystemEvent(event.type_, &event.data[..ndata]))
+            }
+            kvm_sys::KVM_EXIT_IRQ_WINDOW_OPEN => Ok(KvmExit::IrqWindowOpen),
+            kvm_sys::KVM_EXIT_INTR => Ok(KvmExit::Interrupted),
+            kvm_sys::KVM_EXIT_X86_RDMSR => {
+                // Safe to use union `msr` field, as Kernel instructed us to.
+                let msr = unsafe { &mut kvm_run.inner.msr };
+
+                Ok(KvmExit::RdMsr(MsrExit::new(msr)))
+            }
+            kvm_sys::KVM_EXIT_X86_WRMSR => {
+                // Safe to use union `msr` field, as Kernel instructed us to.
+                let msr = unsafe { &mut kvm_run.inner.msr };
+
+                Ok(KvmExit::WrMsr(MsrExit::new(msr)))
             }
+            kvm_sys::KVM_EXIT_HYPERCALL => {
+                // Safe to use union `hypercall` field, as Kernel instructed us to.
+                let hypercall = unsafe { &mut kvm_run.inner.hypercall };
+
+                Ok(KvmExit::Hypercall(
+                    hypercall.nr,
+                    hypercall.args,
+                    &mut hypercall.ret,
+                ))
+            }
+            kvm_sys::KVM_EXIT_EXCEPTION => {
+                // Safe to use union `ex` field, as Kernel instructed us to.
+                let ex = unsafe { kvm_run.inner.ex };
+
+                Ok(KvmExit::Exception(ex.exception, ex.error_code))
+            }
+            _ => Ok(KvmExit::Unknown(kvm_run.exit_reason)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::FromRawFd;
+
+    /// VCPU backed by a memfd instead of a KVM VCPU fd, all VCPU ioctls fail with `ENOTTY`.
+    fn fake_vcpu() -> Vcpu {
+        let fd = unsafe { libc::memfd_create(b"vcpu\0".as_ptr().cast(), 0) };
+        assert!(fd >= 0);
+        let file = unsafe { fs::File::from_raw_fd(fd) };
+        file.set_len(0x1000).unwrap();
+
+        let kvm_run = KvmRun::new(&file, 0x1000).unwrap();
+        Vcpu::new(file, kvm_run, 0, 0)
+    }
+
+    #[test]
+    fn guest_debug_state_on_error() {
+        let mut vcpu = fake_vcpu();
+
+        assert!(vcpu.set_single_step(true).is_err());
+        assert!(!vcpu.single_step());
+
+        assert!(vcpu.set_sw_breakpoints(true).is_err());
+        assert!(!vcpu.sw_breakpoints());
+
+        let bp = HwBreakpoint::Exec(0x1000);
+        assert!(vcpu.set_hw_breakpoint(0, Some(bp)).is_err());
+        assert_eq!(vcpu.hw_breakpoints(), &[None; NR_HW_BREAKPOINTS]);
+    }
+}
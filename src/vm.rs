@@ -4,12 +4,97 @@
 
 //! VM system ioctls.
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::io;
+use std::ops;
 use std::os::unix::io::FromRawFd;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 
-use crate::vcpu::Vcpu;
-use crate::{ioctl, kvm_sys, KvmRun, PhysAddr, UserMem};
+use crate::cap::{CapBool, CapInt};
+use crate::irqchip::{self, IrqChip, IrqChipState, IrqLine, NR_IRQS};
+use crate::msr::{MsrExitReason, MsrFilter};
+use crate::vcpu::{KvmExit, Vcpu};
+use crate::{kvm_sys, Error, KvmRun, PhysAddr, Result, UserMem};
+
+/// Flags for memory regions mapped with
+/// [`Vm::set_user_memory_region`](crate::vm::Vm::set_user_memory_region).
+///
+/// Flags can be combined with the `|` operator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemFlags(u32);
+
+impl MemFlags {
+    /// Regular guest memory which is readable and writeable by the guest.
+    pub const NONE: MemFlags = MemFlags(0);
+    /// Guest memory is readonly (`KVM_MEM_READONLY`).
+    ///
+    /// Guest reads are served from the memory region, while guest writes trigger a
+    /// [`KvmExit::MmioWrite`](crate::vcpu::KvmExit::MmioWrite) exit and leave the memory
+    /// untouched. Requires the [`ReadonlyMem`](crate::cap::CapBool::ReadonlyMem) capability.
+    pub const READONLY: MemFlags = MemFlags(kvm_sys::KVM_MEM_READONLY);
+    /// Track guest writes to the memory region (`KVM_MEM_LOG_DIRTY_PAGES`).
+    ///
+    /// Pages written by the guest can be queried with
+    /// [`Vm::get_dirty_log`](crate::vm::Vm::get_dirty_log).
+    pub const LOG_DIRTY_PAGES: MemFlags = MemFlags(kvm_sys::KVM_MEM_LOG_DIRTY_PAGES);
+
+    /// Check if all flags in `other` are set.
+    pub fn contains(self, other: MemFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for MemFlags {
+    type Output = MemFlags;
+
+    fn bitor(self, rhs: MemFlags) -> MemFlags {
+        MemFlags(self.0 | rhs.0)
+    }
+}
+
+/// Size of a guest page as tracked by the dirty log.
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Bitmap of guest pages written since the dirty log of a memory slot was last fetched or
+/// cleared.
+///
+/// Bit `n` corresponds to the `n`-th page (`4K`) of the memory slot, relative to the slots guest
+/// physical start address.
+pub struct DirtyBitmap {
+    bitmap: Vec<u64>,
+    pages: usize,
+}
+
+impl DirtyBitmap {
+    fn new(pages: usize) -> DirtyBitmap {
+        DirtyBitmap {
+            bitmap: vec![0; pages.div_ceil(64)],
+            pages,
+        }
+    }
+
+    /// Number of pages covered by the bitmap.
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Check if the page with index `page` is dirty.
+    pub fn is_dirty(&self, page: usize) -> bool {
+        page < self.pages && self.bitmap[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    /// Iterator over the indexes of all dirty pages.
+    pub fn dirty_pages(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.pages).filter(move |&page| self.is_dirty(page))
+    }
+
+    /// Raw bitmap words as returned by KVM.
+    pub fn as_slice(&self) -> &[u64] {
+        &self.bitmap
+    }
+}
 
 /// Wrapper for VM ioctls.
 ///
@@ -21,53 +106,608 @@ use crate::{ioctl, kvm_sys, KvmRun, PhysAddr, UserMem};
 pub struct Vm {
     vm: fs::File,
     vcpu_mmap_size: usize,
+    nr_memslots: u32,
+    memslots: BTreeMap<u32, MemSlot>,
+    manual_dirty_log_protect: bool,
+}
+
+/// Number of memory slots assumed if `KVM_CAP_NR_MEMSLOTS` is not available.
+const DEFAULT_NR_MEMSLOTS: u32 = 32;
+
+/// Guest physical address range of a memory slot currently mapped into the VM.
+#[derive(Clone, Copy)]
+struct MemSlot {
+    phys_addr: u64,
+    len: u64,
+    userspace_addr: u64,
+    flags: MemFlags,
+}
+
+impl MemSlot {
+    fn overlaps(&self, phys_addr: u64, len: u64) -> bool {
+        self.phys_addr < phys_addr + len && phys_addr < self.phys_addr + self.len
+    }
+
+    /// Check if the slot can be changed to `other` in place, which is the case if only the
+    /// [`MemFlags::LOG_DIRTY_PAGES`] flag differs. KVM rejects any other change of an existing
+    /// slot.
+    fn updates_in_place(&self, other: &MemSlot) -> bool {
+        self.phys_addr == other.phys_addr
+            && self.len == other.len
+            && self.userspace_addr == other.userspace_addr
+            && self.flags.contains(MemFlags::READONLY) == other.flags.contains(MemFlags::READONLY)
+    }
 }
 
 impl Vm {
-    pub(crate) fn new(vm: fs::File, vcpu_mmap_size: usize) -> Vm {
-        Vm { vm, vcpu_mmap_size }
+    pub(crate) fn new(vm: fs::File, vcpu_mmap_size: usize, nr_memslots: i32) -> Vm {
+        let nr_memslots = if nr_memslots > 0 {
+            nr_memslots as u32
+        } else {
+            DEFAULT_NR_MEMSLOTS
+        };
+
+        Vm {
+            vm,
+            vcpu_mmap_size,
+            nr_memslots,
+            memslots: BTreeMap::new(),
+            manual_dirty_log_protect: false,
+        }
+    }
+
+    /// Check availability of an extension on the VM with the
+    /// [`KVM_CHECK_EXTENSION`][kvm-check-extension] ioctl.
+    ///
+    /// Requires the [`CheckExtensionVm`](crate::cap::CapBool::CheckExtensionVm) capability.
+    ///
+    /// [kvm-check-extension]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-check-extension
+    pub fn check_extenstion(&self, cap: CapBool) -> Result<bool> {
+        ioctl!(&self.vm, KVM_CHECK_EXTENSION, cap.into()).map(|ret| ret > 0)
+    }
+
+    /// Check availability of an extension on the VM with the
+    /// [`KVM_CHECK_EXTENSION`][kvm-check-extension] ioctl.
+    ///
+    /// Requires the [`CheckExtensionVm`](crate::cap::CapBool::CheckExtensionVm) capability.
+    ///
+    /// [kvm-check-extension]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-check-extension
+    pub fn check_extenstion_int(&self, cap: CapInt) -> Result<i32> {
+        ioctl!(&self.vm, KVM_CHECK_EXTENSION, cap.into())
     }
 
     /// Map memory from userspace into the VM as `guest physical` memory starting at address
-    /// `phys_addr`.
+    /// `phys_addr` using the memory slot `slot` and the memory region flags `flags`.
     /// The underlying operation is the [`KVM_SET_USER_MEMORY_REGION`][kvm-set-user-memory-region]
     /// ioctl.
     ///
+    /// If `slot` is already in use, the existing mapping is removed before the new mapping is
+    /// created, which allows to resize or move a memory slot. If only
+    /// [`MemFlags::LOG_DIRTY_PAGES`](crate::vm::MemFlags::LOG_DIRTY_PAGES) changes, the existing
+    /// mapping is updated in place. If creating the new mapping fails, the old mapping is
+    /// restored if possible, otherwise `slot` is left unused.
+    ///
+    /// Returns [`Error::MemSlotLimit`](crate::Error::MemSlotLimit) if `slot` exceeds the number of
+    /// memory slots supported by KVM (`KVM_CAP_NR_MEMSLOTS`),
+    /// [`Error::MemSlotOverlap`](crate::Error::MemSlotOverlap) if the guest physical range
+    /// overlaps with a different slot, [`Error::InvalidArgument`](crate::Error::InvalidArgument) if
+    /// the guest physical range exceeds the address space and
+    /// [`Error::UnsupportedCap`](crate::Error::UnsupportedCap) if `flags` requests
+    /// [`MemFlags::READONLY`](crate::vm::MemFlags::READONLY) but the
+    /// [`ReadonlyMem`](crate::cap::CapBool::ReadonlyMem) capability is not available.
+    ///
     /// # Safety
     ///
     /// The `mem: &UserMem` argument passed to this function must at least live as long the `Vcpu`
-    /// instance.
+    /// instance or until the memory slot is removed with
+    /// [`delete_user_memory_region`](crate::vm::Vm::delete_user_memory_region).
     ///
     /// [kvm-set-user-memory-region]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-user-memory-region
     pub unsafe fn set_user_memory_region(
-        &self,
+        &mut self,
+        slot: u32,
         phys_addr: PhysAddr,
         mem: &UserMem,
-    ) -> io::Result<()> {
-        // Create guest physical memory mapping for `slot : 0` at guest `phys_addr`.
-        let mut kvm_mem = kvm_sys::kvm_userspace_memory_region::default();
-        kvm_mem.userspace_addr = mem.ptr as u64;
-        kvm_mem.memory_size = mem.len as u64;
-        kvm_mem.guest_phys_addr = phys_addr.0;
-
-        ioctl(
+        flags: MemFlags,
+    ) -> Result<()> {
+        if flags.contains(MemFlags::READONLY) && !self.check_extenstion(CapBool::ReadonlyMem)? {
+            return Err(Error::UnsupportedCap("KVM_CAP_READONLY_MEM"));
+        }
+
+        if slot >= self.nr_memslots {
+            return Err(Error::MemSlotLimit(slot));
+        }
+
+        let len = mem.len as u64;
+        if phys_addr.0.checked_add(len).is_none() {
+            return Err(Error::InvalidArgument(
+                "memory region exceeds guest physical address space",
+            ));
+        }
+
+        if let Some((other, _)) = self
+            .memslots
+            .iter()
+            .find(|(&other, m)| other != slot && m.overlaps(phys_addr.0, len))
+        {
+            return Err(Error::MemSlotOverlap(slot, *other));
+        }
+
+        let new = MemSlot {
+            phys_addr: phys_addr.0,
+            len,
+            userspace_addr: mem.ptr as u64,
+            flags,
+        };
+
+        // KVM does not allow to change the size or the addresses of an existing slot, hence
+        // delete the old mapping first, unless only the dirty logging is toggled.
+        let old = match self.memslots.get(&slot) {
+            Some(old) if old.updates_in_place(&new) => None,
+            Some(&old) => {
+                self.delete_user_memory_region(slot)?;
+                Some(old)
+            }
+            None => None,
+        };
+
+        if let Err(err) = self.map_memslot(slot, &new) {
+            // Try to restore the old mapping, if that fails as well the slot stays unused.
+            if let Some(old) = old {
+                if self.map_memslot(slot, &old).is_ok() {
+                    self.memslots.insert(slot, old);
+                }
+            }
+            return Err(err);
+        }
+
+        self.memslots.insert(slot, new);
+        Ok(())
+    }
+
+    /// Create or update the guest physical memory mapping of `slot` with the
+    /// [`KVM_SET_USER_MEMORY_REGION`][kvm-set-user-memory-region] ioctl.
+    ///
+    /// [kvm-set-user-memory-region]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-user-memory-region
+    fn map_memslot(&self, slot: u32, m: &MemSlot) -> Result<()> {
+        let kvm_mem = kvm_sys::kvm_userspace_memory_region {
+            slot,
+            flags: m.flags.0,
+            userspace_addr: m.userspace_addr,
+            memory_size: m.len,
+            guest_phys_addr: m.phys_addr,
+        };
+
+        ioctl!(
+            &self.vm,
+            KVM_SET_USER_MEMORY_REGION,
+            &kvm_mem as *const _ as u64
+        )
+        .map(|_| ())
+    }
+
+    /// Remove the memory mapping of memory slot `slot` from the VM by setting its size to `0`
+    /// with the [`KVM_SET_USER_MEMORY_REGION`][kvm-set-user-memory-region] ioctl.
+    ///
+    /// [kvm-set-user-memory-region]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-user-memory-region
+    pub fn delete_user_memory_region(&mut self, slot: u32) -> Result<()> {
+        if !self.memslots.contains_key(&slot) {
+            return Err(Error::MemSlotUnused(slot));
+        }
+
+        let kvm_mem = kvm_sys::kvm_userspace_memory_region {
+            slot,
+            ..Default::default()
+        };
+
+        ioctl!(
+            &self.vm,
+            KVM_SET_USER_MEMORY_REGION,
+            &kvm_mem as *const _ as u64
+        )?;
+
+        self.memslots.remove(&slot);
+        Ok(())
+    }
+
+    /// Get the lowest memory slot id which is currently not in use, or `None` if all memory slots
+    /// are in use.
+    pub fn free_memslot(&self) -> Option<u32> {
+        (0..self.nr_memslots).find(|slot| !self.memslots.contains_key(slot))
+    }
+
+    /// Enable manual dirty log protection with the [`KVM_ENABLE_CAP`][kvm-enable-cap] ioctl for
+    /// the [`ManualDirtyLogProtect2`](crate::cap::CapInt::ManualDirtyLogProtect2) capability.
+    ///
+    /// Once enabled, [`get_dirty_log`](crate::vm::Vm::get_dirty_log) does not reset the dirty
+    /// log anymore and pages must explicitly be re-protected with
+    /// [`clear_dirty_log`](crate::vm::Vm::clear_dirty_log).
+    ///
+    /// [kvm-enable-cap]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-enable-cap
+    pub fn enable_manual_dirty_log_protect(&mut self) -> Result<()> {
+        let flags = self.check_extenstion_int(CapInt::ManualDirtyLogProtect2)? as u64;
+        if flags & kvm_sys::KVM_DIRTY_LOG_MANUAL_PROTECT_ENABLE == 0 {
+            return Err(Error::UnsupportedCap("KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2"));
+        }
+
+        let mut cap = kvm_sys::kvm_enable_cap::default();
+        cap.cap = kvm_sys::KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2 as u32;
+        cap.args[0] = kvm_sys::KVM_DIRTY_LOG_MANUAL_PROTECT_ENABLE;
+
+        ioctl!(&self.vm, KVM_ENABLE_CAP, &cap as *const _ as u64)?;
+
+        self.manual_dirty_log_protect = true;
+        Ok(())
+    }
+
+    /// Get the dirty log of memory slot `slot` with the [`KVM_GET_DIRTY_LOG`][kvm-get-dirty-log]
+    /// ioctl.
+    ///
+    /// The memory slot must have been mapped with
+    /// [`MemFlags::LOG_DIRTY_PAGES`](crate::vm::MemFlags::LOG_DIRTY_PAGES). Unless manual dirty
+    /// log protection is enabled, fetching the dirty log also resets it.
+    ///
+    /// [kvm-get-dirty-log]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-dirty-log
+    pub fn get_dirty_log(&self, slot: u32) -> Result<DirtyBitmap> {
+        let mut bitmap = DirtyBitmap::new(self.dirty_log_pages(slot)?);
+
+        let mut log = kvm_sys::kvm_dirty_log::default();
+        log.slot = slot;
+        log.dirty_bitmap = bitmap.bitmap.as_mut_ptr() as u64;
+
+        ioctl!(&self.vm, KVM_GET_DIRTY_LOG, &log as *const _ as u64)?;
+        Ok(bitmap)
+    }
+
+    /// Reset the dirty state and re-protect all pages of memory slot `slot` marked in `bitmap`
+    /// with the [`KVM_CLEAR_DIRTY_LOG`][kvm-clear-dirty-log] ioctl.
+    ///
+    /// Requires manual dirty log protection to be enabled with
+    /// [`enable_manual_dirty_log_protect`](crate::vm::Vm::enable_manual_dirty_log_protect).
+    ///
+    /// [kvm-clear-dirty-log]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-clear-dirty-log
+    pub fn clear_dirty_log(&self, slot: u32, bitmap: &DirtyBitmap) -> Result<()> {
+        if !self.manual_dirty_log_protect {
+            return Err(Error::InvalidArgument(
+                "manual dirty log protection not enabled",
+            ));
+        }
+
+        let pages = self.dirty_log_pages(slot)?;
+        if bitmap.pages != pages {
+            return Err(Error::InvalidArgument(
+                "dirty bitmap does not match memory slot",
+            ));
+        }
+
+        let log = kvm_sys::kvm_clear_dirty_log {
+            slot,
+            num_pages: pages as u32,
+            first_page: 0,
+            dirty_bitmap: bitmap.bitmap.as_ptr() as u64,
+        };
+
+        ioctl!(&self.vm, KVM_CLEAR_DIRTY_LOG, &log as *const _ as u64).map(|_| ())
+    }
+
+    /// Get the number of pages tracked by the dirty log of memory slot `slot`.
+    fn dirty_log_pages(&self, slot: u32) -> Result<usize> {
+        match self.memslots.get(&slot) {
+            Some(m) if m.flags.contains(MemFlags::LOG_DIRTY_PAGES) => {
+                Ok(m.len.div_ceil(PAGE_SIZE) as usize)
+            }
+            Some(_) => Err(Error::InvalidArgument(
+                "memory slot does not log dirty pages",
+            )),
+            None => Err(Error::MemSlotUnused(slot)),
+        }
+    }
+
+    /// Forward guest MSR accesses failing for any of the `reasons` to user space as
+    /// [`KvmExit::RdMsr`](crate::vcpu::KvmExit::RdMsr) and
+    /// [`KvmExit::WrMsr`](crate::vcpu::KvmExit::WrMsr) exits, with the
+    /// [`KVM_ENABLE_CAP`][kvm-enable-cap] ioctl for the
+    /// [`X86UserSpaceMsr`](crate::cap::CapBool::X86UserSpaceMsr) capability.
+    ///
+    /// [kvm-enable-cap]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-enable-cap
+    pub fn enable_user_space_msr(&self, reasons: MsrExitReason) -> Result<()> {
+        if !self.check_extenstion(CapBool::X86UserSpaceMsr)? {
+            return Err(Error::UnsupportedCap("KVM_CAP_X86_USER_SPACE_MSR"));
+        }
+
+        let mut cap = kvm_sys::kvm_enable_cap::default();
+        cap.cap = kvm_sys::KVM_CAP_X86_USER_SPACE_MSR as u32;
+        cap.args[0] = u64::from(reasons.bits());
+
+        ioctl!(&self.vm, KVM_ENABLE_CAP, &cap as *const _ as u64).map(|_| ())
+    }
+
+    /// Install the MSR `filter` with the [`KVM_X86_SET_MSR_FILTER`][kvm-set-msr-filter] ioctl,
+    /// replacing any previously installed filter.
+    ///
+    /// Requires the [`X86MsrFilter`](crate::cap::CapBool::X86MsrFilter) capability.
+    ///
+    /// [kvm-set-msr-filter]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-x86-set-msr-filter
+    pub fn set_msr_filter(&self, filter: &MsrFilter) -> Result<()> {
+        // The bitmaps are borrowed from `filter` and only read during the ioctl.
+        let kvm_filter = filter.as_kvm();
+
+        ioctl!(
             &self.vm,
-            kvm_sys::KVM_SET_USER_MEMORY_REGION,
-            &kvm_mem as *const _ as u64,
+            KVM_X86_SET_MSR_FILTER,
+            &kvm_filter as *const _ as u64
         )
         .map(|_| ())
     }
 
+    /// Create the in-kernel interrupt controller with the [`KVM_CREATE_IRQCHIP`][kvm-create-irqchip]
+    /// ioctl, see [`irqchip`](crate::irqchip).
+    ///
+    /// Must be called before the first VCPU is created, as the local APIC of a VCPU is only
+    /// emulated in the Kernel if the irqchip exists when the VCPU is created. With the in-kernel
+    /// irqchip, guest `hlt` instructions are handled by the Kernel and do not exit to user space.
+    ///
+    /// Returns [`Error::UnsupportedCap`](crate::Error::UnsupportedCap) if the
+    /// [`Irqchip`](crate::cap::CapBool::Irqchip) capability is not supported.
+    ///
+    /// [kvm-create-irqchip]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-create-irqchip
+    #[cfg(target_arch = "x86_64")]
+    pub fn create_irqchip(&self) -> Result<()> {
+        if !self.check_extenstion(CapBool::Irqchip)? {
+            return Err(Error::UnsupportedCap("KVM_CAP_IRQCHIP"));
+        }
+
+        ioctl!(&self.vm, KVM_CREATE_IRQCHIP, 0).map(|_| ())
+    }
+
+    /// Set the level of the IRQ line `irq` of the in-kernel irqchip with the
+    /// [`KVM_IRQ_LINE`][kvm-irq-line] ioctl.
+    ///
+    /// Edge triggered interrupts are raised by asserting and de-asserting the line.
+    ///
+    /// [kvm-irq-line]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-irq-line
+    pub fn set_irq_line(&self, irq: u32, level: bool) -> Result<()> {
+        irqchip::set_irq_line(&self.vm, irq, level)
+    }
+
+    /// Get a handle to the IRQ line `irq` of the in-kernel irqchip, which can be moved into a
+    /// device to raise interrupts without access to the VM.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if `irq` exceeds
+    /// [`NR_IRQS`](crate::irqchip::NR_IRQS).
+    pub fn irq_line(&self, irq: u32) -> Result<IrqLine> {
+        if irq >= NR_IRQS {
+            return Err(Error::InvalidArgument("IRQ line exceeds NR_IRQS"));
+        }
+
+        Ok(IrqLine::new(self.vm.try_clone()?, irq))
+    }
+
+    /// Inject a message signalled interrupt (MSI) with the 64 bit `address` and `data` with the
+    /// [`KVM_SIGNAL_MSI`][kvm-signal-msi] ioctl.
+    ///
+    /// On x86 the address encodes the destination APIC ID in bits `19:12` on top of
+    /// [`APIC_BASE`](crate::irqchip::APIC_BASE), and the data the vector in bits `7:0`.
+    ///
+    /// Returns `false` if the guest blocked the interrupt. Requires the
+    /// [`SignalMsi`](crate::cap::CapBool::SignalMsi) capability and the in-kernel irqchip.
+    ///
+    /// [kvm-signal-msi]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-signal-msi
+    pub fn signal_msi(&self, address: u64, data: u32) -> Result<bool> {
+        let mut msi = kvm_sys::kvm_msi::default();
+        msi.address_lo = address as u32;
+        msi.address_hi = (address >> 32) as u32;
+        msi.data = data;
+
+        ioctl!(&self.vm, KVM_SIGNAL_MSI, &msi as *const _ as u64).map(|ret| ret > 0)
+    }
+
+    /// Get the state of the interrupt controller `chip` of the in-kernel irqchip with the
+    /// [`KVM_GET_IRQCHIP`][kvm-get-irqchip] ioctl.
+    ///
+    /// [kvm-get-irqchip]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-get-irqchip
+    #[cfg(target_arch = "x86_64")]
+    pub fn get_irqchip(&self, chip: IrqChip) -> Result<IrqChipState> {
+        let mut irqchip = IrqChipState::kvm_for(chip);
+        ioctl!(&self.vm, KVM_GET_IRQCHIP, &mut irqchip as *mut _ as u64)?;
+        Ok(IrqChipState::from_kvm(chip, &irqchip))
+    }
+
+    /// Set the state of an interrupt controller of the in-kernel irqchip with the
+    /// [`KVM_SET_IRQCHIP`][kvm-set-irqchip] ioctl.
+    ///
+    /// [kvm-set-irqchip]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-set-irqchip
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_irqchip(&self, state: &IrqChipState) -> Result<()> {
+        let irqchip = state.as_kvm();
+        ioctl!(&self.vm, KVM_SET_IRQCHIP, &irqchip as *const _ as u64).map(|_| ())
+    }
+
     /// Create a new virtual cpu with the [`KVM_CREATE_VCPU`][kvm-create-vcpu] ioctl.
     /// Returns a wrapper [`vcpu::Vcpu`][crate::vcpu::Vcpu] representing the VCPU.
     ///
     /// [kvm-create-vcpu]: https://www.kernel.org/doc/html/latest/virt/kvm/api.html#kvm-create-vcpu
-    pub fn create_vpcu(&self, id: u64) -> io::Result<Vcpu> {
-        let vcpu = ioctl(&self.vm, kvm_sys::KVM_CREATE_VCPU, id)
-            .map(|fd| unsafe { fs::File::from_raw_fd(fd) })?;
+    pub fn create_vpcu(&self, id: u64) -> Result<Vcpu> {
+        let vcpu =
+            ioctl!(&self.vm, KVM_CREATE_VCPU, id).map(|fd| unsafe { fs::File::from_raw_fd(fd) })?;
 
         let kvm_run = KvmRun::new(&vcpu, self.vcpu_mmap_size)?;
 
-        Ok(Vcpu::new(vcpu, kvm_run))
+        // Size of the XSAVE state, `KVM_CAP_XSAVE2` is only reported on VM fds by newer Kernels.
+        // Older Kernels report `0`, in which case the legacy `4K` XSAVE area is used.
+        let xsave2_size = self.check_extenstion_int(CapInt::Xsave2)?;
+
+        Ok(Vcpu::new(vcpu, kvm_run, id, xsave2_size as usize))
+    }
+
+    /// Run each of the `vcpus` on its own thread and pass all exits to the shared `handler`
+    /// together with the id of the exiting VCPU.
+    ///
+    /// The VCPUs run until the `handler` returns `Ok(false)` or an error for one of the VCPUs, or
+    /// running a VCPU fails. All other VCPUs are then kicked out of the guest with a
+    /// [`VcpuHandle`](crate::vcpu::VcpuHandle) and their threads finish as well, the resulting
+    /// [`KvmExit::Interrupted`](crate::vcpu::KvmExit::Interrupted) exits are not passed to the
+    /// `handler`. Returns the VCPUs once all threads finished, on error the VCPUs are dropped and
+    /// the first error is returned.
+    ///
+    /// ```no_run
+    /// # use kvm_rs::vcpu::KvmExit;
+    /// # fn main() -> kvm_rs::Result<()> {
+    /// let vm = kvm_rs::kvm::Kvm::new()?.create_vm()?;
+    /// vm.create_irqchip()?;
+    /// let vcpus = vec![vm.create_vpcu(0)?, vm.create_vpcu(1)?];
+    /// // .. setup guest memory and registers.
+    /// vm.run_vcpus(vcpus, |id, exit| match exit {
+    ///     KvmExit::IoOut(port, data) => {
+    ///         println!("vcpu{}: out {:#x} {:x?}", id, port, data);
+    ///         Ok(true)
+    ///     }
+    ///     _ => Ok(false),
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn run_vcpus<F>(&self, mut vcpus: Vec<Vcpu>, handler: F) -> Result<Vec<Vcpu>>
+    where
+        F: Fn(u64, KvmExit<'_>) -> Result<bool> + Sync,
+    {
+        let handler = &handler;
+        let handles = vcpus
+            .iter_mut()
+            .map(Vcpu::handle)
+            .collect::<Result<Vec<_>>>()?;
+        let stop = &AtomicBool::new(false);
+        let stop_all = || {
+            stop.store(true, Ordering::SeqCst);
+            for handle in &handles {
+                // Kicking only fails if the signal can't be sent, the VCPU then stops on its next
+                // exit.
+                let _ = handle.kick();
+            }
+        };
+        let stop_all = &stop_all;
+
+        let results: Vec<Result<Vcpu>> = thread::scope(|s| {
+            let threads: Vec<_> = vcpus
+                .into_iter()
+                .map(|mut vcpu| {
+                    s.spawn(move || {
+                        let ret = run_vcpu_until_stop(&mut vcpu, stop, handler);
+                        stop_all();
+                        ret.map(|_| vcpu)
+                    })
+                })
+                .collect();
+
+            // Join all threads before reporting the first error.
+            threads
+                .into_iter()
+                .map(|t| t.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+                .collect()
+        });
+
+        let mut vcpus = results.into_iter().collect::<Result<Vec<_>>>()?;
+        // Drop the kicks of VCPUs which were not running anymore, kick signals pending on the
+        // finished VCPU threads are gone with the threads.
+        vcpus.iter_mut().for_each(Vcpu::clear_kick);
+        Ok(vcpus)
+    }
+}
+
+/// Run `vcpu` and pass the exits to `handler` until it returns `Ok(false)` or `stop` is set, see
+/// [`Vm::run_vcpus`].
+fn run_vcpu_until_stop<F>(vcpu: &mut Vcpu, stop: &AtomicBool, handler: &F) -> Result<()>
+where
+    F: Fn(u64, KvmExit<'_>) -> Result<bool>,
+{
+    let id = vcpu.id();
+    loop {
+        let exit = vcpu.run()?;
+        // Drop the exit caused by the kick of another VCPU thread stopping.
+        if stop.load(Ordering::SeqCst) && matches!(exit, KvmExit::Interrupted) {
+            return Ok(());
+        }
+        if !handler(id, exit)? || stop.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kvm::Kvm;
+
+    #[test]
+    fn memslot_update_in_place() {
+        let slot = MemSlot {
+            phys_addr: 0x1000,
+            len: 0x2000,
+            userspace_addr: 0x7f00_0000_0000,
+            flags: MemFlags::NONE,
+        };
+        let dirty = MemSlot {
+            flags: MemFlags::LOG_DIRTY_PAGES,
+            ..slot
+        };
+        assert!(slot.updates_in_place(&dirty));
+        assert!(dirty.updates_in_place(&slot));
+        assert!(!slot.updates_in_place(&MemSlot {
+            flags: MemFlags::READONLY,
+            ..slot
+        }));
+        assert!(!slot.updates_in_place(&MemSlot {
+            len: 0x1000,
+            ..slot
+        }));
+        assert!(!slot.updates_in_place(&MemSlot {
+            phys_addr: 0x2000,
+            ..slot
+        }));
+        assert!(!slot.updates_in_place(&MemSlot {
+            userspace_addr: 0x7f00_0000_1000,
+            ..slot
+        }));
+    }
+
+    #[test]
+    #[ignore = "requires /dev/kvm"]
+    fn memslot_flags_update() {
+        let mut vm = Kvm::new().unwrap().create_vm().unwrap();
+        let mem = UserMem::new(0x2000).unwrap();
+
+        unsafe { vm.set_user_memory_region(0, PhysAddr(0x1000), &mem, MemFlags::NONE) }.unwrap();
+        assert!(vm.get_dirty_log(0).is_err());
+
+        unsafe { vm.set_user_memory_region(0, PhysAddr(0x1000), &mem, MemFlags::LOG_DIRTY_PAGES) }
+            .unwrap();
+        let bitmap = vm.get_dirty_log(0).unwrap();
+        assert_eq!(bitmap.pages(), 2);
+        assert_eq!(bitmap.dirty_pages().count(), 0);
+
+        unsafe { vm.set_user_memory_region(0, PhysAddr(0x1000), &mem, MemFlags::NONE) }.unwrap();
+        assert!(vm.get_dirty_log(0).is_err());
+        assert_eq!(vm.free_memslot(), Some(1));
+    }
+
+    #[test]
+    #[ignore = "requires /dev/kvm"]
+    fn memslot_restore_on_failure() {
+        let mut vm = Kvm::new().unwrap().create_vm().unwrap();
+        let mem = UserMem::new(0x2000).unwrap();
+        // KVM rejects memory regions which are not a multiple of the page size.
+        let bad = UserMem::new(0x1800).unwrap();
+
+        unsafe { vm.set_user_memory_region(0, PhysAddr(0x1000), &mem, MemFlags::NONE) }.unwrap();
+        assert!(
+            unsafe { vm.set_user_memory_region(0, PhysAddr(0x1000), &bad, MemFlags::NONE) }
+                .is_err()
+        );
+
+        // The old mapping is restored and can still be updated in place.
+        assert_eq!(vm.free_memslot(), Some(1));
+        unsafe { vm.set_user_memory_region(0, PhysAddr(0x1000), &mem, MemFlags::LOG_DIRTY_PAGES) }
+            .unwrap();
+        assert_eq!(vm.get_dirty_log(0).unwrap().pages(), 2);
     }
 }
@@ -4,9 +4,15 @@
 
 //! `x86_64` flags and bitfields.
 
+pub mod gdt;
+pub mod idt;
+pub mod mode;
+pub mod paging;
+
 pub use x86_64::*;
 
 #[rustfmt::skip]
+#[allow(clippy::module_inception, clippy::identity_op)]
 mod x86_64 {
     /* Rflags Register */
 
@@ -116,7 +122,7 @@ mod x86_64 {
      */
 
     /// Mask for physical base address of paging structure.
-    pub const CR3_PAGE_BASE_MASK: u64 = 0xffff_ffff_ffff_0000;
+    pub const CR3_PAGE_BASE_MASK: u64 = 0xffff_ffff_ffff_f000;
 
     /// Page-level Write-Through.
     pub const CR3_PWT: u64 = 1 << 3;
@@ -125,6 +131,10 @@ mod x86_64 {
 
     /* Control Register CR4 (flags for arch extenstions processor capabilities) */
 
+    /// Page Size Extensions.
+    ///
+    /// When set enables `4M` pages with `32-bit` paging.
+    pub const CR4_PSE: u64 = 1 << 4;
     /// Physical Address Extenstion.
     ///
     /// When set enables paging to produce physicall addresses with more than 32 bits. Required before
@@ -134,17 +144,38 @@ mod x86_64 {
     ///
     /// When set in `long mode` enables `5-level` paging to translate `57-bit` linear addresses. When
     /// cleared use `4-level` paging to translate `48-bit` linear addresses.
-    pub const CR4_LA57: u64 = 1 << 5;
+    pub const CR4_LA57: u64 = 1 << 12;
 
-    /* Extended Feature Enable Register (EFER) */
+    /* Model Specific Registers (MSR)
+     *
+     * MSR numbers used with the `rdmsr` and `wrmsr` instructions, see
+     * https://johannst.github.io/notes/arch/x86_64.html#model-specific-register-msr.
+     */
 
-    /// Extended Feature Enable Register MSR number.
-    ///
-    /// MSR number used with the [`rdmsr`][msr] and [`wrmsr`][msr] instructions to read/write the
-    /// `EFER` model specific register.
-    ///
-    /// [msr]: https://johannst.github.io/notes/arch/x86_64.html#model-specific-register-msr
-    pub const MSR_EFER: u64 = 0xc000_0080;
+    /// Time-Stamp Counter (`IA32_TIME_STAMP_COUNTER`).
+    pub const MSR_TSC: u32 = 0x10;
+    /// Local APIC base address and enable (`IA32_APIC_BASE`).
+    pub const MSR_APIC_BASE: u32 = 0x1b;
+    /// Page Attribute Table (`IA32_PAT`).
+    pub const MSR_PAT: u32 = 0x277;
+    /// Extended Feature Enable Register (`IA32_EFER`).
+    pub const MSR_EFER: u32 = 0xc000_0080;
+    /// `syscall` target segment selectors (`IA32_STAR`).
+    pub const MSR_STAR: u32 = 0xc000_0081;
+    /// `syscall` target `rip` in `long mode` (`IA32_LSTAR`).
+    pub const MSR_LSTAR: u32 = 0xc000_0082;
+    /// `syscall` target `rip` in compatibility mode (`IA32_CSTAR`).
+    pub const MSR_CSTAR: u32 = 0xc000_0083;
+    /// `syscall` `rflags` mask (`IA32_FMASK`).
+    pub const MSR_FMASK: u32 = 0xc000_0084;
+    /// `fs` segment base (`IA32_FS_BASE`).
+    pub const MSR_FS_BASE: u32 = 0xc000_0100;
+    /// `gs` segment base (`IA32_GS_BASE`).
+    pub const MSR_GS_BASE: u32 = 0xc000_0101;
+    /// `gs` segment base swapped in by `swapgs` (`IA32_KERNEL_GS_BASE`).
+    pub const MSR_KERNEL_GS_BASE: u32 = 0xc000_0102;
+
+    /* Extended Feature Enable Register (EFER) */
 
     /// Long Mode Enable.
     ///
@@ -154,6 +185,91 @@ mod x86_64 {
     ///
     /// When set indicates long mode is active.
     pub const EFER_LMA: u64 = 1 << 10;
+    /// No-Execute Enable.
+    ///
+    /// When set enables the [PAGE_ENTRY_NX](crate::x86_64::PAGE_ENTRY_NX) bit in `PAE` paging
+    /// entries.
+    pub const EFER_NXE: u64 = 1 << 11;
+
+    /* Extended Control Register XCR0 (XSAVE feature enabled mask) */
+
+    /// x87 FPU state.
+    pub const XCR0_X87: u64 = 1 << 0;
+    /// SSE state (`xmm` registers and `mxcsr`).
+    pub const XCR0_SSE: u64 = 1 << 1;
+    /// AVX state (upper halves of the `ymm` registers).
+    pub const XCR0_AVX: u64 = 1 << 2;
+
+    /* XSAVE Area (standard format) */
+
+    /// Offset of the `xmm` registers in the legacy region.
+    pub const XSAVE_XMM_OFFSET: usize = 160;
+    /// Offset of the XSAVE header (`XSTATE_BV`, `XCOMP_BV`).
+    pub const XSAVE_HEADER_OFFSET: usize = 512;
+    /// Offset of the upper halves of the `ymm` registers (AVX state component).
+    pub const XSAVE_YMM_HI_OFFSET: usize = 576;
+
+    /* Exception Vectors */
+
+    /// Divide error (`#DE`).
+    pub const EXCEPTION_DE: u8 = 0;
+    /// Debug exception (`#DB`).
+    pub const EXCEPTION_DB: u8 = 1;
+    /// Non-maskable interrupt (NMI).
+    pub const EXCEPTION_NMI: u8 = 2;
+    /// Breakpoint exception (`#BP`), raised by `int3`.
+    pub const EXCEPTION_BP: u8 = 3;
+    /// Invalid opcode (`#UD`).
+    pub const EXCEPTION_UD: u8 = 6;
+    /// Double fault (`#DF`).
+    pub const EXCEPTION_DF: u8 = 8;
+    /// General protection fault (`#GP`).
+    pub const EXCEPTION_GP: u8 = 13;
+    /// Page fault (`#PF`).
+    pub const EXCEPTION_PF: u8 = 14;
+    /// Number of exception vectors reserved by the architecture (`0-31`).
+    pub const NR_EXCEPTIONS: u8 = 32;
+
+    /// Check if the processor pushes an error code for the exception `vector`.
+    pub const fn exception_has_error_code(vector: u8) -> bool {
+        matches!(vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+    }
+
+    /* Debug Register DR6 (debug status) */
+
+    /// Breakpoint condition detected for breakpoint `0-3` (`B0-B3`).
+    pub const DR6_B: [u64; 4] = [1 << 0, 1 << 1, 1 << 2, 1 << 3];
+    /// Single step.
+    ///
+    /// When set indicates the debug exception was triggered by single stepping
+    /// ([RFLAGS_TF](crate::x86_64::RFLAGS_TF)).
+    pub const DR6_BS: u64 = 1 << 14;
+
+    /* Debug Register DR7 (debug control) */
+
+    /// Bits of DR7 that are always set (reserved bit 10) + exact breakpoint enable (`GE`).
+    pub const DR7_FIXED: u64 = 0b11 << 9;
+
+    /// Break on instruction execution (`R/W` field).
+    pub const DR7_RW_EXEC: u64 = 0b00;
+    /// Break on data writes (`R/W` field).
+    pub const DR7_RW_WRITE: u64 = 0b01;
+    /// Break on data reads or writes (`R/W` field).
+    pub const DR7_RW_READ_WRITE: u64 = 0b11;
+
+    /// 1-byte length (`LEN` field).
+    pub const DR7_LEN_1: u64 = 0b00;
+    /// 2-byte length (`LEN` field).
+    pub const DR7_LEN_2: u64 = 0b01;
+    /// 8-byte length (`LEN` field).
+    pub const DR7_LEN_8: u64 = 0b10;
+    /// 4-byte length (`LEN` field).
+    pub const DR7_LEN_4: u64 = 0b11;
+
+    /// Global enable bit (`G0-G3`) for breakpoint `idx`.
+    pub const fn dr7_global_enable(idx: usize) -> u64 { 1 << (idx * 2 + 1) }
+    /// `R/W` and `LEN` fields for breakpoint `idx`.
+    pub const fn dr7_rw_len(idx: usize, rw: u64, len: u64) -> u64 { ((len << 2) | rw) << (16 + idx * 4) }
 
     /* Paging */
 
@@ -163,4 +279,30 @@ mod x86_64 {
     ///
     /// If set, region reference by paging entry is writeable.
     pub const PAGE_ENTRY_RW: u64 = 1 << 1;
+    /// Page region user/supervisor.
+    ///
+    /// If set, region reference by paging entry is accessible from user mode (`CPL=3`).
+    pub const PAGE_ENTRY_US: u64 = 1 << 2;
+    /// Page-level Write-Through.
+    pub const PAGE_ENTRY_PWT: u64 = 1 << 3;
+    /// Page-level Cache Disable.
+    pub const PAGE_ENTRY_PCD: u64 = 1 << 4;
+    /// Page accessed (set by the processor).
+    pub const PAGE_ENTRY_ACCESSED: u64 = 1 << 5;
+    /// Page dirty (set by the processor).
+    pub const PAGE_ENTRY_DIRTY: u64 = 1 << 6;
+    /// Page size.
+    ///
+    /// If set in a page directory entry (or `PDPT` entry in `long mode`), the entry maps a large
+    /// page instead of referencing the next paging structure.
+    pub const PAGE_ENTRY_PS: u64 = 1 << 7;
+    /// Global page.
+    pub const PAGE_ENTRY_GLOBAL: u64 = 1 << 8;
+    /// Execute disable.
+    ///
+    /// If set, instruction fetches from the region referenced by paging entry are not allowed
+    /// (requires [EFER_NXE](crate::x86_64::EFER_NXE)).
+    pub const PAGE_ENTRY_NX: u64 = 1 << 63;
+    /// Mask for physical address of the page or next paging structure in `PAE` paging entries.
+    pub const PAGE_ENTRY_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
 }
@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Global descriptor table (GDT) and segment descriptors.
+
+use crate::kvm_sys::{kvm_dtable, kvm_segment};
+use crate::{PhysAddr, Result, UserMem};
+
+/// Size of a task state segment (TSS) without IO permission bitmap.
+pub const TSS_SIZE: u32 = 104;
+
+/// Kind of a segment described by a [`Descriptor`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// 64 bit code segment (execute + read).
+    Code64,
+    /// 32 bit code segment (execute + read).
+    Code32,
+    /// 32 bit data segment (read + write).
+    Data,
+    /// Busy 64 bit TSS, occupies two GDT entries.
+    Tss64,
+    /// Busy 32 bit TSS.
+    Tss32,
+}
+
+/// Segment descriptor which can be added to a [`Gdt`].
+///
+/// Limits are byte granular, the granularity flag is set automatically for limits larger than
+/// `1M`, in which case the lower 12 bits of the limit must be set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Descriptor {
+    kind: Kind,
+    base: u64,
+    limit: u32,
+    dpl: u8,
+}
+
+impl Descriptor {
+    /// 64 bit code segment with privilege level `dpl` (base and limit are ignored in 64 bit
+    /// mode).
+    pub fn code64(dpl: u8) -> Descriptor {
+        Descriptor {
+            kind: Kind::Code64,
+            base: 0,
+            limit: 0xffff_ffff,
+            dpl,
+        }
+    }
+
+    /// 32 bit code segment `[base : base + limit]` with privilege level `dpl`.
+    pub fn code32(base: u32, limit: u32, dpl: u8) -> Descriptor {
+        Descriptor {
+            kind: Kind::Code32,
+            base: base.into(),
+            limit,
+            dpl,
+        }
+    }
+
+    /// Writeable data segment `[base : base + limit]` with privilege level `dpl`.
+    pub fn data(base: u32, limit: u32, dpl: u8) -> Descriptor {
+        Descriptor {
+            kind: Kind::Data,
+            base: base.into(),
+            limit,
+            dpl,
+        }
+    }
+
+    /// 64 bit task state segment at `base` with `limit` (at least `TSS_SIZE - 1`).
+    pub fn tss64(base: u64, limit: u32) -> Descriptor {
+        Descriptor {
+            kind: Kind::Tss64,
+            base,
+            limit,
+            dpl: 0,
+        }
+    }
+
+    /// 32 bit task state segment at `base` with `limit` (at least `TSS_SIZE - 1`).
+    pub fn tss32(base: u32, limit: u32) -> Descriptor {
+        Descriptor {
+            kind: Kind::Tss32,
+            base: base.into(),
+            limit,
+            dpl: 0,
+        }
+    }
+
+    /// Get the descriptor as [`kvm_segment`](crate::kvm_sys::kvm_segment) referenced by
+    /// `selector`.
+    pub fn segment(&self, selector: u16) -> kvm_segment {
+        let (type_, s, l, db) = match self.kind {
+            Kind::Code64 => (0b1010, 1, 1, 0),
+            Kind::Code32 => (0b1010, 1, 0, 1),
+            Kind::Data => (0b0010, 1, 0, 1),
+            Kind::Tss64 | Kind::Tss32 => (0b1011, 0, 0, 0),
+        };
+
+        let mut seg = kvm_segment::default();
+        seg.base = self.base;
+        seg.limit = self.limit;
+        seg.selector = selector;
+        seg.type_ = type_;
+        seg.present = 1;
+        seg.dpl = self.dpl;
+        seg.db = db;
+        seg.s = s;
+        seg.l = l;
+        seg.g = u8::from(self.limit > 0xf_ffff);
+        seg
+    }
+
+    /// Encode the descriptor into GDT entries.
+    fn encode(&self) -> Vec<u64> {
+        let seg = self.segment(0);
+        let limit = if seg.g == 1 {
+            u64::from(self.limit >> 12)
+        } else {
+            u64::from(self.limit)
+        };
+        let base = self.base;
+
+        let desc = (limit & 0xffff)
+            | (base & 0xff_ffff) << 16
+            | u64::from(seg.type_) << 40
+            | u64::from(seg.s) << 44
+            | u64::from(seg.dpl & 0b11) << 45
+            | u64::from(seg.present) << 47
+            | (limit >> 16 & 0xf) << 48
+            | u64::from(seg.l) << 53
+            | u64::from(seg.db) << 54
+            | u64::from(seg.g) << 55
+            | (base >> 24 & 0xff) << 56;
+
+        match self.kind {
+            // Upper 8 bytes of the 16 byte system descriptor hold bits 63:32 of the base.
+            Kind::Tss64 => vec![desc, base >> 32],
+            _ => vec![desc],
+        }
+    }
+}
+
+/// Builder for a global descriptor table.
+///
+/// The first entry is the mandatory null descriptor.
+pub struct Gdt {
+    entries: Vec<u64>,
+}
+
+impl Default for Gdt {
+    fn default() -> Gdt {
+        Gdt::new()
+    }
+}
+
+impl Gdt {
+    pub fn new() -> Gdt {
+        Gdt { entries: vec![0] }
+    }
+
+    /// Add the descriptor `desc` and get the corresponding
+    /// [`kvm_segment`](crate::kvm_sys::kvm_segment) with the selector referencing the new entry.
+    pub fn add(&mut self, desc: Descriptor) -> kvm_segment {
+        let selector = (self.entries.len() * 8) as u16 | u16::from(desc.dpl & 0b11);
+        self.entries.extend(desc.encode());
+        desc.segment(selector)
+    }
+
+    /// Size of the GDT in bytes.
+    pub fn size(&self) -> usize {
+        self.entries.len() * 8
+    }
+
+    /// Write the GDT to the physical address `phys` of `mem` and get the
+    /// [`kvm_dtable`](crate::kvm_sys::kvm_dtable) for
+    /// [`kvm_sregs.gdt`](crate::kvm_sys::kvm_sregs), where `base` is the guest linear address
+    /// mapping `phys`.
+    pub fn write(&self, mem: &mut UserMem, phys: PhysAddr, base: u64) -> Result<kvm_dtable> {
+        let bytes: Vec<u8> = self.entries.iter().flat_map(|e| e.to_le_bytes()).collect();
+        mem.load(phys, &bytes)?;
+
+        let mut dtable = kvm_dtable::default();
+        dtable.base = base;
+        dtable.limit = (self.size() - 1) as u16;
+        Ok(dtable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gdt_encode() {
+        let mut gdt = Gdt::new();
+        let cs = gdt.add(Descriptor::code64(0));
+        let ds = gdt.add(Descriptor::data(0, 0xffff_ffff, 3));
+        let cs32 = gdt.add(Descriptor::code32(0x1000, 0xffff, 0));
+        let tr = gdt.add(Descriptor::tss64(0xffff_8000_1234_5678, TSS_SIZE - 1));
+
+        assert_eq!(cs.selector, 0x08);
+        assert_eq!(ds.selector, 0x13);
+        assert_eq!(cs32.selector, 0x18);
+        assert_eq!(tr.selector, 0x20);
+        assert_eq!(gdt.size(), 6 * 8);
+
+        assert_eq!(
+            gdt.entries,
+            vec![
+                0,
+                0x00af_9a00_0000_ffff,
+                0x00cf_f200_0000_ffff,
+                0x0040_9a00_1000_ffff,
+                0x1200_8b34_5678_0067,
+                0xffff_8000,
+            ]
+        );
+
+        assert_eq!((cs.l, cs.db, cs.g, cs.s), (1, 0, 1, 1));
+        assert_eq!((ds.type_, ds.dpl, ds.limit), (2, 3, 0xffff_ffff));
+        assert_eq!((tr.type_, tr.s, tr.base), (11, 0, 0xffff_8000_1234_5678));
+    }
+
+    #[test]
+    fn gdt_write() {
+        let mut mem = UserMem::new(0x1000).unwrap();
+        let mut gdt = Gdt::new();
+        gdt.add(Descriptor::code64(0));
+
+        let dtable = gdt.write(&mut mem, PhysAddr(0x100), 0x4100).unwrap();
+        assert_eq!((dtable.base, dtable.limit), (0x4100, 15));
+        assert_eq!(
+            &mem.as_ref()[0x108..0x110],
+            &0x00af_9a00_0000_ffffu64.to_le_bytes()
+        );
+    }
+}
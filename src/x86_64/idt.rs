@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Interrupt descriptor table (IDT) and default exception handlers.
+//!
+//! The default exception handlers installed by [`Idt::install_exception_stubs`] report the
+//! exception to the host by writing the vector to the IO port [`EXCEPTION_PORT`] and halt the
+//! VCPU afterwards. Before the write, the handler loads the error code into `rsi` (`0` if the
+//! exception has no error code) and the faulting instruction pointer into `rdi`. With
+//! [`Vcpu::set_exception_port`](crate::vcpu::Vcpu::set_exception_port) this is decoded into a
+//! [`KvmExit::GuestException`](crate::vcpu::KvmExit::GuestException) exit.
+
+use crate::kvm_sys::kvm_dtable;
+use crate::x86_64::{exception_has_error_code, NR_EXCEPTIONS};
+use crate::{Error, PhysAddr, Result, UserMem};
+
+/// IO port used by the default exception handlers to report exceptions to the host.
+pub const EXCEPTION_PORT: u16 = 0x0ee0;
+
+/// Size of a single exception stub written by [`Idt::install_exception_stubs`].
+const STUB_SIZE: usize = 16;
+
+/// Number of bytes used by [`Idt::install_exception_stubs`] for the exception handlers.
+pub const EXCEPTION_STUBS_SIZE: usize = NR_EXCEPTIONS as usize * STUB_SIZE + 0x20;
+
+/// Guest exception reported by the default exception handlers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuestException {
+    /// Exception vector.
+    pub vector: u8,
+    /// Error code pushed by the processor, `0` if the exception has no error code.
+    pub error_code: u64,
+    /// Guest linear address of the faulting instruction pointer.
+    pub rip: u64,
+}
+
+/// Kind of a [`Gate`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Interrupt gate, clears `rflags.IF` when entering the handler.
+    Interrupt,
+    /// Trap gate, leaves `rflags.IF` unchanged.
+    Trap,
+}
+
+/// Gate descriptor which can be installed in an [`Idt`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Gate {
+    kind: Kind,
+    selector: u16,
+    offset: u64,
+    dpl: u8,
+}
+
+impl Gate {
+    /// Interrupt gate to the handler at `offset` in the code segment `selector`, which can be
+    /// invoked with `int n` from privilege level `dpl`.
+    pub fn interrupt(selector: u16, offset: u64, dpl: u8) -> Gate {
+        Gate {
+            kind: Kind::Interrupt,
+            selector,
+            offset,
+            dpl,
+        }
+    }
+
+    /// Trap gate to the handler at `offset` in the code segment `selector`, which can be invoked
+    /// with `int n` from privilege level `dpl`.
+    pub fn trap(selector: u16, offset: u64, dpl: u8) -> Gate {
+        Gate {
+            kind: Kind::Trap,
+            selector,
+            offset,
+            dpl,
+        }
+    }
+
+    /// Encode the gate into the lower `8` bytes of an IDT entry.
+    fn encode(&self) -> u64 {
+        let type_: u64 = match self.kind {
+            Kind::Interrupt => 0b1110,
+            Kind::Trap => 0b1111,
+        };
+
+        (self.offset & 0xffff)
+            | u64::from(self.selector) << 16
+            | type_ << 40
+            | u64::from(self.dpl & 0b11) << 45
+            | 1 << 47
+            | (self.offset >> 16 & 0xffff) << 48
+    }
+}
+
+/// Builder for an interrupt descriptor table.
+///
+/// The IDT covers the vectors up to the highest vector a gate is installed for.
+pub struct Idt {
+    long: bool,
+    gates: Vec<Option<Gate>>,
+}
+
+impl Idt {
+    /// IDT with 16 byte gates for `long mode`.
+    pub fn new64() -> Idt {
+        Idt {
+            long: true,
+            gates: Vec::new(),
+        }
+    }
+
+    /// IDT with 8 byte gates for 32 bit `protected mode`.
+    pub fn new32() -> Idt {
+        Idt {
+            long: false,
+            gates: Vec::new(),
+        }
+    }
+
+    /// Install `gate` for the interrupt `vector`.
+    pub fn set(&mut self, vector: u8, gate: Gate) {
+        let idx = usize::from(vector);
+        if self.gates.len() <= idx {
+            self.gates.resize(idx + 1, None);
+        }
+        self.gates[idx] = Some(gate);
+    }
+
+    /// Size of the IDT in bytes.
+    pub fn size(&self) -> usize {
+        self.gates.len() * self.entry_size()
+    }
+
+    fn entry_size(&self) -> usize {
+        if self.long {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Write the IDT to the physical address `phys` of `mem` and get the
+    /// [`kvm_dtable`](crate::kvm_sys::kvm_dtable) for
+    /// [`kvm_sregs.idt`](crate::kvm_sys::kvm_sregs), where `base` is the guest linear address
+    /// mapping `phys`.
+    ///
+    /// Returns [`Error::InvalidArgument`](crate::Error::InvalidArgument) if a handler offset does
+    /// not fit into 32 bit for a 32 bit IDT.
+    pub fn write(&self, mem: &mut UserMem, phys: PhysAddr, base: u64) -> Result<kvm_dtable> {
+        let mut bytes = Vec::with_capacity(self.size());
+        for gate in &self.gates {
+            let (lo, hi) = match gate {
+                Some(gate) if !self.long && gate.offset > u64::from(u32::MAX) => {
+                    return Err(Error::InvalidArgument("gate offset exceeds 32 bit"))
+                }
+                Some(gate) => (gate.encode(), gate.offset >> 32),
+                None => (0, 0),
+            };
+
+            bytes.extend_from_slice(&lo.to_le_bytes());
+            if self.long {
+                // Upper 8 bytes of the 16 byte gate hold bits 63:32 of the offset.
+                bytes.extend_from_slice(&hi.to_le_bytes());
+            }
+        }
+        mem.load(phys, &bytes)?;
+
+        let mut dtable = kvm_dtable::default();
+        dtable.base = base;
+        dtable.limit = self.size().saturating_sub(1) as u16;
+        Ok(dtable)
+    }
+
+    /// Write the default exception handlers (see [module documentation](self)) for the exception
+    /// vectors `0-31` to the physical address `phys` of `mem`, and install interrupt gates for
+    /// them, where `base` is the guest linear address mapping `phys` and `selector` the code
+    /// segment of the handlers.
+    ///
+    /// The handlers use [`EXCEPTION_STUBS_SIZE`] bytes.
+    pub fn install_exception_stubs(
+        &mut self,
+        mem: &mut UserMem,
+        phys: PhysAddr,
+        base: u64,
+        selector: u16,
+    ) -> Result<()> {
+        let common = NR_EXCEPTIONS as usize * STUB_SIZE;
+        let mut code = vec![0x90u8; EXCEPTION_STUBS_SIZE];
+
+        for vector in 0..NR_EXCEPTIONS {
+            let off = usize::from(vector) * STUB_SIZE;
+            let stub = &mut code[off..off + STUB_SIZE];
+
+            // Push a dummy error code, if the processor does not push one, to get a uniform
+            // stack layout.
+            if exception_has_error_code(vector) {
+                // nop (xchg ax, ax)
+                stub[0..2].copy_from_slice(&[0x66, 0x90]);
+            } else {
+                // push 0
+                stub[0..2].copy_from_slice(&[0x6a, 0x00]);
+            }
+            // push vector
+            stub[2..4].copy_from_slice(&[0x6a, vector]);
+            // jmp common
+            let rel = common as i32 - (off + 9) as i32;
+            stub[4] = 0xe9;
+            stub[5..9].copy_from_slice(&rel.to_le_bytes());
+
+            self.set(vector, Gate::interrupt(selector, base + off as u64, 0));
+        }
+
+        // pop rax ; vector
+        // pop rsi ; error code
+        // mov rdi, [rsp] ; faulting rip
+        let mut handler = vec![0x58, 0x5e];
+        if self.long {
+            handler.push(0x48);
+        }
+        handler.extend_from_slice(&[0x8b, 0x3c, 0x24]);
+        // mov dx, EXCEPTION_PORT
+        handler.extend_from_slice(&[0x66, 0xba]);
+        handler.extend_from_slice(&EXCEPTION_PORT.to_le_bytes());
+        // out dx, eax
+        // 1: hlt
+        // jmp 1b
+        handler.extend_from_slice(&[0xef, 0xf4, 0xeb, 0xfd]);
+        code[common..common + handler.len()].copy_from_slice(&handler);
+
+        mem.load(phys, &code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn idt_write() {
+        let mut mem = UserMem::new(0x1000).unwrap();
+
+        let mut idt = Idt::new64();
+        idt.set(1, Gate::trap(0x8, 0xffff_8000_1234_5678, 3));
+        assert_eq!(idt.size(), 2 * 16);
+
+        let dtable = idt.write(&mut mem, PhysAddr(0x100), 0x4100).unwrap();
+        assert_eq!((dtable.base, dtable.limit), (0x4100, 31));
+        assert_eq!(&mem.as_ref()[0x100..0x110], &[0; 16]);
+        assert_eq!(
+            &mem.as_ref()[0x110..0x118],
+            &0x1234_ef00_0008_5678u64.to_le_bytes()
+        );
+        assert_eq!(&mem.as_ref()[0x118..0x120], &0xffff_8000u64.to_le_bytes());
+
+        let mut idt = Idt::new32();
+        idt.set(0, Gate::interrupt(0x8, 0x1234_5678, 0));
+        let dtable = idt.write(&mut mem, PhysAddr(0x200), 0x200).unwrap();
+        assert_eq!(dtable.limit, 7);
+        assert_eq!(
+            &mem.as_ref()[0x200..0x208],
+            &0x1234_8e00_0008_5678u64.to_le_bytes()
+        );
+
+        idt.set(0, Gate::interrupt(0x8, 0x1_0000_0000, 0));
+        assert!(matches!(
+            idt.write(&mut mem, PhysAddr(0x200), 0x200),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn idt_exception_stubs() {
+        let mut mem = UserMem::new(0x1000).unwrap();
+
+        let mut idt = Idt::new64();
+        idt.install_exception_stubs(&mut mem, PhysAddr(0x400), 0x8400, 0x8)
+            .unwrap();
+        assert_eq!(idt.size(), 32 * 16);
+
+        let code = &mem.as_ref()[0x400..];
+        // #DE: push 0 ; push 0 ; jmp common.
+        assert_eq!(
+            &code[0..9],
+            &[0x6a, 0x00, 0x6a, 0x00, 0xe9, 0xf7, 0x01, 0x00, 0x00]
+        );
+        // #PF: nop ; push 14 ; jmp common.
+        assert_eq!(&code[0xe0..0xe4], &[0x66, 0x90, 0x6a, 14]);
+        assert_eq!(&code[0x200..0x206], &[0x58, 0x5e, 0x48, 0x8b, 0x3c, 0x24]);
+
+        idt.write(&mut mem, PhysAddr(0x0), 0x8000).unwrap();
+        let pf = u64::from_le_bytes(mem.as_ref()[14 * 16..][..8].try_into().unwrap());
+        assert_eq!(pf, 0x0000_8e00_0008_84e0);
+    }
+}
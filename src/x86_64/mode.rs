@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Helpers to setup the VCPU operating mode.
+//!
+//! The helpers setup the control registers and write a GDT with flat segments and a TSS into
+//! guest memory, which are used as follows.
+//!
+//! | Selector | Descriptor                 |
+//! |----------|----------------------------|
+//! | `0x00`   | null                       |
+//! | `0x08`   | code (`cs`)                |
+//! | `0x10`   | data (`ds, es, fs, gs, ss`) |
+//! | `0x18`   | TSS (`tr`)                 |
+//!
+//! After the operating mode is setup, [`setup_exception_handlers`] can be used to install an IDT
+//! with the [default exception handlers](crate::x86_64::idt).
+
+use crate::kvm_sys::kvm_sregs;
+use crate::vcpu::Vcpu;
+use crate::x86_64::gdt::{Descriptor, Gdt, TSS_SIZE};
+use crate::x86_64::idt::{Idt, EXCEPTION_PORT};
+use crate::x86_64::paging::translate;
+use crate::x86_64::*;
+use crate::{Error, PhysAddr, Result, UserMem};
+
+/// Number of bytes used for the GDT and TSS by the setup helpers.
+pub const GDT_AREA_SIZE: u64 = 0x100;
+
+/// Offset of the TSS relative to the GDT.
+const TSS_OFFSET: u64 = 0x80;
+
+/// Number of bytes used for the IDT and the exception handlers by [`setup_exception_handlers`].
+pub const IDT_AREA_SIZE: u64 = 0x1000;
+
+/// Offset of the exception handlers relative to the IDT.
+const STUBS_OFFSET: u64 = 0x200;
+
+/// Setup 32 bit protected mode without paging.
+///
+/// The GDT is placed at the guest physical address `gdt`, see [`setup_long_mode`].
+pub fn setup_protected_mode(vcpu: &Vcpu, mem: &mut UserMem, gdt: u64) -> Result<()> {
+    let mut sregs = vcpu.get_sregs()?;
+    sregs.cr0 = CR0_PE;
+    sregs.cr3 = 0;
+    sregs.cr4 = 0;
+    sregs.efer = 0;
+
+    setup_segments(&mut sregs, mem, gdt, false)?;
+    vcpu.set_sregs(sregs)
+}
+
+/// Setup 32 bit protected mode with `PAE` paging, `cr3` references the page directory pointer
+/// table.
+///
+/// The GDT is placed at the guest linear address `gdt`, see [`setup_long_mode`].
+pub fn setup_pae_mode(vcpu: &Vcpu, mem: &mut UserMem, cr3: u64, gdt: u64) -> Result<()> {
+    let mut sregs = vcpu.get_sregs()?;
+    sregs.cr0 = CR0_PG | CR0_PE;
+    sregs.cr3 = cr3;
+    sregs.cr4 = CR4_PAE;
+    sregs.efer = 0;
+
+    setup_segments(&mut sregs, mem, gdt, false)?;
+    vcpu.set_sregs(sregs)
+}
+
+/// Setup 64 bit long mode with `4-level` paging, `cr3` references the `PML4` (eg
+/// [`PageTables::cr3`](crate::x86_64::paging::PageTables::cr3)).
+///
+/// The GDT and TSS use [`GDT_AREA_SIZE`] bytes at the guest linear address `gdt`, which must be
+/// aligned to [`GDT_AREA_SIZE`] and mapped by the page tables.
+pub fn setup_long_mode(vcpu: &Vcpu, mem: &mut UserMem, cr3: u64, gdt: u64) -> Result<()> {
+    let mut sregs = vcpu.get_sregs()?;
+    sregs.cr0 = CR0_PG | CR0_PE;
+    sregs.cr3 = cr3;
+    sregs.cr4 = CR4_PAE;
+    sregs.efer = EFER_LMA | EFER_LME;
+
+    setup_segments(&mut sregs, mem, gdt, true)?;
+    vcpu.set_sregs(sregs)
+}
+
+/// Install an IDT with the [default exception handlers](crate::x86_64::idt) for the current
+/// operating mode of `vcpu` and enable decoding
+/// [`KvmExit::GuestException`](crate::vcpu::KvmExit::GuestException) exits on
+/// [`EXCEPTION_PORT`](crate::x86_64::idt::EXCEPTION_PORT).
+///
+/// The IDT and handlers use [`IDT_AREA_SIZE`] bytes at the guest linear address `idt`, which must
+/// be aligned to [`IDT_AREA_SIZE`] and mapped executable by the page tables.
+pub fn setup_exception_handlers(vcpu: &mut Vcpu, mem: &mut UserMem, idt: u64) -> Result<()> {
+    if !idt.is_multiple_of(IDT_AREA_SIZE) {
+        return Err(Error::InvalidArgument("IDT not aligned to IDT_AREA_SIZE"));
+    }
+
+    let mut sregs = vcpu.get_sregs()?;
+
+    // The IDT area does not cross a page, hence translating the start is sufficient.
+    let phys = match translate(&sregs, mem, idt)? {
+        Some(tr) => tr.phys,
+        None => return Err(Error::InvalidArgument("IDT address not mapped")),
+    };
+
+    let mut table = if sregs.efer & EFER_LMA != 0 {
+        Idt::new64()
+    } else {
+        Idt::new32()
+    };
+    table.install_exception_stubs(
+        mem,
+        PhysAddr(phys.0 + STUBS_OFFSET),
+        idt + STUBS_OFFSET,
+        sregs.cs.selector,
+    )?;
+    sregs.idt = table.write(mem, phys, idt)?;
+
+    vcpu.set_sregs(sregs)?;
+    vcpu.set_exception_port(Some(EXCEPTION_PORT));
+    Ok(())
+}
+
+/// Write the GDT and TSS to `gdt` and load the segment registers in `sregs`.
+fn setup_segments(sregs: &mut kvm_sregs, mem: &mut UserMem, gdt: u64, long: bool) -> Result<()> {
+    if !gdt.is_multiple_of(GDT_AREA_SIZE) {
+        return Err(Error::InvalidArgument("GDT not aligned to GDT_AREA_SIZE"));
+    }
+
+    // The GDT area does not cross a page, hence translating the start is sufficient.
+    let phys = match translate(sregs, mem, gdt)? {
+        Some(tr) => tr.phys,
+        None => return Err(Error::InvalidArgument("GDT address not mapped")),
+    };
+
+    let tss = gdt + TSS_OFFSET;
+    let (code, tss_desc) = if long {
+        (Descriptor::code64(0), Descriptor::tss64(tss, TSS_SIZE - 1))
+    } else {
+        (
+            Descriptor::code32(0, 0xffff_ffff, 0),
+            Descriptor::tss32(tss as u32, TSS_SIZE - 1),
+        )
+    };
+
+    let mut table = Gdt::new();
+    let cs = table.add(code);
+    let ds = table.add(Descriptor::data(0, 0xffff_ffff, 0));
+    let tr = table.add(tss_desc);
+    sregs.gdt = table.write(mem, phys, gdt)?;
+
+    // Zeroed TSS with the IO permission bitmap base pointing past the TSS limit (no bitmap).
+    let mut tss_bytes = [0u8; TSS_SIZE as usize];
+    tss_bytes[102..].copy_from_slice(&(TSS_SIZE as u16).to_le_bytes());
+    mem.load(PhysAddr(phys.0 + TSS_OFFSET), &tss_bytes)?;
+
+    sregs.cs = cs;
+    sregs.ds = ds;
+    sregs.es = ds;
+    sregs.fs = ds;
+    sregs.gs = ds;
+    sregs.ss = ds;
+    sregs.tr = tr;
+    Ok(())
+}
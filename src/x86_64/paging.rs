@@ -0,0 +1,814 @@
+// SPDX-License-Identifier: MIT
+//
+// Copyright (c) 2021, Johannes Stoelp <dev@memzero.de>
+
+//! Guest paging structures.
+
+use std::ops;
+
+use crate::kvm_sys::kvm_sregs;
+use crate::x86_64::*;
+use crate::{Error, PhysAddr, Result, UserMem};
+
+/// Size of a `4K` page.
+pub const PAGE_SIZE_4K: u64 = 0x1000;
+/// Size of a `2M` page.
+pub const PAGE_SIZE_2M: u64 = 0x20_0000;
+/// Size of a `4M` page (`32-bit` paging).
+pub const PAGE_SIZE_4M: u64 = 0x40_0000;
+/// Size of a `1G` page.
+pub const PAGE_SIZE_1G: u64 = 0x4000_0000;
+
+/// Result of translating a guest virtual address with [`translate`].
+///
+/// The permissions are the combined permissions of all paging entries used for the translation.
+/// With paging disabled all permissions are granted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Translation {
+    /// Guest physical address.
+    pub phys: PhysAddr,
+    /// Size of the page mapping the virtual address.
+    pub page_size: u64,
+    /// Page is writeable ([`PAGE_ENTRY_RW`](crate::x86_64::PAGE_ENTRY_RW)).
+    pub writeable: bool,
+    /// Page is accessible from user mode ([`PAGE_ENTRY_US`](crate::x86_64::PAGE_ENTRY_US)).
+    pub user: bool,
+    /// Page is executable ([`PAGE_ENTRY_NX`](crate::x86_64::PAGE_ENTRY_NX)).
+    pub executable: bool,
+}
+
+/// Translate the guest virtual address `addr` to a guest physical address by walking the guest
+/// page tables in `mem` in software.
+///
+/// The paging mode is selected by `CR0`, `CR4` and `EFER` of `sregs` and supports `32-bit`,
+/// `PAE`, `4-level` and `5-level` paging. The page tables are located by `CR3`. `mem` is expected
+/// to be mapped at guest physical address `0`.
+///
+/// Returns `None` if the address is not mapped or not canonical. Returns
+/// [`Error::GuestMemOutOfBounds`](crate::Error::GuestMemOutOfBounds) if a paging structure is not
+/// located in `mem`.
+///
+/// The [`Vcpu::translate`](crate::vcpu::Vcpu::translate) function asks KVM to do the same
+/// translation for the current VCPU state.
+pub fn translate(sregs: &kvm_sregs, mem: &UserMem, addr: u64) -> Result<Option<Translation>> {
+    if sregs.cr0 & CR0_PG == 0 {
+        // Without paging, linear addresses are physical addresses.
+        return Ok(Some(Translation {
+            phys: PhysAddr(addr & 0xffff_ffff),
+            page_size: PAGE_SIZE_4K,
+            writeable: true,
+            user: true,
+            executable: true,
+        }));
+    }
+
+    if sregs.cr4 & CR4_PAE == 0 {
+        return translate_32bit(sregs, mem, addr & 0xffff_ffff);
+    }
+
+    let long_mode = sregs.efer & EFER_LMA != 0;
+    let nx = sregs.efer & EFER_NXE != 0;
+
+    // Bit positions of the virtual address indexing into the paging structures.
+    let (shifts, addr, mut table): (&[u64], _, _) = if !long_mode {
+        // PAE paging: PDPT is 32 byte aligned.
+        (&[30, 21, 12], addr & 0xffff_ffff, sregs.cr3 & 0xffff_ffe0)
+    } else {
+        let bits = if sregs.cr4 & CR4_LA57 != 0 { 57 } else { 48 };
+        if !is_canonical(addr, bits) {
+            return Ok(None);
+        }
+
+        let shifts: &[u64] = if bits == 57 {
+            &[48, 39, 30, 21, 12]
+        } else {
+            &[39, 30, 21, 12]
+        };
+        (shifts, addr, sregs.cr3 & CR3_PAGE_BASE_MASK)
+    };
+
+    let mut writeable = true;
+    let mut user = true;
+    let mut executable = true;
+
+    for (level, &shift) in shifts.iter().enumerate() {
+        // PAE paging only has 4 PDPT entries, which don't have permission bits.
+        let pae_pdpt = !long_mode && level == 0;
+
+        let idx = (addr >> shift) & if pae_pdpt { 0x3 } else { 0x1ff };
+        let entry = read_entry(mem, table + idx * 8)?;
+
+        if entry & PAGE_ENTRY_PRESENT == 0 {
+            return Ok(None);
+        }
+
+        if !pae_pdpt {
+            writeable &= entry & PAGE_ENTRY_RW != 0;
+            user &= entry & PAGE_ENTRY_US != 0;
+        }
+        if nx {
+            executable &= entry & PAGE_ENTRY_NX == 0;
+        }
+
+        // Large pages can be mapped by PDPT entries (1G) and PD entries (2M).
+        let large = !pae_pdpt && (shift == 21 || shift == 30) && entry & PAGE_ENTRY_PS != 0;
+
+        if shift == 12 || large {
+            let page_size = 1 << shift;
+            let base = entry & PAGE_ENTRY_ADDR_MASK & !(page_size - 1);
+
+            return Ok(Some(Translation {
+                phys: PhysAddr(base | (addr & (page_size - 1))),
+                page_size,
+                writeable,
+                user,
+                executable,
+            }));
+        }
+
+        table = entry & PAGE_ENTRY_ADDR_MASK;
+    }
+
+    unreachable!("last paging level maps a page");
+}
+
+/// Translate with `32-bit` paging using 4 byte paging entries.
+fn translate_32bit(sregs: &kvm_sregs, mem: &UserMem, addr: u64) -> Result<Option<Translation>> {
+    let pd = sregs.cr3 & 0xffff_f000;
+    let pde = read_entry32(mem, pd + ((addr >> 22) & 0x3ff) * 4)?;
+
+    if pde & PAGE_ENTRY_PRESENT == 0 {
+        return Ok(None);
+    }
+
+    if sregs.cr4 & CR4_PSE != 0 && pde & PAGE_ENTRY_PS != 0 {
+        // 4M page, bits 20:13 of the PDE hold bits 39:32 of the physical address (PSE-36).
+        let base = (pde & 0xffc0_0000) | ((pde >> 13) & 0xff) << 32;
+
+        return Ok(Some(Translation {
+            phys: PhysAddr(base | (addr & (PAGE_SIZE_4M - 1))),
+            page_size: PAGE_SIZE_4M,
+            writeable: pde & PAGE_ENTRY_RW != 0,
+            user: pde & PAGE_ENTRY_US != 0,
+            executable: true,
+        }));
+    }
+
+    let pt = pde & 0xffff_f000;
+    let pte = read_entry32(mem, pt + ((addr >> 12) & 0x3ff) * 4)?;
+
+    if pte & PAGE_ENTRY_PRESENT == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(Translation {
+        phys: PhysAddr((pte & 0xffff_f000) | (addr & (PAGE_SIZE_4K - 1))),
+        page_size: PAGE_SIZE_4K,
+        writeable: pde & pte & PAGE_ENTRY_RW != 0,
+        user: pde & pte & PAGE_ENTRY_US != 0,
+        executable: true,
+    }))
+}
+
+/// Attributes of a page mapping created with [`PageTables::map`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    /// Page is present, readonly, supervisor only and executable.
+    pub const NONE: PageFlags = PageFlags(0);
+    /// Page is writeable ([`PAGE_ENTRY_RW`](crate::x86_64::PAGE_ENTRY_RW)).
+    pub const RW: PageFlags = PageFlags(PAGE_ENTRY_RW);
+    /// Page is accessible from user mode ([`PAGE_ENTRY_US`](crate::x86_64::PAGE_ENTRY_US)).
+    pub const USER: PageFlags = PageFlags(PAGE_ENTRY_US);
+    /// Page is not executable ([`PAGE_ENTRY_NX`](crate::x86_64::PAGE_ENTRY_NX)), requires
+    /// [`EFER_NXE`](crate::x86_64::EFER_NXE).
+    pub const NX: PageFlags = PageFlags(PAGE_ENTRY_NX);
+    /// Page is global ([`PAGE_ENTRY_GLOBAL`](crate::x86_64::PAGE_ENTRY_GLOBAL)).
+    pub const GLOBAL: PageFlags = PageFlags(PAGE_ENTRY_GLOBAL);
+
+    /// Check if all flags in `other` are set.
+    pub fn contains(self, other: PageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl ops::BitOr for PageFlags {
+    type Output = PageFlags;
+
+    fn bitor(self, rhs: PageFlags) -> PageFlags {
+        PageFlags(self.0 | rhs.0)
+    }
+}
+
+/// Page size used by [`PageTables::map`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    /// Size in bytes.
+    pub fn bytes(self) -> u64 {
+        match self {
+            PageSize::Size4K => PAGE_SIZE_4K,
+            PageSize::Size2M => PAGE_SIZE_2M,
+            PageSize::Size1G => PAGE_SIZE_1G,
+        }
+    }
+
+    /// Bit position of the virtual address indexing the paging structure mapping the page.
+    fn shift(self) -> u64 {
+        match self {
+            PageSize::Size4K => 12,
+            PageSize::Size2M => 21,
+            PageSize::Size1G => 30,
+        }
+    }
+}
+
+/// Builder for `4-level` page tables in guest memory.
+///
+/// Paging structures are allocated from a physical range of the [`UserMem`](crate::UserMem),
+/// which is expected to be mapped at guest physical address `0`. Intermediate paging entries
+/// grant all permissions, the [`PageFlags`] are applied to the entries mapping the pages.
+///
+/// ```text
+/// +------+    +------+    +------+    +------+
+/// | PML4 | -> | PDPT | -> |  PD  | -> |  PT  | -> 4K page
+/// +------+    +------+    +------+    +------+
+///                 |           +-----------------> 2M page
+///                 +-----------------------------> 1G page
+/// ```
+pub struct PageTables {
+    pml4: PhysAddr,
+    /// Next free page to allocate a paging structure from.
+    next: u64,
+    /// End of the physical range to allocate paging structures from.
+    end: u64,
+}
+
+impl PageTables {
+    /// Create empty page tables, allocating paging structures from the physical range `[base :
+    /// base + len)` of `mem`.
+    ///
+    /// The range must be `4K` aligned and is used starting with the `PML4` at `base`.
+    pub fn new(mem: &mut UserMem, base: PhysAddr, len: u64) -> Result<PageTables> {
+        if !base.0.is_multiple_of(PAGE_SIZE_4K) || !len.is_multiple_of(PAGE_SIZE_4K) {
+            return Err(Error::InvalidArgument("page table range not 4K aligned"));
+        }
+
+        let mut pt = PageTables {
+            pml4: base,
+            next: base.0,
+            end: base.0 + len,
+        };
+        pt.pml4 = pt.alloc(mem)?;
+        Ok(pt)
+    }
+
+    /// Value for `CR3` referencing the `PML4`.
+    pub fn cr3(&self) -> u64 {
+        self.pml4.0
+    }
+
+    /// Map the virtual range `[virt : virt + len)` to the physical range `[phys : phys + len)`
+    /// using pages of size `size` with attributes `flags`.
+    ///
+    /// Existing page mappings are replaced. Returns
+    /// [`Error::InvalidArgument`](crate::Error::InvalidArgument) if the ranges are not aligned to
+    /// the page size, the virtual range is not canonical, a page overlaps with a page of a
+    /// different size, the ranges exceed the address space or no memory is left to allocate
+    /// paging structures. Running out of memory is detected before any paging entry is
+    /// modified.
+    pub fn map(
+        &mut self,
+        mem: &mut UserMem,
+        virt: u64,
+        phys: PhysAddr,
+        len: u64,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<()> {
+        let page = size.bytes();
+        if !virt.is_multiple_of(page) || !phys.0.is_multiple_of(page) || !len.is_multiple_of(page) {
+            return Err(Error::InvalidArgument("mapping not aligned to page size"));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        let last = match (virt.checked_add(len - 1), phys.0.checked_add(len - 1)) {
+            (Some(last), Some(_)) => last,
+            _ => return Err(Error::InvalidArgument("mapping exceeds address space")),
+        };
+        // Both ends must be canonical and in the same half, the range can't span the hole.
+        if !is_canonical(virt, 48) || !is_canonical(last, 48) || (virt ^ last) >> 63 != 0 {
+            return Err(Error::InvalidArgument("virtual address not canonical"));
+        }
+
+        // Fail before modifying any paging entry if not enough memory is left.
+        if self.missing_tables(mem, virt, last, size)? > (self.end - self.next) / PAGE_SIZE_4K {
+            return Err(Error::InvalidArgument("page table memory exhausted"));
+        }
+
+        let large = if size == PageSize::Size4K {
+            0
+        } else {
+            PAGE_ENTRY_PS
+        };
+
+        for off in (0..len).step_by(page as usize) {
+            let entry = self.entry(mem, virt + off, size)?;
+            let val = (phys.0 + off) | PAGE_ENTRY_PRESENT | large | flags.0;
+            mem.load(entry, &val.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Identity map the physical range `[phys : phys + len)`, see [`PageTables::map`].
+    pub fn identity_map(
+        &mut self,
+        mem: &mut UserMem,
+        phys: PhysAddr,
+        len: u64,
+        size: PageSize,
+        flags: PageFlags,
+    ) -> Result<()> {
+        self.map(mem, phys.0, phys, len, size, flags)
+    }
+
+    /// Number of paging structures to allocate to map the virtual range `[virt : last]` with pages
+    /// of size `size`.
+    fn missing_tables(&self, mem: &UserMem, virt: u64, last: u64, size: PageSize) -> Result<u64> {
+        let mut missing = 0;
+
+        // Each entry of the level `shift` references a paging structure covering `1 << shift`.
+        for &shift in [39, 30, 21]
+            .iter()
+            .take_while(|&&shift| shift > size.shift())
+        {
+            for idx in (virt >> shift)..=(last >> shift) {
+                if !self.has_table(mem, idx << shift, shift)? {
+                    missing += 1;
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Check if the paging structure referenced by the entry of the level `shift` for `virt`
+    /// exists.
+    fn has_table(&self, mem: &UserMem, virt: u64, shift: u64) -> Result<bool> {
+        let mut table = self.pml4;
+
+        for &level in &[39, 30, 21] {
+            let val = read_entry(mem, table.0 + ((virt >> level) & 0x1ff) * 8)?;
+            if val & PAGE_ENTRY_PRESENT == 0 {
+                return Ok(false);
+            }
+            if val & PAGE_ENTRY_PS != 0 {
+                return Err(Error::InvalidArgument("page overlaps with larger page"));
+            }
+            if level == shift {
+                return Ok(true);
+            }
+            table = PhysAddr(val & PAGE_ENTRY_ADDR_MASK);
+        }
+
+        unreachable!("last paging level maps a page");
+    }
+
+    /// Get the address of the paging entry mapping the page of size `size` at `virt`, allocating
+    /// intermediate paging structures as needed.
+    fn entry(&mut self, mem: &mut UserMem, virt: u64, size: PageSize) -> Result<PhysAddr> {
+        let mut table = self.pml4;
+
+        for &shift in &[39, 30, 21, 12] {
+            let entry = PhysAddr(table.0 + ((virt >> shift) & 0x1ff) * 8);
+            if shift == size.shift() {
+                let val = read_entry(mem, entry.0)?;
+                if val & PAGE_ENTRY_PRESENT != 0 && (shift != 12 && val & PAGE_ENTRY_PS == 0) {
+                    return Err(Error::InvalidArgument("page overlaps with smaller pages"));
+                }
+                return Ok(entry);
+            }
+
+            let val = read_entry(mem, entry.0)?;
+            table = if val & PAGE_ENTRY_PRESENT == 0 {
+                let next = self.alloc(mem)?;
+                let val = next.0 | PAGE_ENTRY_PRESENT | PAGE_ENTRY_RW | PAGE_ENTRY_US;
+                mem.load(entry, &val.to_le_bytes())?;
+                next
+            } else if val & PAGE_ENTRY_PS != 0 {
+                return Err(Error::InvalidArgument("page overlaps with larger page"));
+            } else {
+                PhysAddr(val & PAGE_ENTRY_ADDR_MASK)
+            };
+        }
+
+        unreachable!("last paging level maps a page");
+    }
+
+    /// Allocate a zeroed page for a paging structure.
+    fn alloc(&mut self, mem: &mut UserMem) -> Result<PhysAddr> {
+        if self.next >= self.end {
+            return Err(Error::InvalidArgument("page table memory exhausted"));
+        }
+
+        let page = PhysAddr(self.next);
+        mem.load(page, &[0u8; PAGE_SIZE_4K as usize])?;
+        self.next += PAGE_SIZE_4K;
+        Ok(page)
+    }
+}
+
+/// Read the 8 byte paging entry at physical address `addr`.
+fn read_entry(mem: &UserMem, addr: u64) -> Result<u64> {
+    let mut entry = [0u8; 8];
+    mem.read(PhysAddr(addr), &mut entry)?;
+    Ok(u64::from_le_bytes(entry))
+}
+
+/// Read the 4 byte `32-bit` paging entry at physical address `addr`.
+fn read_entry32(mem: &UserMem, addr: u64) -> Result<u64> {
+    let mut entry = [0u8; 4];
+    mem.read(PhysAddr(addr), &mut entry)?;
+    Ok(u64::from(u32::from_le_bytes(entry)))
+}
+
+/// Check if bits `63:bits-1` of `addr` are all equal.
+fn is_canonical(addr: u64, bits: u32) -> bool {
+    let upper = (addr as i64) >> (bits - 1);
+    upper == 0 || upper == -1
+}
+
+#[cfg(test)]
+#[allow(clippy::identity_op)]
+mod tests {
+    use super::*;
+
+    fn w32(mem: &mut UserMem, addr: u64, val: u32) {
+        mem.load(PhysAddr(addr), &val.to_le_bytes()).unwrap();
+    }
+
+    fn w64(mem: &mut UserMem, addr: u64, val: u64) {
+        mem.load(PhysAddr(addr), &val.to_le_bytes()).unwrap();
+    }
+
+    fn sregs(cr0: u64, cr3: u64, cr4: u64, efer: u64) -> kvm_sregs {
+        kvm_sregs {
+            cr0,
+            cr3,
+            cr4,
+            efer,
+            ..Default::default()
+        }
+    }
+
+    const P: u64 = PAGE_ENTRY_PRESENT;
+    const RW: u64 = PAGE_ENTRY_RW;
+    const US: u64 = PAGE_ENTRY_US;
+
+    #[test]
+    fn translate_no_paging() {
+        let mem = UserMem::new(0x1000).unwrap();
+        let sregs = sregs(CR0_PE, 0, 0, 0);
+
+        let tr = translate(&sregs, &mem, 0x1234_5678).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x1234_5678));
+        assert!(tr.writeable && tr.user && tr.executable);
+    }
+
+    #[test]
+    fn translate_32bit() {
+        let mut mem = UserMem::new(0x4000).unwrap();
+        // PDE[1] -> PT at 0x2000, PDE[2] -> 4M page at 0x1_0080_0000 (PSE-36).
+        w32(&mut mem, 0x1000 + 1 * 4, (0x2000 | P | RW) as u32);
+        w32(
+            &mut mem,
+            0x1000 + 2 * 4,
+            (0x80_0000 | 1 << 13 | P | PAGE_ENTRY_PS) as u32,
+        );
+        // PTE[3] -> 0x3000, readonly user page.
+        w32(&mut mem, 0x2000 + 3 * 4, (0x3000 | P | US) as u32);
+
+        let sregs = sregs(CR0_PG | CR0_PE, 0x1000, CR4_PSE, 0);
+
+        let tr = translate(&sregs, &mem, 0x40_3123).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x3123));
+        assert_eq!(tr.page_size, PAGE_SIZE_4K);
+        assert!(!tr.writeable && !tr.user);
+
+        let tr = translate(&sregs, &mem, 0x81_2345).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x1_0081_2345));
+        assert_eq!(tr.page_size, PAGE_SIZE_4M);
+
+        assert_eq!(translate(&sregs, &mem, 0x40_4000).unwrap(), None);
+        assert_eq!(translate(&sregs, &mem, 0xc0_0000).unwrap(), None);
+    }
+
+    #[test]
+    fn translate_pae() {
+        let mut mem = UserMem::new(0x4000).unwrap();
+        // PDPTE[1] -> PD at 0x2000.
+        w64(&mut mem, 0x1020 + 1 * 8, 0x2000 | P);
+        // PDE[0] -> PT at 0x3000, PDE[1] -> 2M page at 0x60_0000 (NX).
+        w64(&mut mem, 0x2000, 0x3000 | P | RW);
+        w64(
+            &mut mem,
+            0x2000 + 1 * 8,
+            0x60_0000 | P | PAGE_ENTRY_PS | PAGE_ENTRY_NX,
+        );
+        // PTE[2] -> 0x5000.
+        w64(&mut mem, 0x3000 + 2 * 8, 0x5000 | P | RW);
+
+        let sregs = sregs(CR0_PG | CR0_PE, 0x1020, CR4_PAE, EFER_NXE);
+
+        let tr = translate(&sregs, &mem, 0x4000_2010).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x5010));
+        assert!(tr.writeable && tr.executable);
+
+        let tr = translate(&sregs, &mem, 0x4020_0010).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x60_0010));
+        assert_eq!(tr.page_size, PAGE_SIZE_2M);
+        assert!(!tr.writeable && !tr.executable);
+
+        assert_eq!(translate(&sregs, &mem, 0x0000_2010).unwrap(), None);
+    }
+
+    #[test]
+    fn translate_4level() {
+        let mut mem = UserMem::new(0x5000).unwrap();
+        // PML4E[1] -> PDPT at 0x2000.
+        w64(&mut mem, 0x1000 + 1 * 8, 0x2000 | P | RW | US);
+        // PDPTE[0] -> PD at 0x3000, PDPTE[1] -> 1G page at 0x8000_0000.
+        w64(&mut mem, 0x2000, 0x3000 | P | RW | US);
+        w64(
+            &mut mem,
+            0x2000 + 1 * 8,
+            0x8000_0000 | P | PAGE_ENTRY_PS | US,
+        );
+        // PDE[0] -> PT at 0x4000.
+        w64(&mut mem, 0x3000, 0x4000 | P | RW | US);
+        // PTE[0] -> 0x7000, NX without EFER.NXE is ignored.
+        w64(&mut mem, 0x4000, 0x7000 | P | RW | PAGE_ENTRY_NX);
+
+        let sregs = sregs(CR0_PG | CR0_PE, 0x1000, CR4_PAE, EFER_LMA | EFER_LME);
+
+        let tr = translate(&sregs, &mem, 0x80_0000_0abc).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x7abc));
+        assert_eq!(tr.page_size, PAGE_SIZE_4K);
+        assert!(tr.writeable && !tr.user && tr.executable);
+
+        let tr = translate(&sregs, &mem, 0x80_4123_4567).unwrap().unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x8123_4567));
+        assert_eq!(tr.page_size, PAGE_SIZE_1G);
+        assert!(!tr.writeable && tr.user);
+
+        assert_eq!(translate(&sregs, &mem, 0x0).unwrap(), None);
+        // Not canonical.
+        assert_eq!(translate(&sregs, &mem, 0x1_0080_0000_0abc).unwrap(), None);
+    }
+
+    #[test]
+    fn translate_5level() {
+        let mut mem = UserMem::new(0x6000).unwrap();
+        // PML5E[0x100] -> PML4 at 0x2000 -> PDPT at 0x3000 -> PD at 0x4000 -> PT at 0x5000.
+        w64(&mut mem, 0x1000 + 0x100 * 8, 0x2000 | P | RW);
+        w64(&mut mem, 0x2000, 0x3000 | P | RW);
+        w64(&mut mem, 0x3000, 0x4000 | P | RW);
+        w64(&mut mem, 0x4000, 0x5000 | P | RW);
+        w64(&mut mem, 0x5000 + 8, 0x1000 | P | RW);
+
+        let sregs = sregs(
+            CR0_PG | CR0_PE,
+            0x1000,
+            CR4_PAE | CR4_LA57,
+            EFER_LMA | EFER_LME,
+        );
+
+        let tr = translate(&sregs, &mem, 0xff00_0000_0000_1008)
+            .unwrap()
+            .unwrap();
+        assert_eq!(tr.phys, PhysAddr(0x1008));
+
+        // Canonical with 48 bits, but upper bits of the 57 bit address are not sign extended.
+        assert_eq!(
+            translate(&sregs, &mem, 0xffff_8000_0000_1008).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn page_tables_map() {
+        let mut mem = UserMem::new(0x10000).unwrap();
+        let mut pt = PageTables::new(&mut mem, PhysAddr(0x1000), 0x8000).unwrap();
+        assert_eq!(pt.cr3(), 0x1000);
+
+        let rw = PageFlags::RW;
+        pt.map(
+            &mut mem,
+            0x40_0000,
+            PhysAddr(0xa000),
+            0x2000,
+            PageSize::Size4K,
+            rw,
+        )
+        .unwrap();
+        pt.map(
+            &mut mem,
+            0x60_0000,
+            PhysAddr(0x20_0000),
+            PAGE_SIZE_2M,
+            PageSize::Size2M,
+            PageFlags::NX,
+        )
+        .unwrap();
+        pt.map(
+            &mut mem,
+            0xffff_8000_0000_0000,
+            PhysAddr(0x0),
+            PAGE_SIZE_1G,
+            PageSize::Size1G,
+            PageFlags::USER,
+        )
+        .unwrap();
+        pt.identity_map(&mut mem, PhysAddr(0x0), 0x1000, PageSize::Size4K, rw)
+            .unwrap();
+
+        let sregs = sregs(
+            CR0_PG | CR0_PE,
+            pt.cr3(),
+            CR4_PAE,
+            EFER_LMA | EFER_LME | EFER_NXE,
+        );
+        let tr = |addr| translate(&sregs, &mem, addr).unwrap().unwrap();
+
+        assert_eq!(tr(0x40_1008).phys, PhysAddr(0xb008));
+        assert!(tr(0x40_1008).writeable && tr(0x40_1008).executable);
+        assert_eq!(tr(0x61_2345).phys, PhysAddr(0x21_2345));
+        assert_eq!(tr(0x61_2345).page_size, PAGE_SIZE_2M);
+        assert!(!tr(0x61_2345).writeable && !tr(0x61_2345).executable);
+        assert_eq!(tr(0xffff_8000_1234_5678).phys, PhysAddr(0x1234_5678));
+        assert!(tr(0xffff_8000_1234_5678).user);
+        assert_eq!(tr(0x123).phys, PhysAddr(0x123));
+        assert_eq!(translate(&sregs, &mem, 0x40_2000).unwrap(), None);
+    }
+
+    #[test]
+    fn page_tables_invalid() {
+        let mut mem = UserMem::new(0x4000).unwrap();
+        assert!(PageTables::new(&mut mem, PhysAddr(0x800), 0x1000).is_err());
+
+        let mut pt = PageTables::new(&mut mem, PhysAddr(0x0), 0x3000).unwrap();
+        let none = PageFlags::NONE;
+
+        // Not aligned.
+        assert!(pt
+            .map(
+                &mut mem,
+                0x1000,
+                PhysAddr(0x0),
+                PAGE_SIZE_2M,
+                PageSize::Size2M,
+                none
+            )
+            .is_err());
+        // Not canonical.
+        assert!(pt
+            .map(
+                &mut mem,
+                0x8000_0000_0000,
+                PhysAddr(0x0),
+                0x1000,
+                PageSize::Size4K,
+                none
+            )
+            .is_err());
+
+        pt.map(
+            &mut mem,
+            0x0,
+            PhysAddr(0x0),
+            PAGE_SIZE_2M,
+            PageSize::Size2M,
+            none,
+        )
+        .unwrap();
+        // Overlaps with 2M page.
+        assert!(pt
+            .map(
+                &mut mem,
+                0x1000,
+                PhysAddr(0x0),
+                0x1000,
+                PageSize::Size4K,
+                none
+            )
+            .is_err());
+        // Overlaps with PD referenced by the PDPT entry.
+        assert!(pt
+            .map(
+                &mut mem,
+                0x0,
+                PhysAddr(0x0),
+                PAGE_SIZE_1G,
+                PageSize::Size1G,
+                none
+            )
+            .is_err());
+        // Remap the 2M page.
+        pt.map(
+            &mut mem,
+            0x0,
+            PhysAddr(0x40_0000),
+            PAGE_SIZE_2M,
+            PageSize::Size2M,
+            none,
+        )
+        .unwrap();
+        // PML4, PDPT, PD allocated, no memory left for another PDPT.
+        assert!(pt
+            .map(
+                &mut mem,
+                0x80_0000_0000,
+                PhysAddr(0x0),
+                PAGE_SIZE_1G,
+                PageSize::Size1G,
+                none
+            )
+            .is_err());
+        // Virtual or physical end overflows.
+        assert!(pt
+            .map(
+                &mut mem,
+                0xffff_ffff_ffe0_0000,
+                PhysAddr(0x0),
+                2 * PAGE_SIZE_2M,
+                PageSize::Size2M,
+                none
+            )
+            .is_err());
+        assert!(pt
+            .map(
+                &mut mem,
+                0x0,
+                PhysAddr(0xffff_ffff_ffe0_0000),
+                2 * PAGE_SIZE_2M,
+                PageSize::Size2M,
+                none
+            )
+            .is_err());
+        // Spans the non-canonical hole.
+        assert!(pt
+            .map(
+                &mut mem,
+                0x7fff_ffe0_0000,
+                PhysAddr(0x0),
+                0xffff_8000_0000_0000 - 0x7fff_ffe0_0000 + PAGE_SIZE_2M,
+                PageSize::Size2M,
+                none
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn page_tables_exhausted() {
+        let mut mem = UserMem::new(0x5000).unwrap();
+        let mut pt = PageTables::new(&mut mem, PhysAddr(0x0), 0x4000).unwrap();
+
+        // PML4, PDPT, PD and a single PT fit, mapping two PTs fails without modifying any entry.
+        let before = mem.as_ref().to_vec();
+        assert!(pt
+            .map(
+                &mut mem,
+                0x0,
+                PhysAddr(0x0),
+                2 * PAGE_SIZE_2M,
+                PageSize::Size4K,
+                PageFlags::NONE
+            )
+            .is_err());
+        assert_eq!(mem.as_ref(), &before[..]);
+
+        pt.map(
+            &mut mem,
+            0x0,
+            PhysAddr(0x0),
+            PAGE_SIZE_2M,
+            PageSize::Size4K,
+            PageFlags::NONE,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn translate_out_of_bounds() {
+        let mem = UserMem::new(0x1000).unwrap();
+        let sregs = sregs(CR0_PG | CR0_PE, 0x8000, CR4_PAE, EFER_LMA | EFER_LME);
+        assert!(translate(&sregs, &mem, 0x0).is_err());
+    }
+}
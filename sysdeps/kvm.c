@@ -24,6 +24,18 @@ int main() {
     // param: none
     // ret  : size of vcpu mmap region in bytes
     printf("pub(crate) const KVM_GET_VCPU_MMAP_SIZE : u64 = 0x%x;\n", KVM_GET_VCPU_MMAP_SIZE);
+    // param: struct kvm_msr_list
+    // ret  : 0 success, -1 error (E2BIG if list too small, nmsrs set to required size)
+    printf("pub(crate) const KVM_GET_MSR_INDEX_LIST : u64 = 0x%lx;\n", KVM_GET_MSR_INDEX_LIST);
+    // param: struct kvm_msr_list
+    // ret  : 0 success, -1 error (E2BIG if list too small, nmsrs set to required size)
+    printf("pub(crate) const KVM_GET_MSR_FEATURE_INDEX_LIST : u64 = 0x%lx;\n", KVM_GET_MSR_FEATURE_INDEX_LIST);
+    // param: struct kvm_msrs (also used for feature MSRs on /dev/kvm)
+    // ret  : number of msrs successfully returned, -1 error
+    printf("pub(crate) const KVM_GET_MSRS : u64 = 0x%lx;\n", KVM_GET_MSRS);
+    // param: struct kvm_cpuid2
+    // ret  : 0 success, -1 error (E2BIG if nent too small)
+    printf("pub(crate) const KVM_GET_SUPPORTED_CPUID : u64 = 0x%lx;\n", KVM_GET_SUPPORTED_CPUID);
 
     /* ioctl's for VM fd */
 
@@ -33,6 +45,68 @@ int main() {
     // param: struct kvm_userspace_memory_region
     // ret  : 0 success, -1 error
     printf("pub(crate) const KVM_SET_USER_MEMORY_REGION : u64 = 0x%lx;\n", KVM_SET_USER_MEMORY_REGION);
+    // param: struct kvm_dirty_log
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_DIRTY_LOG : u64 = 0x%lx;\n", KVM_GET_DIRTY_LOG);
+    // param: struct kvm_clear_dirty_log
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_CLEAR_DIRTY_LOG : u64 = 0x%lx;\n", KVM_CLEAR_DIRTY_LOG);
+    // param: struct kvm_enable_cap
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_ENABLE_CAP : u64 = 0x%lx;\n", KVM_ENABLE_CAP);
+    // param: struct kvm_msr_filter
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_X86_SET_MSR_FILTER : u64 = 0x%lx;\n", KVM_X86_SET_MSR_FILTER);
+    // param: none
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_CREATE_IRQCHIP : u64 = 0x%x;\n", KVM_CREATE_IRQCHIP);
+    // param: struct kvm_irq_level
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_IRQ_LINE : u64 = 0x%lx;\n", KVM_IRQ_LINE);
+    // param: struct kvm_irqchip
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_IRQCHIP : u64 = 0x%lx;\n", KVM_GET_IRQCHIP);
+    // param: struct kvm_irqchip
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_IRQCHIP : u64 = 0x%lx;\n", KVM_SET_IRQCHIP);
+    // param: struct kvm_msi
+    // ret  : >0 delivered, 0 blocked by the guest, -1 error
+    printf("pub(crate) const KVM_SIGNAL_MSI : u64 = 0x%lx;\n", KVM_SIGNAL_MSI);
+
+    /* struct kvm_userspace_memory_region constants */
+
+    printf("pub(crate) const KVM_MEM_LOG_DIRTY_PAGES : u32 = 0x%lx;\n", KVM_MEM_LOG_DIRTY_PAGES);
+    printf("pub(crate) const KVM_MEM_READONLY : u32 = 0x%lx;\n", KVM_MEM_READONLY);
+
+    /* KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2 constants */
+
+    printf("pub(crate) const KVM_DIRTY_LOG_MANUAL_PROTECT_ENABLE : u64 = 0x%x;\n", KVM_DIRTY_LOG_MANUAL_PROTECT_ENABLE);
+
+    /* struct kvm_irqchip constants */
+
+    printf("pub(crate) const KVM_IRQCHIP_PIC_MASTER : u32 = 0x%x;\n", KVM_IRQCHIP_PIC_MASTER);
+    printf("pub(crate) const KVM_IRQCHIP_PIC_SLAVE : u32 = 0x%x;\n", KVM_IRQCHIP_PIC_SLAVE);
+    printf("pub(crate) const KVM_IRQCHIP_IOAPIC : u32 = 0x%x;\n", KVM_IRQCHIP_IOAPIC);
+    printf("pub(crate) const KVM_IOAPIC_NUM_PINS : usize = %d;\n", KVM_IOAPIC_NUM_PINS);
+
+    /* struct kvm_lapic_state constants */
+
+    printf("pub(crate) const KVM_APIC_REG_SIZE : usize = 0x%x;\n", KVM_APIC_REG_SIZE);
+
+    /* KVM_CAP_X86_USER_SPACE_MSR constants */
+
+    printf("pub(crate) const KVM_MSR_EXIT_REASON_INVAL : u32 = 0x%x;\n", KVM_MSR_EXIT_REASON_INVAL);
+    printf("pub(crate) const KVM_MSR_EXIT_REASON_UNKNOWN : u32 = 0x%x;\n", KVM_MSR_EXIT_REASON_UNKNOWN);
+    printf("pub(crate) const KVM_MSR_EXIT_REASON_FILTER : u32 = 0x%x;\n", KVM_MSR_EXIT_REASON_FILTER);
+
+    /* struct kvm_msr_filter constants */
+
+    printf("pub(crate) const KVM_MSR_FILTER_DEFAULT_ALLOW : u32 = 0x%x;\n", KVM_MSR_FILTER_DEFAULT_ALLOW);
+    printf("pub(crate) const KVM_MSR_FILTER_DEFAULT_DENY : u32 = 0x%x;\n", KVM_MSR_FILTER_DEFAULT_DENY);
+    printf("pub(crate) const KVM_MSR_FILTER_READ : u32 = 0x%x;\n", KVM_MSR_FILTER_READ);
+    printf("pub(crate) const KVM_MSR_FILTER_WRITE : u32 = 0x%x;\n", KVM_MSR_FILTER_WRITE);
+    printf("pub(crate) const KVM_MSR_FILTER_MAX_RANGES : usize = %d;\n", KVM_MSR_FILTER_MAX_RANGES);
+    printf("pub(crate) const KVM_MSR_FILTER_MAX_BITMAP_SIZE : usize = 0x%x;\n", KVM_MSR_FILTER_MAX_BITMAP_SIZE);
 
     /* ioctl's for VCPU fd */
 
@@ -60,20 +134,115 @@ int main() {
     // param: struct kvm_guest_debug
     // ret  : 0 success, -1 error
     printf("pub(crate) const KVM_SET_GUEST_DEBUG : u64 = 0x%lx;\n", KVM_SET_GUEST_DEBUG);
+    // param: struct kvm_translation
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_TRANSLATE : u64 = 0x%lx;\n", KVM_TRANSLATE);
+    // param: struct kvm_fpu
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_FPU : u64 = 0x%lx;\n", KVM_GET_FPU);
+    // param: struct kvm_fpu
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_FPU : u64 = 0x%lx;\n", KVM_SET_FPU);
+    // param: struct kvm_xsave
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_XSAVE : u64 = 0x%lx;\n", KVM_GET_XSAVE);
+    // param: struct kvm_xsave (KVM_CAP_XSAVE2 bytes)
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_XSAVE2 : u64 = 0x%lx;\n", KVM_GET_XSAVE2);
+    // param: struct kvm_xsave (KVM_CAP_XSAVE2 bytes)
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_XSAVE : u64 = 0x%lx;\n", KVM_SET_XSAVE);
+    // param: struct kvm_xcrs
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_XCRS : u64 = 0x%lx;\n", KVM_GET_XCRS);
+    // param: struct kvm_xcrs
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_XCRS : u64 = 0x%lx;\n", KVM_SET_XCRS);
+    // param: struct kvm_msrs
+    // ret  : number of msrs successfully set, -1 error
+    printf("pub(crate) const KVM_SET_MSRS : u64 = 0x%lx;\n", KVM_SET_MSRS);
+    // param: struct kvm_cpuid2
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_CPUID2 : u64 = 0x%lx;\n", KVM_SET_CPUID2);
+    // param: struct kvm_cpuid2
+    // ret  : 0 success, -1 error (E2BIG if nent too small)
+    printf("pub(crate) const KVM_GET_CPUID2 : u64 = 0x%lx;\n", KVM_GET_CPUID2);
+    // param: struct kvm_lapic_state
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_LAPIC : u64 = 0x%lx;\n", KVM_GET_LAPIC);
+    // param: struct kvm_lapic_state
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_LAPIC : u64 = 0x%lx;\n", KVM_SET_LAPIC);
+    // param: struct kvm_interrupt
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_INTERRUPT : u64 = 0x%lx;\n", KVM_INTERRUPT);
+    // param: none
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_NMI : u64 = 0x%x;\n", KVM_NMI);
+    // param: struct kvm_vcpu_events
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_VCPU_EVENTS : u64 = 0x%lx;\n", KVM_GET_VCPU_EVENTS);
+    // param: struct kvm_vcpu_events
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_VCPU_EVENTS : u64 = 0x%lx;\n", KVM_SET_VCPU_EVENTS);
+    // param: struct kvm_mp_state
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_GET_MP_STATE : u64 = 0x%lx;\n", KVM_GET_MP_STATE);
+    // param: struct kvm_mp_state
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_MP_STATE : u64 = 0x%lx;\n", KVM_SET_MP_STATE);
+    // param: struct kvm_signal_mask
+    // ret  : 0 success, -1 error
+    printf("pub(crate) const KVM_SET_SIGNAL_MASK : u64 = 0x%lx;\n", KVM_SET_SIGNAL_MASK);
+
+    /* struct kvm_mp_state constants */
+
+    printf("pub(crate) const KVM_MP_STATE_RUNNABLE : u32 = 0x%x;\n", KVM_MP_STATE_RUNNABLE);
+    printf("pub(crate) const KVM_MP_STATE_UNINITIALIZED : u32 = 0x%x;\n", KVM_MP_STATE_UNINITIALIZED);
+    printf("pub(crate) const KVM_MP_STATE_INIT_RECEIVED : u32 = 0x%x;\n", KVM_MP_STATE_INIT_RECEIVED);
+    printf("pub(crate) const KVM_MP_STATE_HALTED : u32 = 0x%x;\n", KVM_MP_STATE_HALTED);
+    printf("pub(crate) const KVM_MP_STATE_SIPI_RECEIVED : u32 = 0x%x;\n", KVM_MP_STATE_SIPI_RECEIVED);
+
+    /* struct kvm_vcpu_events constants */
+
+    printf("pub(crate) const KVM_VCPUEVENT_VALID_NMI_PENDING : u32 = 0x%x;\n", KVM_VCPUEVENT_VALID_NMI_PENDING);
+    printf("pub(crate) const KVM_VCPUEVENT_VALID_SIPI_VECTOR : u32 = 0x%x;\n", KVM_VCPUEVENT_VALID_SIPI_VECTOR);
+    printf("pub(crate) const KVM_VCPUEVENT_VALID_SHADOW : u32 = 0x%x;\n", KVM_VCPUEVENT_VALID_SHADOW);
+    printf("pub(crate) const KVM_VCPUEVENT_VALID_SMM : u32 = 0x%x;\n", KVM_VCPUEVENT_VALID_SMM);
+
+    /* struct kvm_cpuid_entry2 constants */
+
+    printf("pub(crate) const KVM_CPUID_FLAG_SIGNIFCANT_INDEX : u32 = 0x%x;\n", KVM_CPUID_FLAG_SIGNIFCANT_INDEX);
+
+    /* struct kvm_xcrs constants */
+
+    printf("pub(crate) const KVM_MAX_XCRS : usize = %d;\n", KVM_MAX_XCRS);
 
     /* struct kvm_guest_debug constants */
 
     printf("pub(crate) const KVM_GUESTDBG_ENABLE : u32 = 0x%x;\n", KVM_GUESTDBG_ENABLE);
     printf("pub(crate) const KVM_GUESTDBG_SINGLESTEP : u32 = 0x%x;\n", KVM_GUESTDBG_SINGLESTEP);
+    printf("pub(crate) const KVM_GUESTDBG_USE_SW_BP : u32 = 0x%x;\n", KVM_GUESTDBG_USE_SW_BP);
+    printf("pub(crate) const KVM_GUESTDBG_USE_HW_BP : u32 = 0x%x;\n", KVM_GUESTDBG_USE_HW_BP);
 
     /* struct kvm_run constants */
 
-    printf("pub(crate) const KVM_EXIT_HLT : u64 = 0x%x;\n", KVM_EXIT_HLT);
+    printf("pub(crate) const KVM_EXIT_EXCEPTION : u64 = 0x%x;\n", KVM_EXIT_EXCEPTION);
     printf("pub(crate) const KVM_EXIT_IO : u64 = 0x%x;\n", KVM_EXIT_IO);
     printf("pub(crate) const KVM_EXIT_IO_IN : u64 = 0x%x;\n", KVM_EXIT_IO_IN);
     printf("pub(crate) const KVM_EXIT_IO_OUT : u64 = 0x%x;\n", KVM_EXIT_IO_OUT);
-    printf("pub(crate) const KVM_EXIT_MMIO : u64 = 0x%x;\n", KVM_EXIT_MMIO);
+    printf("pub(crate) const KVM_EXIT_HYPERCALL : u64 = 0x%x;\n", KVM_EXIT_HYPERCALL);
     printf("pub(crate) const KVM_EXIT_DEBUG : u64 = 0x%x;\n", KVM_EXIT_DEBUG);
+    printf("pub(crate) const KVM_EXIT_HLT : u64 = 0x%x;\n", KVM_EXIT_HLT);
+    printf("pub(crate) const KVM_EXIT_MMIO : u64 = 0x%x;\n", KVM_EXIT_MMIO);
+    printf("pub(crate) const KVM_EXIT_IRQ_WINDOW_OPEN : u64 = 0x%x;\n", KVM_EXIT_IRQ_WINDOW_OPEN);
+    printf("pub(crate) const KVM_EXIT_SHUTDOWN : u64 = 0x%x;\n", KVM_EXIT_SHUTDOWN);
+    printf("pub(crate) const KVM_EXIT_FAIL_ENTRY : u64 = 0x%x;\n", KVM_EXIT_FAIL_ENTRY);
+    printf("pub(crate) const KVM_EXIT_INTR : u64 = 0x%x;\n", KVM_EXIT_INTR);
+    printf("pub(crate) const KVM_EXIT_INTERNAL_ERROR : u64 = 0x%x;\n", KVM_EXIT_INTERNAL_ERROR);
+    printf("pub(crate) const KVM_EXIT_SYSTEM_EVENT : u64 = 0x%x;\n", KVM_EXIT_SYSTEM_EVENT);
+    printf("pub(crate) const KVM_EXIT_X86_RDMSR : u64 = 0x%x;\n", KVM_EXIT_X86_RDMSR);
+    printf("pub(crate) const KVM_EXIT_X86_WRMSR : u64 = 0x%x;\n", KVM_EXIT_X86_WRMSR);
 
     /* Capabilities */
 
@@ -89,6 +258,53 @@ int main() {
     //
     // ret: 0 unsupported, 1 supported
     printf("pub(crate) const KVM_CAP_CHECK_EXTENSION_VM : u64 = 0x%x;\n", KVM_CAP_CHECK_EXTENSION_VM);
+    // Check if memory regions can be mapped readonly (KVM_MEM_READONLY).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_READONLY_MEM : u64 = 0x%x;\n", KVM_CAP_READONLY_MEM);
+    // Check if the XSAVE state can be accessed (KVM_GET_XSAVE, KVM_SET_XSAVE).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_XSAVE : u64 = 0x%x;\n", KVM_CAP_XSAVE);
+    // Check if the extended control registers can be accessed (KVM_GET_XCRS, KVM_SET_XCRS).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_XCRS : u64 = 0x%x;\n", KVM_CAP_XCRS);
+    // Check if feature MSRs can be queried (KVM_GET_MSR_FEATURE_INDEX_LIST, KVM_GET_MSRS on
+    // /dev/kvm).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_GET_MSR_FEATURES : u64 = 0x%x;\n", KVM_CAP_GET_MSR_FEATURES);
+    // Check if MSR filters can be installed (KVM_X86_SET_MSR_FILTER).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_X86_MSR_FILTER : u64 = 0x%x;\n", KVM_CAP_X86_MSR_FILTER);
+    // Check if guest MSR accesses can be forwarded to user space (KVM_MSR_EXIT_REASON_*).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_X86_USER_SPACE_MSR : u64 = 0x%x;\n", KVM_CAP_X86_USER_SPACE_MSR);
+    // Check if the CPUID can be configured (KVM_GET_SUPPORTED_CPUID, KVM_SET_CPUID2,
+    // KVM_GET_CPUID2).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_EXT_CPUID : u64 = 0x%x;\n", KVM_CAP_EXT_CPUID);
+    // Check if an in-kernel interrupt controller can be created (KVM_CREATE_IRQCHIP,
+    // KVM_IRQ_LINE, KVM_GET_IRQCHIP, KVM_SET_IRQCHIP).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_IRQCHIP : u64 = 0x%x;\n", KVM_CAP_IRQCHIP);
+    // Check if MSIs can be injected (KVM_SIGNAL_MSI).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_SIGNAL_MSI : u64 = 0x%x;\n", KVM_CAP_SIGNAL_MSI);
+    // Check if pending VCPU events can be accessed (KVM_GET_VCPU_EVENTS, KVM_SET_VCPU_EVENTS).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_VCPU_EVENTS : u64 = 0x%x;\n", KVM_CAP_VCPU_EVENTS);
+    // Check if the multiprocessing state can be accessed (KVM_GET_MP_STATE, KVM_SET_MP_STATE).
+    //
+    // ret: 0 unsupported, 1 supported
+    printf("pub(crate) const KVM_CAP_MP_STATE : u64 = 0x%x;\n", KVM_CAP_MP_STATE);
 
     /* Int Capabilities */
 
@@ -100,6 +316,18 @@ int main() {
     //
     // ret: 0 unsupported, >0 #vcpus
     printf("pub(crate) const KVM_CAP_MAX_VCPUS : u64 = 0x%x;\n", KVM_CAP_MAX_VCPUS);
+    // Check the max amount of memory slots.
+    //
+    // ret: 0 unsupported, >0 #slots
+    printf("pub(crate) const KVM_CAP_NR_MEMSLOTS : u64 = 0x%x;\n", KVM_CAP_NR_MEMSLOTS);
+    // Check the supported flags for manual dirty log protection (KVM_CLEAR_DIRTY_LOG).
+    //
+    // ret: 0 unsupported, >0 KVM_DIRTY_LOG_* flags
+    printf("pub(crate) const KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2 : u64 = 0x%x;\n", KVM_CAP_MANUAL_DIRTY_LOG_PROTECT2);
+    // Check the size of the XSAVE state for KVM_GET_XSAVE2 (VM fd only).
+    //
+    // ret: 0 unsupported, >0 size in bytes
+    printf("pub(crate) const KVM_CAP_XSAVE2 : u64 = 0x%x;\n", KVM_CAP_XSAVE2);
 
     /* Testing constants */
 
@@ -114,11 +342,23 @@ int main() {
     printf("#[cfg(test)] const TEST_KVM_DTABLE_ALIGN : usize = %ld;\n", alignof(struct kvm_dtable));
     printf("#[cfg(test)] const TEST_KVM_USERSPACE_MEMORY_REGION_SIZE : usize = %ld;\n", sizeof(struct kvm_userspace_memory_region));
     printf("#[cfg(test)] const TEST_KVM_USERSPACE_MEMORY_REGION_ALIGN : usize = %ld;\n", alignof(struct kvm_userspace_memory_region));
+    printf("#[cfg(test)] const TEST_KVM_DIRTY_LOG_SIZE : usize = %ld;\n", sizeof(struct kvm_dirty_log));
+    printf("#[cfg(test)] const TEST_KVM_DIRTY_LOG_ALIGN : usize = %ld;\n", alignof(struct kvm_dirty_log));
+    printf("#[cfg(test)] const TEST_KVM_CLEAR_DIRTY_LOG_SIZE : usize = %ld;\n", sizeof(struct kvm_clear_dirty_log));
+    printf("#[cfg(test)] const TEST_KVM_CLEAR_DIRTY_LOG_ALIGN : usize = %ld;\n", alignof(struct kvm_clear_dirty_log));
+    printf("#[cfg(test)] const TEST_KVM_ENABLE_CAP_SIZE : usize = %ld;\n", sizeof(struct kvm_enable_cap));
+    printf("#[cfg(test)] const TEST_KVM_ENABLE_CAP_ALIGN : usize = %ld;\n", alignof(struct kvm_enable_cap));
     printf("#[cfg(test)] const TEST_KVM_RUN_SIZE : usize = %ld;\n", sizeof(struct kvm_run));
     printf("#[cfg(test)] const TEST_KVM_RUN_ALIGN : usize = %ld;\n", alignof(struct kvm_run));
     printf("#[cfg(test)] const TEST_KVM_RUN_IO_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->io));
     printf("#[cfg(test)] const TEST_KVM_RUN_MMIO_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->mmio));
     printf("#[cfg(test)] const TEST_KVM_RUN_DEBUG_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->debug));
+    printf("#[cfg(test)] const TEST_KVM_RUN_FAIL_ENTRY_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->fail_entry));
+    printf("#[cfg(test)] const TEST_KVM_RUN_EX_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->ex));
+    printf("#[cfg(test)] const TEST_KVM_RUN_HYPERCALL_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->hypercall));
+    printf("#[cfg(test)] const TEST_KVM_RUN_INTERNAL_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->internal));
+    printf("#[cfg(test)] const TEST_KVM_RUN_SYSTEM_EVENT_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->system_event));
+    printf("#[cfg(test)] const TEST_KVM_RUN_MSR_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->msr));
     printf("#[cfg(test)] const TEST_KVM_RUN_UNION_S_SIZE : usize = %ld;\n", sizeof(((struct kvm_run*)0)->s));
     printf("#[cfg(test)] const TEST_KVM_DEBUGREGS_SIZE: usize = %ld;\n", sizeof(struct kvm_debugregs));
     printf("#[cfg(test)] const TEST_KVM_DEBUGREGS_ALIGN: usize = %ld;\n", alignof(struct kvm_debugregs));
@@ -126,6 +366,43 @@ int main() {
     printf("#[cfg(test)] const TEST_KVM_GUEST_DEBUG_ALIGN: usize = %ld;\n", alignof(struct kvm_guest_debug));
     printf("#[cfg(test)] const TEST_KVM_GUEST_DEBUG_ARCH_SIZE: usize = %ld;\n", sizeof(struct kvm_guest_debug_arch));
     printf("#[cfg(test)] const TEST_KVM_GUEST_DEBUG_ARCH_ALIGN: usize = %ld;\n", alignof(struct kvm_guest_debug_arch));
+    printf("#[cfg(test)] const TEST_KVM_TRANSLATION_SIZE: usize = %ld;\n", sizeof(struct kvm_translation));
+    printf("#[cfg(test)] const TEST_KVM_TRANSLATION_ALIGN: usize = %ld;\n", alignof(struct kvm_translation));
+    printf("#[cfg(test)] const TEST_KVM_FPU_SIZE: usize = %ld;\n", sizeof(struct kvm_fpu));
+    printf("#[cfg(test)] const TEST_KVM_FPU_ALIGN: usize = %ld;\n", alignof(struct kvm_fpu));
+    printf("#[cfg(test)] const TEST_KVM_XSAVE_SIZE: usize = %ld;\n", sizeof(struct kvm_xsave));
+    printf("#[cfg(test)] const TEST_KVM_XSAVE_ALIGN: usize = %ld;\n", alignof(struct kvm_xsave));
+    printf("#[cfg(test)] const TEST_KVM_XCR_SIZE: usize = %ld;\n", sizeof(struct kvm_xcr));
+    printf("#[cfg(test)] const TEST_KVM_XCR_ALIGN: usize = %ld;\n", alignof(struct kvm_xcr));
+    printf("#[cfg(test)] const TEST_KVM_XCRS_SIZE: usize = %ld;\n", sizeof(struct kvm_xcrs));
+    printf("#[cfg(test)] const TEST_KVM_XCRS_ALIGN: usize = %ld;\n", alignof(struct kvm_xcrs));
+    printf("#[cfg(test)] const TEST_KVM_MSR_ENTRY_SIZE: usize = %ld;\n", sizeof(struct kvm_msr_entry));
+    printf("#[cfg(test)] const TEST_KVM_MSR_ENTRY_ALIGN: usize = %ld;\n", alignof(struct kvm_msr_entry));
+    printf("#[cfg(test)] const TEST_KVM_MSRS_SIZE: usize = %ld;\n", sizeof(struct kvm_msrs));
+    printf("#[cfg(test)] const TEST_KVM_MSRS_ALIGN: usize = %ld;\n", alignof(struct kvm_msrs));
+    printf("#[cfg(test)] const TEST_KVM_MSR_LIST_SIZE: usize = %ld;\n", sizeof(struct kvm_msr_list));
+    printf("#[cfg(test)] const TEST_KVM_MSR_LIST_ALIGN: usize = %ld;\n", alignof(struct kvm_msr_list));
+    printf("#[cfg(test)] const TEST_KVM_MSR_FILTER_RANGE_SIZE: usize = %ld;\n", sizeof(struct kvm_msr_filter_range));
+    printf("#[cfg(test)] const TEST_KVM_MSR_FILTER_RANGE_ALIGN: usize = %ld;\n", alignof(struct kvm_msr_filter_range));
+    printf("#[cfg(test)] const TEST_KVM_MSR_FILTER_SIZE: usize = %ld;\n", sizeof(struct kvm_msr_filter));
+    printf("#[cfg(test)] const TEST_KVM_MSR_FILTER_ALIGN: usize = %ld;\n", alignof(struct kvm_msr_filter));
+    printf("#[cfg(test)] const TEST_KVM_CPUID_ENTRY2_SIZE: usize = %ld;\n", sizeof(struct kvm_cpuid_entry2));
+    printf("#[cfg(test)] const TEST_KVM_CPUID_ENTRY2_ALIGN: usize = %ld;\n", alignof(struct kvm_cpuid_entry2));
+    printf("#[cfg(test)] const TEST_KVM_CPUID2_SIZE: usize = %ld;\n", sizeof(struct kvm_cpuid2));
+    printf("#[cfg(test)] const TEST_KVM_CPUID2_ALIGN: usize = %ld;\n", alignof(struct kvm_cpuid2));
+    printf("#[cfg(test)] const TEST_KVM_IRQ_LEVEL_SIZE: usize = %ld;\n", sizeof(struct kvm_irq_level));
+    printf("#[cfg(test)] const TEST_KVM_PIC_STATE_SIZE: usize = %ld;\n", sizeof(struct kvm_pic_state));
+    printf("#[cfg(test)] const TEST_KVM_IOAPIC_STATE_SIZE: usize = %ld;\n", sizeof(struct kvm_ioapic_state));
+    printf("#[cfg(test)] const TEST_KVM_IOAPIC_STATE_ALIGN: usize = %ld;\n", alignof(struct kvm_ioapic_state));
+    printf("#[cfg(test)] const TEST_KVM_IRQCHIP_SIZE: usize = %ld;\n", sizeof(struct kvm_irqchip));
+    printf("#[cfg(test)] const TEST_KVM_IRQCHIP_ALIGN: usize = %ld;\n", alignof(struct kvm_irqchip));
+    printf("#[cfg(test)] const TEST_KVM_LAPIC_STATE_SIZE: usize = %ld;\n", sizeof(struct kvm_lapic_state));
+    printf("#[cfg(test)] const TEST_KVM_MSI_SIZE: usize = %ld;\n", sizeof(struct kvm_msi));
+    printf("#[cfg(test)] const TEST_KVM_INTERRUPT_SIZE: usize = %ld;\n", sizeof(struct kvm_interrupt));
+    printf("#[cfg(test)] const TEST_KVM_VCPU_EVENTS_SIZE: usize = %ld;\n", sizeof(struct kvm_vcpu_events));
+    printf("#[cfg(test)] const TEST_KVM_VCPU_EVENTS_ALIGN: usize = %ld;\n", alignof(struct kvm_vcpu_events));
+    printf("#[cfg(test)] const TEST_KVM_SIGNAL_MASK_SIZE: usize = %ld;\n", sizeof(struct kvm_signal_mask));
+    printf("#[cfg(test)] const TEST_KVM_SIGNAL_MASK_ALIGN: usize = %ld;\n", alignof(struct kvm_signal_mask));
 
     return 0;
 }